@@ -0,0 +1,357 @@
+//! Exports trained networks as standalone source code
+//!
+//! The generated code has no dependencies, weights are embedded as constant arrays. Every
+//! neuron is unrolled into its own statement followed by one for its activation, so the code
+//! contains no loops.
+
+use std::fmt::Write;
+
+use neural_network::NeuralNetwork;
+use neural_network::NeuronType;
+use neural_network::float::{Float, Precision};
+use neural_network::plan::PlanError;
+
+pub struct CodegenOptions {
+    /// Name of the generated function
+    pub function_name: String,
+
+    /// Prefix of the generated constants
+    pub constant_prefix: String
+}
+
+impl CodegenOptions {
+    pub fn defaults() -> Self {
+        CodegenOptions {
+            function_name: "predict".to_string(),
+            constant_prefix: "LAYER".to_string()
+        }
+    }
+}
+
+/// Generates `fn predict(input: &[T; N]) -> [T; M]` as Rust source
+pub fn to_rust<F: Float>(network: &NeuralNetwork<F>, opt_options: Option<CodegenOptions>) -> Result<String, PlanError> {
+    let options = opt_options.unwrap_or_else(CodegenOptions::defaults);
+    let plan = try!(network.build());
+    let ty = rust_type::<F>();
+
+    let mut out = String::new();
+    writeln!(out, "// Generated by deeplearning, do not edit").unwrap();
+    writeln!(out, "").unwrap();
+
+    for (index, layer) in plan.layers().iter().enumerate() {
+        writeln!(out, "const {}_{}_WEIGHTS: [[{}; {}]; {}] = [", options.constant_prefix, index, ty, layer.inputs(), layer.outputs()).unwrap();
+        for row in layer.weights().chunks(layer.inputs()) {
+            writeln!(out, "    [{}],", join(row, rust_literal)).unwrap();
+        }
+        writeln!(out, "];").unwrap();
+        writeln!(out, "const {}_{}_BIASES: [{}; {}] = [{}];", options.constant_prefix, index, ty, layer.outputs(), join(layer.biases(), rust_literal)).unwrap();
+        writeln!(out, "").unwrap();
+    }
+
+    writeln!(out, "pub fn {}(input: &[{}; {}]) -> [{}; {}] {{", options.function_name, ty, plan.inputs(), ty, plan.outputs()).unwrap();
+
+    let mut sources: Vec<String> = (0..plan.inputs()).map(|i| format!("input[{}]", i)).collect();
+    for (index, layer) in plan.layers().iter().enumerate() {
+        let constants = format!("{}_{}", options.constant_prefix, index);
+        let targets: Vec<String> = (0..layer.outputs()).map(|i| format!("layer_{}_{}", index, i)).collect();
+
+        for (i, target) in targets.iter().enumerate() {
+            writeln!(out, "    let {} = {};", target, weighted_sum(&constants, i, &sources)).unwrap();
+            if layer.activation() != NeuronType::Identity {
+                writeln!(out, "    let {} = {};", target, rust_activation(layer.activation(), target, ty)).unwrap();
+            }
+        }
+
+        sources = targets;
+    }
+
+    writeln!(out, "    [{}]", sources.join(", ")).unwrap();
+    writeln!(out, "}}").unwrap();
+
+    Ok(out)
+}
+
+/// Generates `void predict(const T input[N], T output[M])` as C source
+pub fn to_c<F: Float>(network: &NeuralNetwork<F>, opt_options: Option<CodegenOptions>) -> Result<String, PlanError> {
+    let options = opt_options.unwrap_or_else(CodegenOptions::defaults);
+    let plan = try!(network.build());
+    let ty = c_type::<F>();
+
+    let mut out = String::new();
+    writeln!(out, "/* Generated by deeplearning, do not edit */").unwrap();
+    writeln!(out, "#include <math.h>").unwrap();
+    writeln!(out, "").unwrap();
+
+    for (index, layer) in plan.layers().iter().enumerate() {
+        writeln!(out, "static const {} {}_{}_WEIGHTS[{}][{}] = {{", ty, options.constant_prefix, index, layer.outputs(), layer.inputs()).unwrap();
+        for row in layer.weights().chunks(layer.inputs()) {
+            writeln!(out, "    {{{}}},", join(row, c_literal)).unwrap();
+        }
+        writeln!(out, "}};").unwrap();
+        writeln!(out, "static const {} {}_{}_BIASES[{}] = {{{}}};", ty, options.constant_prefix, index, layer.outputs(), join(layer.biases(), c_literal)).unwrap();
+        writeln!(out, "").unwrap();
+    }
+
+    writeln!(out, "void {}(const {} input[{}], {} output[{}]) {{", options.function_name, ty, plan.inputs(), ty, plan.outputs()).unwrap();
+
+    let last = plan.layers().len() - 1;
+    let mut sources: Vec<String> = (0..plan.inputs()).map(|i| format!("input[{}]", i)).collect();
+    for (index, layer) in plan.layers().iter().enumerate() {
+        let constants = format!("{}_{}", options.constant_prefix, index);
+        let mut targets = Vec::with_capacity(layer.outputs());
+
+        for i in 0..layer.outputs() {
+            let (target, declaration) = if index == last {
+                (format!("output[{}]", i), String::new())
+            } else {
+                (format!("layer_{}_{}", index, i), format!("const {} ", ty))
+            };
+            let sum = weighted_sum(&constants, i, &sources);
+
+            if layer.activation() == NeuronType::Identity {
+                writeln!(out, "    {}{} = {};", declaration, target, sum).unwrap();
+            } else {
+                let name = format!("sum_{}_{}", index, i);
+                writeln!(out, "    const {} {} = {};", ty, name, sum).unwrap();
+                writeln!(out, "    {}{} = {};", declaration, target, c_activation::<F>(layer.activation(), &name)).unwrap();
+            }
+
+            targets.push(target);
+        }
+
+        sources = targets;
+    }
+
+    writeln!(out, "}}").unwrap();
+
+    Ok(out)
+}
+
+fn rust_type<F: Float>() -> &'static str {
+    match F::precision() {
+        Precision::Single => "f32",
+        Precision::Double => "f64"
+    }
+}
+
+fn c_type<F: Float>() -> &'static str {
+    match F::precision() {
+        Precision::Single => "float",
+        Precision::Double => "double"
+    }
+}
+
+/// Expression applying the activation to the variable `x`
+fn rust_activation(activation: NeuronType, x: &str, ty: &str) -> String {
+    match activation {
+        NeuronType::Identity => x.to_string(),
+        NeuronType::SigMoid => format!("1.0 / (1.0 + (-{}).exp())", x),
+        NeuronType::TanH => format!("{}.tanh()", x),
+        NeuronType::DeLu => format!("if {} > 0.0 {{ {} }} else {{ 0.0 as {} }}", x, x, ty)
+    }
+}
+
+/// Expression applying the activation to the variable `x`
+fn c_activation<F: Float>(activation: NeuronType, x: &str) -> String {
+    let suffix = match F::precision() {
+        Precision::Single => "f",
+        Precision::Double => ""
+    };
+
+    match activation {
+        NeuronType::Identity => x.to_string(),
+        NeuronType::SigMoid => format!("1.0{} / (1.0{} + exp{}(-{}))", suffix, suffix, suffix, x),
+        NeuronType::TanH => format!("tanh{}({})", suffix, x),
+        NeuronType::DeLu => format!("{} > 0.0{} ? {} : 0.0{}", x, suffix, x, suffix)
+    }
+}
+
+/// Bias plus the unrolled dot product of one neuron, summed in the order of the scalar kernel
+fn weighted_sum(constants: &str, neuron: usize, sources: &[String]) -> String {
+    if sources.is_empty() {
+        return format!("{}_BIASES[{}]", constants, neuron);
+    }
+
+    let products: Vec<String> = sources.iter().enumerate()
+        .map(|(j, source)| format!("{}_WEIGHTS[{}][{}] * {}", constants, neuron, j, source))
+        .collect();
+    format!("{}_BIASES[{}] + ({})", constants, neuron, products.join(" + "))
+}
+
+/// Shortest literal that reads back as exactly the same value
+fn rust_literal<F: Float>(value: F) -> String {
+    let ty = rust_type::<F>();
+    let v = value.to_f64();
+
+    if v.is_nan() {
+        format!("{}::NAN", ty)
+    } else if v == ::std::f64::INFINITY {
+        format!("{}::INFINITY", ty)
+    } else if v == ::std::f64::NEG_INFINITY {
+        format!("{}::NEG_INFINITY", ty)
+    } else {
+        float_literal(value)
+    }
+}
+
+fn c_literal<F: Float>(value: F) -> String {
+    let v = value.to_f64();
+
+    if v.is_nan() {
+        "NAN".to_string()
+    } else if v == ::std::f64::INFINITY {
+        "INFINITY".to_string()
+    } else if v == ::std::f64::NEG_INFINITY {
+        "-INFINITY".to_string()
+    } else {
+        match F::precision() {
+            Precision::Single => format!("{}f", float_literal(value)),
+            Precision::Double => float_literal(value)
+        }
+    }
+}
+
+/// Formats with a decimal point or exponent so the literal is never read as an integer
+fn float_literal<F: Float>(value: F) -> String {
+    let literal = format!("{:?}", value);
+    if literal.contains('.') || literal.contains('e') {
+        literal
+    } else {
+        format!("{}.0", literal)
+    }
+}
+
+fn join<F: Float>(values: &[F], literal: fn(F) -> String) -> String {
+    values.iter().map(|v| literal(*v)).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write as IoWrite;
+    use std::path::Path;
+    use std::process::{self, Command};
+
+    use rand::{Rng, SeedableRng, StdRng};
+
+    use neural_network::{Instance, NeuralNetwork};
+    use neural_network::cpu::CpuInstance;
+    use neural_network::differential::random_network;
+    use neural_network::float::Float;
+
+    use super::*;
+
+    /// Generates a program printing the outputs for every sample, one value per line, compiles
+    /// and runs it. `None` when the compiler is not installed.
+    type Run<F> = fn(&NeuralNetwork<F>, &[Vec<F>], &Path) -> Option<Vec<f64>>;
+
+    fn execute(mut compiler: Command, binary_path: &Path, source: &str) -> Vec<f64> {
+        let compiled = compiler.output().unwrap();
+        assert!(compiled.status.success(), "{}\n{}", String::from_utf8_lossy(&compiled.stderr), source);
+
+        let run = Command::new(binary_path).output().unwrap();
+        assert!(run.status.success());
+        String::from_utf8(run.stdout).unwrap().lines().map(|line| line.parse().unwrap()).collect()
+    }
+
+    fn run_rust<F: Float>(network: &NeuralNetwork<F>, samples: &[Vec<F>], path: &Path) -> Option<Vec<f64>> {
+        let mut source = to_rust(network, None).unwrap();
+        source.push_str("\nfn main() {\n");
+        for sample in samples {
+            source.push_str(&format!("    for value in predict(&[{}]).iter() {{ println!(\"{{:?}}\", value); }}\n", join(sample, rust_literal)));
+        }
+        source.push_str("}\n");
+
+        let source_path = path.with_extension("rs");
+        File::create(&source_path).unwrap().write_all(source.as_bytes()).unwrap();
+
+        let mut rustc = Command::new(env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()));
+        rustc.arg("-O").arg("-o").arg(path).arg(&source_path);
+        Some(execute(rustc, path, &source))
+    }
+
+    /// Skipped when `cc`, or the compiler named by `CC`, is not installed
+    fn run_c<F: Float>(network: &NeuralNetwork<F>, samples: &[Vec<F>], path: &Path) -> Option<Vec<f64>> {
+        let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        if Command::new(&cc).arg("--version").output().is_err() {
+            return None;
+        }
+
+        let ty = c_type::<F>();
+        let outputs = network.layers().last().unwrap().len();
+
+        let mut source = to_c(network, None).unwrap();
+        source.push_str("\n#include <stdio.h>\n\nint main(void) {\n");
+        source.push_str(&format!("    {} output[{}];\n", ty, outputs));
+        for sample in samples {
+            source.push_str("    {\n");
+            source.push_str(&format!("        const {} input[] = {{{}}};\n", ty, join(sample, c_literal)));
+            source.push_str("        predict(input, output);\n");
+            source.push_str(&format!("        for (int i = 0; i < {}; i++) printf(\"%.17g\\n\", (double) output[i]);\n", outputs));
+            source.push_str("    }\n");
+        }
+        source.push_str("    return 0;\n}\n");
+
+        let source_path = path.with_extension("c");
+        File::create(&source_path).unwrap().write_all(source.as_bytes()).unwrap();
+
+        let mut compiler = Command::new(cc);
+        compiler.arg("-std=c99").arg("-O2").arg("-o").arg(path).arg(&source_path).arg("-lm");
+        Some(execute(compiler, path, &source))
+    }
+
+    /// Runs the generated code of random networks on random inputs and compares the outputs with
+    /// `CpuInstance`
+    fn compare_with_cpu<F: Float>(language: &str, run: Run<F>, seed: usize, tolerance: f64) {
+        let seed_slice: &[usize] = &[seed];
+        let mut rng: StdRng = SeedableRng::from_seed(seed_slice);
+        let directory = env::temp_dir().join(format!("deeplearning-codegen-{}-{}-{}-{}", process::id(), language, rust_type::<F>(), seed));
+        fs::create_dir_all(&directory).unwrap();
+
+        for network_index in 0..5 {
+            let network: NeuralNetwork<F> = random_network(&mut rng, 4, 8);
+            let samples: Vec<Vec<F>> = (0..5).map(|_| (0..network.inputs()).map(|_| F::from_f64(rng.gen_range(-2.0, 2.0))).collect()).collect();
+
+            let actual = match run(&network, &samples, &directory.join(format!("network_{}", network_index))) {
+                Some(actual) => actual,
+                None => break
+            };
+
+            let mut instance = CpuInstance::new(&network).unwrap();
+            let mut outputs = vec![F::zero(); instance.output_len()];
+            let mut expected = Vec::new();
+            for sample in &samples {
+                instance.calculate(sample, &mut outputs).unwrap();
+                expected.extend(outputs.iter().map(|v| v.to_f64()));
+            }
+
+            assert_eq!(actual.len(), expected.len());
+            for (a, e) in actual.iter().zip(expected.iter()) {
+                assert!((a - e).abs() <= tolerance * e.abs().max(1.0), "{} network {}: generated {}, cpu {}", language, network_index, a, e);
+            }
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn generated_rust_matches_cpu_f64() {
+        compare_with_cpu::<f64>("rust", run_rust, 1, 1e-12);
+    }
+
+    #[test]
+    fn generated_rust_matches_cpu_f32() {
+        compare_with_cpu::<f32>("rust", run_rust, 2, 1e-5);
+    }
+
+    #[test]
+    fn generated_c_matches_cpu_f64() {
+        compare_with_cpu::<f64>("c", run_c, 3, 1e-12);
+    }
+
+    #[test]
+    fn generated_c_matches_cpu_f32() {
+        compare_with_cpu::<f32>("c", run_c, 4, 1e-5);
+    }
+}
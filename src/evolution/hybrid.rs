@@ -0,0 +1,213 @@
+//! Neuroevolution combined with gradient descent
+//!
+//! `HybridRating` serves as the `rate` closure of `genetic_evolution`. It runs a few optimizer
+//! steps on every individual before measuring its fitness, the negated mean loss.
+//!
+//! ```ignore
+//! let mut hybrid = HybridRating::new(MeanSquaredError, &data, Sgd::new(0.1), None);
+//! let (best, fitness) = genetic_evolution(20, StopRule::GenerationReached(50), &mut new, &mut |nn| hybrid.rate(nn), None);
+//! ```
+
+use rand::{Rng, OsRng, SeedableRng, StdRng};
+
+use neural_network::NeuralNetwork;
+use neural_network::float::Float;
+
+use training::dataset::Dataset;
+use training::loss::{self, Loss, LossError};
+use training::optimizer::Optimizer;
+
+/// What happens to the weights learned during rating
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Inheritance {
+    /// Learned weights are written back into the individual and passed on to its children
+    Lamarckian,
+
+    /// Only the fitness benefits from learning, the individual keeps its weights
+    Baldwinian
+}
+
+pub struct HybridOptions {
+    /// Optimizer steps per rating
+    pub steps: usize,
+
+    /// Samples per step, drawn at random, the whole dataset is used when it is smaller
+    pub batch_size: usize,
+
+    pub inheritance: Inheritance,
+
+    /// Seed for drawing batches, a random seed is chosen when `None`
+    pub seed: Option<usize>
+}
+
+impl HybridOptions {
+    pub fn defaults() -> Self {
+        HybridOptions {
+            steps: 10,
+            batch_size: 32,
+            inheritance: Inheritance::Lamarckian,
+            seed: None
+        }
+    }
+}
+
+/// Rates networks after refining them with gradient descent
+pub struct HybridRating<'a, F: Float + 'a, L: Loss<F>, O: Optimizer<F> + Clone> {
+    loss: L,
+    data: &'a Dataset<F>,
+
+    /// Every individual starts with a fresh clone of this optimizer
+    optimizer: O,
+    options: HybridOptions,
+    rng: StdRng
+}
+
+impl<'a, F: Float, L: Loss<F>, O: Optimizer<F> + Clone> HybridRating<'a, F, L, O> {
+    pub fn new(loss: L, data: &'a Dataset<F>, optimizer: O, opt_options: Option<HybridOptions>) -> Self {
+        let options = opt_options.unwrap_or_else(HybridOptions::defaults);
+        let seed: &[usize] = &[options.seed.unwrap_or_else(|| OsRng::new().unwrap().gen())];
+
+        HybridRating {
+            loss: loss,
+            data: data,
+            optimizer: optimizer,
+            options: options,
+            rng: SeedableRng::from_seed(seed)
+        }
+    }
+
+    pub fn options(&self) -> &HybridOptions {
+        &self.options
+    }
+
+    /// Runs the optimizer steps on a network
+    pub fn refine(&mut self, network: &mut NeuralNetwork<F>) -> Result<(), LossError> {
+        let mut optimizer = self.optimizer.clone();
+        let size = ::std::cmp::min(self.options.batch_size, self.data.len());
+        let mut batch: Vec<usize> = (0..size).collect();
+
+        for _ in 0..self.options.steps {
+            if size < self.data.len() {
+                for index in batch.iter_mut() {
+                    *index = self.rng.gen_range(0, self.data.len());
+                }
+            }
+
+            let (_, gradient) = try!(loss::gradient(&self.loss, network, self.data, &batch));
+            try!(optimizer.step(network, &gradient));
+        }
+
+        Ok(())
+    }
+
+    /// Refines the network according to the inheritance mode and returns its fitness
+    pub fn try_rate(&mut self, network: &mut NeuralNetwork<F>) -> Result<f64, LossError> {
+        match self.options.inheritance {
+            Inheritance::Lamarckian => {
+                try!(self.refine(network));
+                loss::fitness(&self.loss, network, self.data)
+            },
+            Inheritance::Baldwinian => {
+                let mut learner = network.clone();
+                try!(self.refine(&mut learner));
+                loss::fitness(&self.loss, &learner, self.data)
+            }
+        }
+    }
+
+    /// Like `try_rate`, networks that can not be rated get a fitness of negative infinity and lose
+    /// against every network that can
+    pub fn rate(&mut self, network: &mut NeuralNetwork<F>) -> f64 {
+        match self.try_rate(network) {
+            Ok(fitness) => fitness,
+            Err(err) => {
+                warn!(target: "genetic_evolution", "Could not rate individual: {}", err);
+                ::std::f64::NEG_INFINITY
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use evolution::{genetic_evolution, StopRule};
+    use neural_network::{Neuron, NeuronType};
+    use training::loss::MeanSquaredError;
+    use training::optimizer::Sgd;
+
+    /// Two layers of two neurons each, with `outputs` neurons in the output layer
+    fn network(outputs: usize) -> NeuralNetwork {
+        NeuralNetwork::from_layers(2, vec![
+            vec![Neuron::new(vec![0.5, -0.3], 0.1, NeuronType::TanH), Neuron::new(vec![0.2, 0.4], -0.2, NeuronType::TanH)],
+            (0..outputs).map(|o| Neuron::new(vec![0.3, o as f64 * 0.1], 0.0, NeuronType::Identity)).collect()
+        ])
+    }
+
+    fn data() -> Dataset {
+        Dataset::new(
+            vec![vec![0.0, 1.0], vec![1.0, 0.0], vec![1.0, 1.0], vec![-1.0, 0.5]],
+            vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![0.5, 0.5], vec![-0.5, 0.2]]
+        ).unwrap()
+    }
+
+    fn hybrid(data: &Dataset, inheritance: Inheritance) -> HybridRating<f64, MeanSquaredError, Sgd> {
+        let mut options = HybridOptions::defaults();
+        options.steps = 5;
+        options.batch_size = 2;
+        options.inheritance = inheritance;
+        options.seed = Some(1);
+        HybridRating::new(MeanSquaredError, data, Sgd::new(0.1), Some(options))
+    }
+
+    fn weights(network: &NeuralNetwork) -> Vec<f64> {
+        network.iter().flat_map(|(_, neuron)| neuron.weights().iter().cloned().chain(Some(neuron.bias()))).collect()
+    }
+
+    #[test]
+    fn lamarckian_keeps_learned_weights() {
+        let data = data();
+        let original = network(2);
+        let untrained = loss::fitness(&MeanSquaredError, &original, &data).unwrap();
+
+        let mut individual = original.clone();
+        let fitness = hybrid(&data, Inheritance::Lamarckian).rate(&mut individual);
+
+        assert!(weights(&individual) != weights(&original));
+        assert_eq!(fitness, loss::fitness(&MeanSquaredError, &individual, &data).unwrap());
+        assert!(fitness > untrained);
+    }
+
+    #[test]
+    fn baldwinian_keeps_inherited_weights() {
+        let data = data();
+        let original = network(2);
+
+        let mut individual = original.clone();
+        let fitness = hybrid(&data, Inheritance::Baldwinian).rate(&mut individual);
+        assert_eq!(weights(&individual), weights(&original));
+
+        // the fitness is the one of the learned weights, same seed same batches
+        let mut learner = original.clone();
+        assert_eq!(fitness, hybrid(&data, Inheritance::Lamarckian).rate(&mut learner));
+        assert!(fitness > loss::fitness(&MeanSquaredError, &original, &data).unwrap());
+    }
+
+    #[test]
+    fn unratable_individuals_lose() {
+        let data = data();
+
+        // three outputs do not match the two targets
+        let mut failing = hybrid(&data, Inheritance::Lamarckian);
+        assert_eq!(failing.rate(&mut network(3)), ::std::f64::NEG_INFINITY);
+
+        let (_, fitness) = genetic_evolution(4, StopRule::GenerationReached(2), &mut |_| network(3), &mut |nn| failing.rate(nn), None);
+        assert_eq!(fitness, ::std::f64::NEG_INFINITY);
+
+        let mut mixed = hybrid(&data, Inheritance::Lamarckian);
+        let (best, fitness) = genetic_evolution(4, StopRule::GenerationReached(2), &mut |i| network(if i == 3 { 2 } else { 3 }), &mut |nn| mixed.rate(nn), None);
+        assert_eq!(best.layers()[1].len(), 2);
+        assert!(fitness.is_finite());
+    }
+}
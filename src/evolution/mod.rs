@@ -1,3 +1,5 @@
+pub mod hybrid;
+
 use std::vec::*;
 
 /// Trait needs to be implemented when struct is used for an evoltionary algorithm
@@ -31,12 +33,44 @@ pub enum StopRule {
     Never
 }
 
+/// Returned by hooks and callbacks to continue or end a run
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Control {
+    Continue,
+    Stop
+}
+
+/// Hooks called during `genetic_evolution`, every event defaults to doing nothing
+pub trait EvolutionHooks<T> {
+    /// Called before the individuals of a generation are rated
+    fn generation_start(&mut self, _generation: usize) -> Control {
+        Control::Continue
+    }
+
+    /// Called after rating with the best individual of the generation
+    fn generation_end(&mut self, _generation: usize, _best: &T, _fitness: f64) -> Control {
+        Control::Continue
+    }
+
+    /// Called when an individual beats the best fitness of all previous generations
+    fn new_best(&mut self, _generation: usize, _best: &T, _fitness: f64) -> Control {
+        Control::Continue
+    }
+}
+
+/// Hook calling a closure at the end of every generation
+pub struct GenerationHook<C>(pub C);
+
+impl<T, C> EvolutionHooks<T> for GenerationHook<C> where C: FnMut(usize, &T, f64) -> Control {
+    fn generation_end(&mut self, generation: usize, best: &T, fitness: f64) -> Control {
+        (self.0)(generation, best, fitness)
+    }
+}
+
 pub struct EvolutionOptions {
     /// Sets the amount of threads that should be used (to calculate fitness)
     ///   Defaults to the amount of cpu cores detected
     pub threads: usize,
-
-    //pub hooks: Vec<EvolutionHooks>
 }
 
 impl EvolutionOptions {
@@ -48,8 +82,27 @@ impl EvolutionOptions {
     }
 }
 
+/// Calls an event on every hook, returns whether any of them requested a stop
+fn notify<T, E>(hooks: &mut [Box<dyn EvolutionHooks<T>>], mut event: E) -> bool
+    where E: FnMut(&mut dyn EvolutionHooks<T>) -> Control
+{
+    hooks.iter_mut().fold(false, |stop, hook| event(&mut **hook) == Control::Stop || stop)
+}
+
+/// Whether a fitness beats another one, NaN is worse than everything
+fn is_better(fitness: f64, than: f64) -> bool {
+    fitness > than || (than.is_nan() && !fitness.is_nan())
+}
+
 pub fn genetic_evolution<T: Evolvable + Clone + Sync + Send, Fnew, Frate>(population: usize, stop_rule: StopRule, new: &mut Fnew, rate: &mut Frate, opt_options: Option<EvolutionOptions>) -> (T, f64)
     where Fnew: FnMut(usize) -> T, Frate: FnMut(&mut T) -> f64
+{
+    genetic_evolution_with_hooks(population, stop_rule, new, rate, opt_options, &mut [])
+}
+
+/// Like `genetic_evolution`, calling the hooks on generation events, any hook can stop the evolution
+pub fn genetic_evolution_with_hooks<T: Evolvable + Clone + Sync + Send, Fnew, Frate>(population: usize, stop_rule: StopRule, new: &mut Fnew, rate: &mut Frate, opt_options: Option<EvolutionOptions>, hooks: &mut [Box<dyn EvolutionHooks<T>>]) -> (T, f64)
+    where Fnew: FnMut(usize) -> T, Frate: FnMut(&mut T) -> f64
 {
     // get options of grab defaults
     let options = match opt_options {
@@ -61,13 +114,20 @@ pub fn genetic_evolution<T: Evolvable + Clone + Sync + Send, Fnew, Frate>(popula
     let mut generationNo: usize = 0;
     let mut prev_fitness: f64 = 0.0;
 
+    // best fitness so far and the generation it was reached in
+    let mut best_fitness: f64 = ::std::f64::NEG_INFINITY;
+    let mut best_generation: usize = 0;
+
     // create initial population
     for i in 0..population {
         generation.push(Box::new(new(i)));
     }
 
     loop {
-        let mut bests: Vec<(f64, usize)> = vec!((-9999.0, 0), (-9999.0, 0));
+        // filled by the first individuals rated, whatever their fitness
+        let mut bests: Vec<(f64, usize)> = Vec::with_capacity(3);
+
+        let mut stop = notify(hooks, |hook| hook.generation_start(generationNo));
 
         // TODO how can we use a threadpool to calculate fitness?
 
@@ -75,18 +135,36 @@ pub fn genetic_evolution<T: Evolvable + Clone + Sync + Send, Fnew, Frate>(popula
         for i in 0..population {
             let fitness = rate(&mut generation[i]);
 
-            if fitness > bests[0].0 {
-                bests[1] = bests[0];
-                bests[0] = (fitness, i);
+            if bests.is_empty() || is_better(fitness, bests[0].0) {
+                bests.insert(0, (fitness, i));
             }
-            else if  fitness > bests[1].0 {
-                bests[1] = (fitness, i);
+            else if bests.len() < 2 || is_better(fitness, bests[1].0) {
+                bests.insert(1, (fitness, i));
             }
+            bests.truncate(2);
         }
 
         info!(target: "genetic_evolution", "Highest Fitness in Generation {} equals {}, thats an improvment of {}", generationNo, bests[0].0, bests[0].0 - prev_fitness);
         prev_fitness = bests[0].0;
 
+        if bests[0].0 > best_fitness {
+            best_fitness = bests[0].0;
+            best_generation = generationNo;
+
+            let best = &generation[bests[0].1];
+            stop = notify(hooks, |hook| hook.new_best(generationNo, best, bests[0].0)) || stop;
+        }
+
+        {
+            let best = &generation[bests[0].1];
+            stop = notify(hooks, |hook| hook.generation_end(generationNo, best, bests[0].0)) || stop;
+        }
+
+        if stop {
+            info!(target: "genetic_evolution", "Stopped by a hook in Generation {}", generationNo);
+            return ((*generation[bests[0].1]).clone(), bests[0].0);
+        }
+
         // Stop Rule
         match stop_rule {
             StopRule::FitnessReached(fitness) => { },
@@ -95,7 +173,11 @@ pub fn genetic_evolution<T: Evolvable + Clone + Sync + Send, Fnew, Frate>(popula
                     return ((*generation[bests[0].1]).clone(), bests[0].0);
                 }
             },
-            StopRule::HasNotImprovedSince(gen) => { },
+            StopRule::HasNotImprovedSince(gen) => {
+                if generationNo - best_generation >= gen {
+                    return ((*generation[bests[0].1]).clone(), bests[0].0);
+                }
+            },
             StopRule::Never => { }
         }
 
@@ -121,3 +203,61 @@ pub fn genetic_evolution<T: Evolvable + Clone + Sync + Send, Fnew, Frate>(popula
         generationNo += 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone)]
+    struct Constant;
+
+    impl Evolvable for Constant {
+        fn cross_over(&self, _other: &Self) -> Self {
+            Constant
+        }
+
+        fn mutate(&mut self) { }
+    }
+
+    #[test]
+    fn stops_after_generations_without_improvement() {
+        for generations in 1..5 {
+            let mut rated = 0;
+            genetic_evolution(4, StopRule::HasNotImprovedSince(generations), &mut |_| Constant, &mut |_: &mut Constant| { rated += 1; 1.0 }, None);
+
+            // the first generation sets the best fitness, the following ones stay flat
+            assert_eq!(rated / 4, generations + 1);
+        }
+    }
+
+    #[derive(Clone)]
+    struct Numbered(usize);
+
+    impl Evolvable for Numbered {
+        fn cross_over(&self, _other: &Self) -> Self {
+            Numbered(self.0)
+        }
+
+        fn mutate(&mut self) { }
+    }
+
+    #[test]
+    fn survives_fitness_without_lower_bound() {
+        for &fitness in &[::std::f64::NEG_INFINITY, -1e6, ::std::f64::NAN] {
+            let (_, best) = genetic_evolution(4, StopRule::GenerationReached(3), &mut |_| Constant, &mut |_: &mut Constant| fitness, None);
+            assert!(best == fitness || (best.is_nan() && fitness.is_nan()));
+        }
+
+        // finite fitness beats negative infinity and NaN, wherever it is rated
+        for position in 0..4 {
+            let mut rate = |individual: &mut Numbered| match individual.0 {
+                i if i == position => -1e6,
+                i if i % 2 == 0 => ::std::f64::NAN,
+                _ => ::std::f64::NEG_INFINITY
+            };
+            let (best, fitness) = genetic_evolution(4, StopRule::GenerationReached(0), &mut |i| Numbered(i), &mut rate, None);
+            assert_eq!(best.0, position);
+            assert_eq!(fitness, -1e6);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Conversion of float networks into fixed-point networks (requires std)
+
+use neural_network::*;
+use neural_network::float::Float;
+use neural_network::plan::PlanError;
+use neural_network::reference::ReferenceInstance;
+
+use fixed::*;
+
+/// Errors when converting a float network
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConvertError {
+    /// The float network could not be compiled
+    Plan(PlanError),
+
+    /// The requested Q-format is not supported
+    Fixed(FixedError)
+}
+
+impl From<PlanError> for ConvertError {
+    fn from(err: PlanError) -> Self {
+        ConvertError::Plan(err)
+    }
+}
+
+impl From<FixedError> for ConvertError {
+    fn from(err: FixedError) -> Self {
+        ConvertError::Fixed(err)
+    }
+}
+
+/// Owned weights of a converted network, borrow them as `FixedNetwork` via `layers`
+#[derive(Clone, Debug)]
+pub struct FixedModel {
+    frac_bits: u8,
+    shapes: Vec<(usize, usize)>,
+    weights: Vec<Vec<i32>>,
+    biases: Vec<Vec<i32>>,
+    activations: Vec<FixedActivation>
+}
+
+/// Error introduced by converting a network to fixed-point
+#[derive(Clone, Debug)]
+pub struct FixedReport {
+    /// Largest rounding error of a single weight or bias
+    pub max_parameter_error: f64,
+
+    /// Amount of weights and biases that did not fit into the Q-format and were clipped
+    pub saturated_parameters: usize,
+
+    /// Amount of samples compared
+    pub samples: usize,
+
+    /// Largest absolute output difference to the float network
+    pub max_output_error: f64,
+
+    /// Mean absolute output difference to the float network
+    pub mean_output_error: f64
+}
+
+impl FixedModel {
+    /// Converts a network into Q-format with `frac_bits` fractional bits, at most `MAX_FRAC_BITS`
+    pub fn convert<F: Float>(network: &NeuralNetwork<F>, frac_bits: u8) -> Result<Self, ConvertError> {
+        if frac_bits > MAX_FRAC_BITS {
+            return Err(ConvertError::Fixed(FixedError::UnsupportedFracBits(frac_bits)));
+        }
+        let plan = try!(network.build());
+
+        let to_raw = |values: &[F]| -> Vec<i32> {
+            values.iter().map(|v| to_fixed(v.to_f64(), frac_bits)).collect()
+        };
+
+        Ok(FixedModel {
+            frac_bits: frac_bits,
+            shapes: plan.layers().iter().map(|layer| (layer.inputs(), layer.outputs())).collect(),
+            weights: plan.layers().iter().map(|layer| to_raw(layer.weights())).collect(),
+            biases: plan.layers().iter().map(|layer| to_raw(layer.biases())).collect(),
+            activations: plan.layers().iter().map(|layer| match layer.activation() {
+                NeuronType::Identity => FixedActivation::Identity,
+                NeuronType::SigMoid => FixedActivation::SigMoid,
+                NeuronType::TanH => FixedActivation::TanH,
+                NeuronType::DeLu => FixedActivation::DeLu
+            }).collect()
+        })
+    }
+
+    /// Amount of fractional bits
+    pub fn frac_bits(&self) -> u8 {
+        self.frac_bits
+    }
+
+    /// Layers borrowing the weights of this model, pass them to `FixedNetwork::new`
+    pub fn layers(&self) -> Vec<FixedLayer> {
+        (0..self.shapes.len()).map(|index| FixedLayer {
+            inputs: self.shapes[index].0,
+            outputs: self.shapes[index].1,
+            weights: &self.weights[index],
+            biases: &self.biases[index],
+            activation: self.activations[index]
+        }).collect()
+    }
+
+    /// Calculates the fixed-point network for float inputs
+    pub fn calculate(&self, inputs: &[f64]) -> Result<Vec<f64>, FixedError> {
+        let layers = self.layers();
+        let network = try!(FixedNetwork::new(self.frac_bits, &layers));
+
+        let raw_inputs: Vec<i32> = inputs.iter().map(|x| to_fixed(*x, self.frac_bits)).collect();
+        let mut front = vec![0; network.buffer_len()];
+        let mut back = vec![0; network.buffer_len()];
+        let mut outputs = vec![0; network.outputs()];
+
+        try!(network.calculate(&raw_inputs, &mut front, &mut back, &mut outputs));
+
+        Ok(outputs.iter().map(|raw| from_fixed(*raw, self.frac_bits)).collect())
+    }
+
+    /// Compares the converted network against its float original over a dataset
+    pub fn report<F: Float>(&self, network: &NeuralNetwork<F>, samples: &[Vec<F>]) -> Result<FixedReport, FixedError> {
+        let plan = match network.build() {
+            Ok(plan) => plan,
+            Err(_) => return Err(FixedError::InvalidNetwork)
+        };
+
+        let mut max_parameter_error: f64 = 0.0;
+        let mut saturated = 0;
+        for layer in plan.layers() {
+            for value in layer.weights().iter().chain(layer.biases().iter()) {
+                let value = value.to_f64();
+                let raw = to_fixed(value, self.frac_bits);
+                if raw == ::std::i32::MAX || raw == ::std::i32::MIN {
+                    saturated += 1;
+                }
+                max_parameter_error = max_parameter_error.max((from_fixed(raw, self.frac_bits) - value).abs());
+            }
+        }
+
+        let reference = match ReferenceInstance::new(network) {
+            Ok(reference) => reference,
+            Err(_) => return Err(FixedError::InvalidNetwork)
+        };
+        let (mut max_output_error, mut sum, mut count) = (0.0f64, 0.0, 0);
+
+        for sample in samples {
+            let expected = match reference.calculate_f64(sample) {
+                Ok(values) => values,
+                Err(_) => return Err(FixedError::InputMismatch(sample.len()))
+            };
+            let inputs: Vec<f64> = sample.iter().map(|x| x.to_f64()).collect();
+            let actual = try!(self.calculate(&inputs));
+
+            for (e, a) in expected.iter().zip(actual.iter()) {
+                let error = (e - a).abs();
+                max_output_error = max_output_error.max(error);
+                sum += error;
+                count += 1;
+            }
+        }
+
+        Ok(FixedReport {
+            max_parameter_error: max_parameter_error,
+            saturated_parameters: saturated,
+            samples: samples.len(),
+            max_output_error: max_output_error,
+            mean_output_error: if count > 0 { sum / count as f64 } else { 0.0 }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, StdRng};
+
+    use neural_network::cpu::CpuInstance;
+    use neural_network::differential::random_network;
+
+    use super::*;
+
+    /// Worst case output error of the fixed-point network: rounding of inputs, weights, biases
+    /// and sums plus the error of the activation approximations, propagated layer by layer
+    fn error_bound(network: &NeuralNetwork<f64>, input_magnitude: f64, frac_bits: u8) -> f64 {
+        let ulp = 1.0 / (1i64 << frac_bits) as f64;
+        let (mut error, mut magnitude) = (ulp / 2.0, input_magnitude);
+
+        for layer in network.build().unwrap().layers() {
+            let (mut next_error, mut next_magnitude) = (0.0f64, 0.0f64);
+
+            for (row, bias) in layer.biases().iter().enumerate() {
+                let weights = &layer.weights()[row * layer.inputs()..(row + 1) * layer.inputs()];
+                let sum: f64 = weights.iter().map(|w| w.abs()).sum();
+
+                let pre_error = sum * error + layer.inputs() as f64 * ulp / 2.0 * (magnitude + error) + ulp;
+                let pre_magnitude = sum * magnitude + bias.abs();
+
+                let (post_error, post_magnitude) = match layer.activation() {
+                    NeuronType::Identity | NeuronType::DeLu => (pre_error, pre_magnitude),
+                    NeuronType::TanH => (pre_error + 0.025 + ulp, 1.0),
+                    NeuronType::SigMoid => (0.25 * pre_error + 0.0125 + ulp, 1.0)
+                };
+                next_error = next_error.max(post_error);
+                next_magnitude = next_magnitude.max(post_magnitude);
+            }
+
+            error = next_error;
+            magnitude = next_magnitude;
+        }
+
+        error
+    }
+
+    #[test]
+    fn matches_cpu_within_quantization_bound() {
+        let seed: &[usize] = &[1];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+
+        for frac_bits in &[8u8, 16, MAX_FRAC_BITS] {
+            for _ in 0..50 {
+                let network: NeuralNetwork<f64> = random_network(&mut rng, 4, 8);
+                let model = FixedModel::convert(&network, *frac_bits).unwrap();
+                let bound = error_bound(&network, 1.0, *frac_bits);
+
+                let mut instance = CpuInstance::new(&network).unwrap();
+                let mut expected = vec![0.0; instance.output_len()];
+
+                for _ in 0..10 {
+                    let inputs: Vec<f64> = (0..network.inputs()).map(|_| rng.gen_range(-1.0, 1.0)).collect();
+                    instance.calculate(&inputs, &mut expected).unwrap();
+                    let actual = model.calculate(&inputs).unwrap();
+
+                    for (a, e) in actual.iter().zip(expected.iter()) {
+                        assert!((a - e).abs() <= bound, "frac_bits {}: fixed {}, cpu {}, bound {}", frac_bits, a, e, bound);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_frac_bits() {
+        let seed: &[usize] = &[2];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let network: NeuralNetwork<f64> = random_network(&mut rng, 2, 4);
+
+        for frac_bits in &[MAX_FRAC_BITS + 1, 63, 64, 255] {
+            assert_eq!(FixedModel::convert(&network, *frac_bits).unwrap_err(), ConvertError::Fixed(FixedError::UnsupportedFracBits(*frac_bits)));
+        }
+        assert!(FixedModel::convert(&network, MAX_FRAC_BITS).is_ok());
+    }
+}
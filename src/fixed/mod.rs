@@ -0,0 +1,278 @@
+//! Fixed-point inference for microcontrollers
+//!
+//! Only depends on `core`, build the crate with `--no-default-features` to get a `no_std`
+//! library containing just this module. Values are signed Q-format numbers stored in an `i32`
+//! with `frac_bits` fractional bits. Weights live in (static) slices and all intermediate values
+//! are written into two caller provided buffers, nothing is allocated.
+//!
+//! TanH and SigMoid are approximated by `x * (27 + x²) / (27 + 9x²)`, which stays within
+//! 0.025 of the exact functions.
+
+#[cfg(feature = "std")]
+pub mod convert;
+
+/// Activation function of a fixed-point layer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FixedActivation {
+    Identity,
+    SigMoid,
+    TanH,
+    /// Rectified linear unit
+    DeLu
+}
+
+/// Errors for fixed-point inference
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FixedError {
+    /// Amount of given inputs does not match the network
+    ///  (amount_of_inputs)
+    InputMismatch(usize),
+
+    /// Size of the given output buffer does not match the network
+    ///  (size_of_buffer)
+    OutputMismatch(usize),
+
+    /// A scratch buffer is smaller than `FixedNetwork::buffer_len`
+    ///  (size_of_buffer)
+    BufferTooSmall(usize),
+
+    /// Weights or biases of a layer do not match its shape
+    ///  (layer_number)
+    ShapeMismatch(usize),
+
+    /// The network has no layers
+    EmptyNetwork,
+
+    /// More fractional bits than `MAX_FRAC_BITS`
+    ///  (frac_bits)
+    UnsupportedFracBits(u8),
+
+    /// The float network could not be compiled, only returned by the converter
+    InvalidNetwork
+}
+
+/// Largest supported amount of fractional bits
+///
+/// The activations multiply Q-format values in `i64`, which overflows from about 29 bits on.
+/// 24 bits keep a margin and still leave 7 integer bits.
+pub const MAX_FRAC_BITS: u8 = 24;
+
+/// A single layer, weights are a row-major `outputs x inputs` matrix
+#[derive(Copy, Clone, Debug)]
+pub struct FixedLayer<'a> {
+    pub inputs: usize,
+    pub outputs: usize,
+    pub weights: &'a [i32],
+    pub biases: &'a [i32],
+    pub activation: FixedActivation
+}
+
+/// Fixed-point neural network
+#[derive(Copy, Clone, Debug)]
+pub struct FixedNetwork<'a> {
+    frac_bits: u8,
+    layers: &'a [FixedLayer<'a>]
+}
+
+impl<'a> FixedNetwork<'a> {
+    /// Creates a network, checks the amount of fractional bits and that all layers fit together
+    pub fn new(frac_bits: u8, layers: &'a [FixedLayer<'a>]) -> Result<Self, FixedError> {
+        if frac_bits > MAX_FRAC_BITS {
+            return Err(FixedError::UnsupportedFracBits(frac_bits));
+        }
+        if layers.is_empty() {
+            return Err(FixedError::EmptyNetwork);
+        }
+
+        for (index, layer) in layers.iter().enumerate() {
+            let fits_previous = index == 0 || layers[index - 1].outputs == layer.inputs;
+            if !fits_previous || layer.weights.len() != layer.inputs * layer.outputs || layer.biases.len() != layer.outputs {
+                return Err(FixedError::ShapeMismatch(index));
+            }
+        }
+
+        Ok(FixedNetwork {
+            frac_bits: frac_bits,
+            layers: layers
+        })
+    }
+
+    /// Amount of fractional bits
+    pub fn frac_bits(&self) -> u8 {
+        self.frac_bits
+    }
+
+    /// Amount of inputs the network expects
+    pub fn inputs(&self) -> usize {
+        self.layers[0].inputs
+    }
+
+    /// Amount of outputs the network produces
+    pub fn outputs(&self) -> usize {
+        self.layers[self.layers.len() - 1].outputs
+    }
+
+    /// Minimum size of each of the two scratch buffers
+    pub fn buffer_len(&self) -> usize {
+        self.layers.iter().fold(0, |len, layer| if layer.outputs > len { layer.outputs } else { len })
+    }
+
+    /// Calculates the network, `front` and `back` must hold at least `buffer_len()` values
+    pub fn calculate(&self, inputs: &[i32], front: &mut [i32], back: &mut [i32], outputs: &mut [i32]) -> Result<(), FixedError> {
+        if inputs.len() != self.inputs() {
+            return Err(FixedError::InputMismatch(inputs.len()));
+        }
+        if outputs.len() != self.outputs() {
+            return Err(FixedError::OutputMismatch(outputs.len()));
+        }
+        let needed = self.buffer_len();
+        if front.len() < needed {
+            return Err(FixedError::BufferTooSmall(front.len()));
+        }
+        if back.len() < needed {
+            return Err(FixedError::BufferTooSmall(back.len()));
+        }
+
+        let last = self.layers.len() - 1;
+        let mut source_is_input = true;
+        let (mut current, mut next) = (front, back);
+
+        for (index, layer) in self.layers.iter().enumerate() {
+            {
+                let source: &[i32] = if source_is_input { inputs } else { &current[..layer.inputs] };
+                let target: &mut [i32] = if index == last { &mut *outputs } else { &mut next[..layer.outputs] };
+                forward(layer, self.frac_bits, source, target);
+            }
+
+            source_is_input = false;
+            ::core::mem::swap(&mut current, &mut next);
+        }
+
+        Ok(())
+    }
+}
+
+fn forward(layer: &FixedLayer, frac_bits: u8, input: &[i32], output: &mut [i32]) {
+    let rounding: i64 = if frac_bits > 0 { 1 << (frac_bits - 1) } else { 0 };
+
+    for row in 0..layer.outputs {
+        let weights = &layer.weights[row * layer.inputs..(row + 1) * layer.inputs];
+
+        // products carry 2 * frac_bits fractional bits
+        let mut acc = (layer.biases[row] as i64) << frac_bits;
+        for i in 0..layer.inputs {
+            acc += weights[i] as i64 * input[i] as i64;
+        }
+
+        let value = saturate((acc + rounding) >> frac_bits);
+        output[row] = activate(layer.activation, value, frac_bits);
+    }
+}
+
+/// Applies an activation function to a fixed-point value
+pub fn activate(activation: FixedActivation, x: i32, frac_bits: u8) -> i32 {
+    match activation {
+        FixedActivation::Identity => x,
+        FixedActivation::DeLu => if x > 0 { x } else { 0 },
+        FixedActivation::TanH => tanh(x, frac_bits),
+        FixedActivation::SigMoid => {
+            // sigmoid(x) = (1 + tanh(x / 2)) / 2
+            let one = 1i32 << frac_bits;
+            (one + tanh(x / 2, frac_bits)) / 2
+        }
+    }
+}
+
+fn tanh(x: i32, frac_bits: u8) -> i32 {
+    let one = 1i64 << frac_bits;
+    let x = x as i64;
+
+    if x >= 3 * one {
+        return one as i32;
+    }
+    if x <= -3 * one {
+        return -one as i32;
+    }
+
+    let x2 = (x * x) >> frac_bits;
+    ((x * (27 * one + x2)) / (27 * one + 9 * x2)) as i32
+}
+
+fn saturate(value: i64) -> i32 {
+    if value > ::core::i32::MAX as i64 {
+        ::core::i32::MAX
+    } else if value < ::core::i32::MIN as i64 {
+        ::core::i32::MIN
+    } else {
+        value as i32
+    }
+}
+
+/// Converts a float into Q-format, saturating at the range of `i32`
+pub fn to_fixed(value: f64, frac_bits: u8) -> i32 {
+    let scaled = value * (1i64 << frac_bits) as f64;
+    let rounded = if scaled >= 0.0 { scaled + 0.5 } else { scaled - 0.5 };
+
+    if rounded >= ::core::i32::MAX as f64 {
+        ::core::i32::MAX
+    } else if rounded <= ::core::i32::MIN as f64 {
+        ::core::i32::MIN
+    } else {
+        rounded as i32
+    }
+}
+
+/// Converts a Q-format value back into a float
+pub fn from_fixed(value: i32, frac_bits: u8) -> f64 {
+    value as f64 / (1i64 << frac_bits) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEIGHTS: [i32; 4] = [1 << 8, -(1 << 7), 1 << 6, 1 << 8];
+    const BIASES: [i32; 2] = [0, 1 << 7];
+
+    fn layer(activation: FixedActivation) -> FixedLayer<'static> {
+        FixedLayer { inputs: 2, outputs: 2, weights: &WEIGHTS, biases: &BIASES, activation: activation }
+    }
+
+    #[test]
+    fn new_validates() {
+        let layers = [layer(FixedActivation::Identity)];
+        assert!(FixedNetwork::new(8, &layers).is_ok());
+        assert_eq!(FixedNetwork::new(MAX_FRAC_BITS + 1, &layers).unwrap_err(), FixedError::UnsupportedFracBits(MAX_FRAC_BITS + 1));
+        assert_eq!(FixedNetwork::new(8, &[]).unwrap_err(), FixedError::EmptyNetwork);
+
+        let mut wide = layer(FixedActivation::Identity);
+        wide.inputs = 1;
+        assert_eq!(FixedNetwork::new(8, &[layer(FixedActivation::Identity), wide]).unwrap_err(), FixedError::ShapeMismatch(1));
+    }
+
+    #[test]
+    fn calculates_identity_layers() {
+        // weights are 1, -0.5, 0.25 and 1 with 8 fractional bits
+        let layers = [layer(FixedActivation::Identity)];
+        let network = FixedNetwork::new(8, &layers).unwrap();
+
+        let (mut front, mut back, mut outputs) = ([0; 2], [0; 2], [0; 2]);
+        network.calculate(&[to_fixed(1.0, 8), to_fixed(2.0, 8)], &mut front, &mut back, &mut outputs).unwrap();
+        assert_eq!(outputs, [to_fixed(0.0, 8), to_fixed(2.75, 8)]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn activations_stay_close_at_max_frac_bits() {
+        let mut x = -6.0;
+        while x <= 6.0 {
+            let raw = to_fixed(x, MAX_FRAC_BITS);
+            let tanh = from_fixed(activate(FixedActivation::TanH, raw, MAX_FRAC_BITS), MAX_FRAC_BITS);
+            let sigmoid = from_fixed(activate(FixedActivation::SigMoid, raw, MAX_FRAC_BITS), MAX_FRAC_BITS);
+
+            assert!((tanh - x.tanh()).abs() <= 0.025, "tanh({}) = {}", x, tanh);
+            assert!((sigmoid - 1.0 / (1.0 + (-x).exp())).abs() <= 0.0125 + 1e-6, "sigmoid({}) = {}", x, sigmoid);
+            x += 0.01;
+        }
+    }
+}
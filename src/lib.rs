@@ -1,18 +1,53 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate log;
+#[cfg(feature = "std")]
 extern crate rand;
+#[cfg(feature = "std")]
 extern crate scoped_threadpool;
+#[cfg(feature = "std")]
 extern crate bincode;
+#[cfg(feature = "std")]
 extern crate rustc_serialize;
+#[cfg(feature = "std")]
 extern crate byteorder;
+#[cfg(feature = "std")]
+extern crate core;
 
+#[cfg(feature = "std")]
 pub mod neural_network;
+#[cfg(feature = "std")]
 pub mod evolution;
+#[cfg(feature = "std")]
+pub mod codegen;
+#[cfg(feature = "std")]
+pub mod training;
+pub mod fixed;
 
+#[cfg(feature = "std")]
 pub use neural_network::NeuralNetwork;
+#[cfg(feature = "std")]
 pub use neural_network::NeuronType;
+#[cfg(feature = "std")]
 pub use neural_network::Instance;
+#[cfg(feature = "std")]
+pub use neural_network::float::{Float, Precision};
+
+#[cfg(feature = "std")]
+pub use neural_network::plan::ExecutionPlan;
 
+#[cfg(feature = "std")]
 pub use neural_network::cpu::CpuInstance;
+#[cfg(feature = "std")]
+pub use neural_network::quantized::QuantizedInstance;
+#[cfg(feature = "std")]
+pub use neural_network::reference::ReferenceInstance;
+#[cfg(feature = "std")]
+pub use neural_network::boxed::{BoxedInstance, Backend};
+#[cfg(feature = "std")]
+pub use neural_network::ensemble::EnsembleInstance;
 
+#[cfg(feature = "std")]
 pub use evolution::*;
@@ -0,0 +1,93 @@
+//! Backend agnostic instances
+//!
+//! Every backend reports its own error type, `boxed` erases it so instances of different
+//! backends can be stored behind the same `BoxedInstance`.
+
+use std::error::Error;
+
+use neural_network::*;
+use neural_network::float::Float;
+use neural_network::cpu::CpuInstance;
+use neural_network::quantized::QuantizedInstance;
+use neural_network::reference::ReferenceInstance;
+
+/// Error of a boxed instance
+pub type BoxedError = Box<dyn Error + Send + Sync>;
+
+/// Instance whose backend is chosen at runtime
+pub type BoxedInstance<'a, F = f64> = Box<dyn Instance<'a, F, Error = BoxedError> + 'a>;
+
+/// Available backends
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Backend {
+    /// `CpuInstance`
+    Cpu,
+    /// `QuantizedInstance` without calibration
+    Quantized,
+    /// `ReferenceInstance`
+    Reference
+}
+
+impl Backend {
+    /// All available backends
+    pub fn all() -> &'static [Backend] {
+        const ALL: [Backend; 3] = [Backend::Cpu, Backend::Quantized, Backend::Reference];
+        &ALL
+    }
+
+    /// Creates an instance of this backend for the given network
+    pub fn instantiate<'a, F: Float>(&self, network: &'a NeuralNetwork<F>) -> Result<BoxedInstance<'a, F>, BoxedError> {
+        match *self {
+            Backend::Cpu => {
+                let instance: CpuInstance<F> = try!(Instance::new(network));
+                Ok(boxed(instance))
+            },
+            Backend::Quantized => {
+                let instance: QuantizedInstance = try!(Instance::new(network));
+                Ok(boxed(instance))
+            },
+            Backend::Reference => {
+                let instance: ReferenceInstance<F> = try!(Instance::new(network));
+                Ok(boxed(instance))
+            }
+        }
+    }
+}
+
+/// Boxes an instance, erasing its backend
+pub fn boxed<'a, F, I>(instance: I) -> BoxedInstance<'a, F>
+    where F: Float, I: Instance<'a, F> + 'a, I::Error: Error + Send + Sync + 'static
+{
+    Box::new(ErasedInstance(instance))
+}
+
+/// Wrapper that boxes the errors of an instance
+struct ErasedInstance<I>(I);
+
+impl<'a, F, I> Instance<'a, F> for ErasedInstance<I>
+    where F: Float, I: Instance<'a, F>, I::Error: Error + Send + Sync + 'static
+{
+    type Error = BoxedError;
+
+    fn new(network: &'a NeuralNetwork<F>) -> Result<Self, BoxedError> {
+        match I::new(network) {
+            Ok(instance) => Ok(ErasedInstance(instance)),
+            Err(err) => Err(Box::new(err))
+        }
+    }
+
+    fn input_len(&self) -> usize {
+        self.0.input_len()
+    }
+
+    fn output_len(&self) -> usize {
+        self.0.output_len()
+    }
+
+    fn calculate(&mut self, inputs: &[F], outputs: &mut [F]) -> Result<(), BoxedError> {
+        match self.0.calculate(inputs, outputs) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(Box::new(err))
+        }
+    }
+}
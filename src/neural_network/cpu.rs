@@ -1,7 +1,19 @@
+use std::error::Error;
+use std::fmt;
+
 use neural_network::*;
+use neural_network::float::Float;
+use neural_network::hooks::{ForwardHook, NoHook, ActivationCapture};
+use neural_network::plan::{ExecutionPlan, PlanError};
+
+pub struct CpuInstance<'a, F: Float + 'a = f64> {
+    network: &'a NeuralNetwork<F>,
 
-pub struct CpuInstance<'a> {
-    network: &'a NeuralNetwork
+    /// Compiled network
+    plan: ExecutionPlan<F>,
+
+    /// Ping-pong buffers for intermediate layer values
+    scratch: (Vec<F>, Vec<F>)
 }
 
 /// Errors for CpuInstace
@@ -16,85 +28,121 @@ pub enum CpuInstanceError {
 
     /// Currently mixing multiple activation functions within one layer is not supported
     ///  (layer_number)
-    UnsupportedActivationMix(usize)
-}
+    UnsupportedActivationMix(usize),
 
-/// applies activation over an set of values
-fn apply_activation(values:&mut Vec<f64>, neuron_type: NeuronType) {
-    match neuron_type {
-        NeuronType::TanH => {
-            for i in 0..values.len() {
-                values[i] = values[i].tanh();
-            }
-        },
-        _ => {
+    /// The network could not be compiled into an execution plan
+    InvalidNetwork(PlanError),
+
+    /// Amount of given inputs does not match the network
+    ///  (amount_of_inputs)
+    InputMismatch(usize),
+
+    /// Size of the given output buffer does not match the network
+    ///  (size_of_buffer)
+    OutputMismatch(usize)
+}
 
+impl fmt::Display for CpuInstanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuInstanceError::ThreadFailure => write!(f, "internal thread failure"),
+            CpuInstanceError::UnsupportedNeuronType(ref neuron_type) => write!(f, "unsupported neuron type {:?}", neuron_type),
+            CpuInstanceError::UnsupportedActivationMix(layer) => write!(f, "layer {} mixes activation functions", layer),
+            CpuInstanceError::InvalidNetwork(ref err) => write!(f, "invalid network: {}", err),
+            CpuInstanceError::InputMismatch(len) => write!(f, "got {} inputs", len),
+            CpuInstanceError::OutputMismatch(len) => write!(f, "output buffer holds {} values", len)
         }
     }
 }
 
-/// validates a neural network and returns either Some(error) or None
-fn validate(network: &NeuralNetwork) -> Option<CpuInstanceError> {
-    // TODO do optional validation (feature flag)
-    None
+impl Error for CpuInstanceError {
+    fn description(&self) -> &str {
+        "cpu instance error"
+    }
 }
 
-impl<'a> Instance<'a, CpuInstanceError> for CpuInstance<'a> {
-    fn new (network: &'a NeuralNetwork) -> Result<Self, CpuInstanceError> {
-        match validate(network) {
-            Some(err) => {
-                return Err(err);
-            },
-            None => {
-                return Ok(CpuInstance {
-                    network: network
-                })
-            }
+impl From<PlanError> for CpuInstanceError {
+    fn from(err: PlanError) -> Self {
+        match err {
+            PlanError::ActivationMix(layer) => CpuInstanceError::UnsupportedActivationMix(layer),
+            err => CpuInstanceError::InvalidNetwork(err)
         }
     }
+}
 
-    fn calculate(&mut self, inputs: &Vec<f64>, outputs: &mut Vec<f64>) -> Result<(), CpuInstanceError> {
-        let mut previous_values: Vec<f64> = Vec::new();
-        let mut current_values: Vec<f64> = Vec::new();
+impl<'a, F: Float> CpuInstance<'a, F> {
+    /// Network this instance was created from
+    pub fn network(&self) -> &'a NeuralNetwork<F> {
+        self.network
+    }
 
-        let mut previous_layer = 0;
+    /// Compiled execution plan used for inference
+    pub fn plan(&self) -> &ExecutionPlan<F> {
+        &self.plan
+    }
 
-        // pretend inputs form the previous layer
-        for val in inputs {
-            previous_values.push(*val);
+    /// Calulates like `calculate` while passing the values of every layer to the hook
+    pub fn calculate_with<H: ForwardHook<F>>(&mut self, inputs: &[F], outputs: &mut [F], hook: &mut H) -> Result<(), CpuInstanceError> {
+        if inputs.len() != self.plan.inputs() {
+            return Err(CpuInstanceError::InputMismatch(inputs.len()));
+        }
+        if outputs.len() != self.plan.outputs() {
+            return Err(CpuInstanceError::OutputMismatch(outputs.len()));
         }
 
-        // loop through neurons
-        for (layer_index, neuron) in self.network.iter() {
-            if previous_layer != layer_index {
-                // new layer reached
-                previous_values = current_values.clone();
-                current_values.clear();
-
-                // apply activation
-                apply_activation(&mut previous_values, NeuronType::TanH);
-            }
+        self.plan.execute_with(inputs, 1, &mut self.scratch, outputs, hook);
 
-            // new neuron
-            let mut val = 0.0;
-            for i in 0..neuron.weights.len() {
-                val += neuron.weights[i] * previous_values[i];
-            }
-            val += neuron.bias;
+        Ok(())
+    }
 
-            current_values.push(val);
+    /// Calulates and records the pre- and post-activation values of every layer
+    pub fn capture(&mut self, inputs: &[F]) -> Result<ActivationCapture<F>, CpuInstanceError> {
+        let mut capture = ActivationCapture::new();
+        let mut outputs = vec![F::zero(); self.plan.outputs()];
+        try!(self.calculate_with(inputs, &mut outputs, &mut capture));
+        Ok(capture)
+    }
 
-            previous_layer = layer_index;
+    /// Calulates `inputs.len() / input_len()` samples at once using matrix-matrix kernels
+    ///
+    /// `outputs` must hold `output_len()` values per sample.
+    pub fn calculate_batch(&mut self, inputs: &[F], outputs: &mut [F]) -> Result<(), CpuInstanceError> {
+        let batch = inputs.len() / self.plan.inputs();
+        if batch == 0 || batch * self.plan.inputs() != inputs.len() {
+            return Err(CpuInstanceError::InputMismatch(inputs.len()));
         }
-
-        // since technically the output layer is represented as an additional output layer we are done here
-        for val in current_values {
-            outputs.push(val);
+        if outputs.len() != batch * self.plan.outputs() {
+            return Err(CpuInstanceError::OutputMismatch(outputs.len()));
         }
 
-        // Also apply activation over outputs
-        apply_activation(outputs, NeuronType::TanH);
+        self.plan.execute_batch(inputs, batch, &mut self.scratch, outputs);
 
         Ok(())
     }
 }
+
+impl<'a, F: Float> Instance<'a, F> for CpuInstance<'a, F> {
+    type Error = CpuInstanceError;
+
+    fn new (network: &'a NeuralNetwork<F>) -> Result<Self, CpuInstanceError> {
+        let plan = try!(network.build());
+
+        Ok(CpuInstance {
+            network: network,
+            plan: plan,
+            scratch: (Vec::new(), Vec::new())
+        })
+    }
+
+    fn input_len(&self) -> usize {
+        self.plan.inputs()
+    }
+
+    fn output_len(&self) -> usize {
+        self.plan.outputs()
+    }
+
+    fn calculate(&mut self, inputs: &[F], outputs: &mut [F]) -> Result<(), CpuInstanceError> {
+        self.calculate_with(inputs, outputs, &mut NoHook)
+    }
+}
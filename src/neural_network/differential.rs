@@ -0,0 +1,230 @@
+//! Differential testing of backends against `ReferenceInstance`
+//!
+//! Random networks (mixed activations, random shapes) and random inputs are generated from a
+//! seed, so every reported mismatch can be reproduced.
+
+use rand::{Rng, SeedableRng, StdRng};
+
+use neural_network::*;
+use neural_network::float::{Float, Precision};
+use neural_network::boxed::Backend;
+use neural_network::reference::ReferenceInstance;
+
+const NEURON_TYPES: [NeuronType; 4] = [NeuronType::Identity, NeuronType::SigMoid, NeuronType::TanH, NeuronType::DeLu];
+
+/// Maximum amount of mismatches kept per report
+const MAX_REPORTED: usize = 10;
+
+pub struct DifferentialOptions {
+    /// Amount of random networks
+    pub networks: usize,
+
+    /// Amount of random inputs per network
+    pub samples: usize,
+
+    /// Maximum amount of layers of a network (including the output layer)
+    pub max_layers: usize,
+
+    /// Maximum amount of inputs and neurons per layer
+    pub max_width: usize,
+
+    /// Seed for networks and inputs
+    pub seed: usize
+}
+
+impl DifferentialOptions {
+    pub fn defaults() -> Self {
+        DifferentialOptions {
+            networks: 50,
+            samples: 10,
+            max_layers: 4,
+            max_width: 16,
+            seed: 1
+        }
+    }
+}
+
+/// Output that differs more than the tolerance
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    /// Index of the generated network
+    pub network: usize,
+    /// Index of the input within the network
+    pub sample: usize,
+    /// Index of the output value
+    pub output: usize,
+
+    pub expected: f64,
+    pub actual: f64
+}
+
+/// Result of checking a backend
+#[derive(Clone, Debug)]
+pub struct DifferentialReport {
+    pub backend: Backend,
+
+    /// Allowed error, relative to `max(1, |expected|)`
+    pub tolerance: f64,
+
+    /// Amount of compared output values
+    pub comparisons: usize,
+
+    /// Largest error, relative to `max(1, |expected|)`
+    pub max_error: f64,
+
+    /// Amount of output values exceeding the tolerance
+    pub failures: usize,
+
+    /// First mismatches, at most 10
+    pub mismatches: Vec<Mismatch>,
+
+    /// Networks the backend refused to instantiate or calculate
+    pub errors: Vec<(usize, String)>
+}
+
+impl DifferentialReport {
+    /// Whether the backend agreed with the reference everywhere
+    pub fn passed(&self) -> bool {
+        self.failures == 0 && self.errors.is_empty()
+    }
+}
+
+/// Tolerance the backends are expected to keep for the float type `F`
+pub fn default_tolerance<F: Float>(backend: Backend) -> f64 {
+    match (backend, F::precision()) {
+        (Backend::Reference, Precision::Double) => 0.0,
+        // outputs are rounded to f32
+        (Backend::Reference, Precision::Single) => ::std::f32::EPSILON as f64,
+        (Backend::Cpu, Precision::Double) => 1e-9,
+        (Backend::Cpu, Precision::Single) => 1e-4,
+        (Backend::Quantized, _) => 0.25
+    }
+}
+
+/// Generates a random network, all neurons of a layer share one activation function
+pub fn random_network<F: Float, R: Rng>(rng: &mut R, max_layers: usize, max_width: usize) -> NeuralNetwork<F> {
+    let inputs = rng.gen_range(1, max_width + 1);
+    let layer_count = rng.gen_range(1, max_layers + 1);
+
+    let mut layers = Vec::with_capacity(layer_count);
+    let mut width = inputs;
+
+    for _ in 0..layer_count {
+        let neurons = rng.gen_range(1, max_width + 1);
+        let neuron_type = NEURON_TYPES[rng.gen_range(0, NEURON_TYPES.len())];
+
+        let layer = (0..neurons).map(|_| {
+            let weights = (0..width).map(|_| F::from_f64(rng.gen_range(-1.0, 1.0))).collect();
+            Neuron::new(weights, F::from_f64(rng.gen_range(-1.0, 1.0)), neuron_type)
+        }).collect();
+
+        layers.push(layer);
+        width = neurons;
+    }
+
+    NeuralNetwork::from_layers(inputs, layers)
+}
+
+/// Checks one backend against the reference
+pub fn check<F: Float>(backend: Backend, tolerance: f64, options: &DifferentialOptions) -> DifferentialReport {
+    let seed: &[usize] = &[options.seed];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+
+    let mut report = DifferentialReport {
+        backend: backend,
+        tolerance: tolerance,
+        comparisons: 0,
+        max_error: 0.0,
+        failures: 0,
+        mismatches: Vec::new(),
+        errors: Vec::new()
+    };
+
+    for network_index in 0..options.networks {
+        let network: NeuralNetwork<F> = random_network(&mut rng, options.max_layers, options.max_width);
+        let reference = ReferenceInstance::new(&network).unwrap();
+
+        let mut instance = match backend.instantiate(&network) {
+            Ok(instance) => instance,
+            Err(err) => {
+                report.errors.push((network_index, format!("{}", err)));
+                continue;
+            }
+        };
+
+        let mut outputs = vec![F::zero(); instance.output_len()];
+
+        for sample in 0..options.samples {
+            let inputs: Vec<F> = (0..network.inputs()).map(|_| F::from_f64(rng.gen_range(-2.0, 2.0))).collect();
+            let expected = reference.calculate_f64(&inputs).unwrap();
+
+            if let Err(err) = instance.calculate(&inputs, &mut outputs) {
+                report.errors.push((network_index, format!("{}", err)));
+                break;
+            }
+
+            for (index, (e, a)) in expected.iter().zip(outputs.iter()).enumerate() {
+                let actual = a.to_f64();
+                let error = (actual - e).abs() / e.abs().max(1.0);
+
+                report.comparisons += 1;
+                report.max_error = report.max_error.max(error);
+
+                // also catches NaN
+                if !(error <= tolerance) {
+                    report.failures += 1;
+                    if report.mismatches.len() < MAX_REPORTED {
+                        report.mismatches.push(Mismatch {
+                            network: network_index,
+                            sample: sample,
+                            output: index,
+                            expected: *e,
+                            actual: actual
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    report
+}
+
+/// Checks every backend against the reference using its default tolerance
+pub fn check_all<F: Float>(options: &DifferentialOptions) -> Vec<DifferentialReport> {
+    Backend::all().iter()
+        .filter(|backend| **backend != Backend::Reference)
+        .map(|backend| check::<F>(*backend, default_tolerance::<F>(*backend), options))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_seeds<F: Float>() {
+        for seed in 1..6 {
+            let mut options = DifferentialOptions::defaults();
+            options.networks = 20;
+            options.seed = seed;
+
+            let mut reports = check_all::<F>(&options);
+            reports.push(check::<F>(Backend::Reference, default_tolerance::<F>(Backend::Reference), &options));
+            assert_eq!(reports.len(), Backend::all().len());
+
+            for report in reports {
+                assert!(report.passed(), "seed {}: {:?}", seed, report);
+            }
+        }
+    }
+
+    #[test]
+    fn backends_match_reference_f64() {
+        check_seeds::<f64>();
+    }
+
+    #[test]
+    fn backends_match_reference_f32() {
+        check_seeds::<f32>();
+    }
+}
@@ -0,0 +1,413 @@
+//! Combining the outputs of several networks
+//!
+//! Typically used with the best individuals of an evolution. Besides the combined output the
+//! ensemble reports how much its members disagree, which serves as an uncertainty signal.
+
+use std::error::Error;
+use std::fmt;
+
+use neural_network::*;
+use neural_network::float::Float;
+use neural_network::boxed::{self, BoxedInstance, BoxedError};
+use neural_network::cpu::CpuInstance;
+
+/// How member outputs are combined
+#[derive(Clone, Debug, PartialEq)]
+pub enum Combination {
+    /// Average of all members
+    Mean,
+
+    /// Weighted average, one finite and non-negative weight per member
+    WeightedMean(Vec<f64>),
+
+    /// Median of all members per output
+    Median,
+
+    /// Every member votes for its largest output, each output receives its share of votes.
+    /// Networks with a single output vote yes when the output is at least 0.5.
+    MajorityVote
+}
+
+/// Errors for EnsembleInstance
+#[derive(Debug)]
+pub enum EnsembleError {
+    /// An ensemble needs at least one member
+    NoMembers,
+
+    /// A member has other input or output sizes than the first member
+    ///  (member_index)
+    ShapeMismatch(usize),
+
+    /// WeightedMean needs exactly one finite, non-negative weight per member with a positive sum
+    InvalidWeights,
+
+    /// Amount of given inputs does not match the members
+    ///  (amount_of_inputs)
+    InputMismatch(usize),
+
+    /// Size of the given output buffer does not match the members
+    ///  (size_of_buffer)
+    OutputMismatch(usize),
+
+    /// A member failed
+    ///  (member_index, error)
+    Member(usize, BoxedError)
+}
+
+impl fmt::Display for EnsembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EnsembleError::NoMembers => write!(f, "ensemble has no members"),
+            EnsembleError::ShapeMismatch(member) => write!(f, "member {} has a different shape", member),
+            EnsembleError::InvalidWeights => write!(f, "invalid member weights"),
+            EnsembleError::InputMismatch(len) => write!(f, "got {} inputs", len),
+            EnsembleError::OutputMismatch(len) => write!(f, "output buffer holds {} values", len),
+            EnsembleError::Member(member, ref err) => write!(f, "member {} failed: {}", member, err)
+        }
+    }
+}
+
+impl Error for EnsembleError {
+    fn description(&self) -> &str {
+        "ensemble instance error"
+    }
+}
+
+/// How much the members disagreed on the last calculation
+#[derive(Clone, Debug, Default)]
+pub struct Disagreement {
+    /// Standard deviation of the member outputs, per output
+    pub std_dev: Vec<f64>,
+
+    /// Mean of `std_dev`
+    pub mean_std_dev: f64,
+
+    /// Share of members that voted for the winning output (1.0 means unanimous)
+    pub vote_agreement: f64
+}
+
+/// Instance combining several member instances
+pub struct EnsembleInstance<'a, F: Float + 'a = f64> {
+    members: Vec<BoxedInstance<'a, F>>,
+    combination: Combination,
+
+    /// Outputs of every member, `members x outputs`
+    member_outputs: Vec<F>,
+    disagreement: Disagreement
+}
+
+impl<'a, F: Float> EnsembleInstance<'a, F> {
+    /// Creates an ensemble from instances of any backend
+    pub fn from_instances(members: Vec<BoxedInstance<'a, F>>, combination: Combination) -> Result<Self, EnsembleError> {
+        if members.is_empty() {
+            return Err(EnsembleError::NoMembers);
+        }
+
+        for (index, member) in members.iter().enumerate() {
+            if member.input_len() != members[0].input_len() || member.output_len() != members[0].output_len() {
+                return Err(EnsembleError::ShapeMismatch(index));
+            }
+        }
+
+        if let Combination::WeightedMean(ref weights) = combination {
+            let sum = weights.iter().fold(0.0, |sum, w| sum + w);
+            let valid = weights.iter().all(|w| w.is_finite() && *w >= 0.0);
+            if weights.len() != members.len() || !valid || !(sum > 0.0) {
+                return Err(EnsembleError::InvalidWeights);
+            }
+        }
+
+        let outputs = members[0].output_len();
+        let count = members.len();
+
+        Ok(EnsembleInstance {
+            members: members,
+            combination: combination,
+            member_outputs: vec![F::zero(); count * outputs],
+            disagreement: Disagreement::default()
+        })
+    }
+
+    /// Creates an ensemble of `CpuInstance`s
+    pub fn from_networks(networks: &'a [NeuralNetwork<F>], combination: Combination) -> Result<Self, EnsembleError> {
+        let mut members = Vec::with_capacity(networks.len());
+        for (index, network) in networks.iter().enumerate() {
+            let instance: CpuInstance<F> = match Instance::new(network) {
+                Ok(instance) => instance,
+                Err(err) => return Err(EnsembleError::Member(index, Box::new(err)))
+            };
+            members.push(boxed::boxed(instance));
+        }
+
+        EnsembleInstance::from_instances(members, combination)
+    }
+
+    /// Amount of members
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Combination used for the output
+    pub fn combination(&self) -> &Combination {
+        &self.combination
+    }
+
+    /// Disagreement of the members on the last calculation
+    pub fn disagreement(&self) -> &Disagreement {
+        &self.disagreement
+    }
+
+    /// Outputs of every member on the last calculation, one row per member
+    pub fn member_outputs(&self) -> &[F] {
+        &self.member_outputs
+    }
+
+    fn combine(&self, outputs: &mut [F]) {
+        let width = outputs.len();
+        let count = self.members.len();
+        let value = |member: usize, output: usize| self.member_outputs[member * width + output].to_f64();
+
+        match self.combination {
+            Combination::Mean => {
+                for o in 0..width {
+                    let sum = (0..count).fold(0.0, |sum, m| sum + value(m, o));
+                    outputs[o] = F::from_f64(sum / count as f64);
+                }
+            },
+            Combination::WeightedMean(ref weights) => {
+                let total = weights.iter().fold(0.0, |sum, w| sum + w);
+                for o in 0..width {
+                    let sum = (0..count).fold(0.0, |sum, m| sum + weights[m] * value(m, o));
+                    outputs[o] = F::from_f64(sum / total);
+                }
+            },
+            Combination::Median => {
+                let mut column: Vec<f64> = Vec::with_capacity(count);
+                for o in 0..width {
+                    column.clear();
+                    column.extend((0..count).map(|m| value(m, o)));
+                    column.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+
+                    let median = if count % 2 == 1 {
+                        column[count / 2]
+                    } else {
+                        (column[count / 2 - 1] + column[count / 2]) / 2.0
+                    };
+                    outputs[o] = F::from_f64(median);
+                }
+            },
+            Combination::MajorityVote => {
+                let votes = self.votes(width);
+                for o in 0..width {
+                    outputs[o] = F::from_f64(votes[o] as f64 / count as f64);
+                }
+            }
+        }
+    }
+
+    /// Votes per output, see `Combination::MajorityVote`
+    fn votes(&self, width: usize) -> Vec<usize> {
+        let mut votes = vec![0; width];
+
+        for member in self.member_outputs.chunks(width) {
+            if width == 1 {
+                if member[0].to_f64() >= 0.5 {
+                    votes[0] += 1;
+                }
+                continue;
+            }
+
+            let mut best = 0;
+            for o in 1..width {
+                if member[o] > member[best] {
+                    best = o;
+                }
+            }
+            votes[best] += 1;
+        }
+
+        votes
+    }
+
+    fn measure_disagreement(&mut self, width: usize) {
+        let count = self.members.len() as f64;
+        let mut std_dev = Vec::with_capacity(width);
+
+        for o in 0..width {
+            let column = self.member_outputs.chunks(width).map(|member| member[o].to_f64());
+            let mean = column.clone().fold(0.0, |sum, v| sum + v) / count;
+            let variance = column.fold(0.0, |sum, v| sum + (v - mean) * (v - mean)) / count;
+            std_dev.push(variance.sqrt());
+        }
+
+        let votes = self.votes(width);
+        let winner = if width == 1 {
+            // yes and no votes both count as agreement
+            ::std::cmp::max(votes[0], self.members.len() - votes[0])
+        } else {
+            votes.iter().fold(0, |best, v| ::std::cmp::max(best, *v))
+        };
+
+        self.disagreement = Disagreement {
+            mean_std_dev: std_dev.iter().fold(0.0, |sum, s| sum + s) / width as f64,
+            std_dev: std_dev,
+            vote_agreement: winner as f64 / count
+        };
+    }
+}
+
+impl<'a, F: Float> Instance<'a, F> for EnsembleInstance<'a, F> {
+    type Error = EnsembleError;
+
+    /// Creates an ensemble with a single member
+    fn new(network: &'a NeuralNetwork<F>) -> Result<Self, EnsembleError> {
+        EnsembleInstance::from_networks(::std::slice::from_ref(network), Combination::Mean)
+    }
+
+    fn input_len(&self) -> usize {
+        self.members[0].input_len()
+    }
+
+    fn output_len(&self) -> usize {
+        self.members[0].output_len()
+    }
+
+    fn calculate(&mut self, inputs: &[F], outputs: &mut [F]) -> Result<(), EnsembleError> {
+        let width = self.output_len();
+        if inputs.len() != self.input_len() {
+            return Err(EnsembleError::InputMismatch(inputs.len()));
+        }
+        if outputs.len() != width {
+            return Err(EnsembleError::OutputMismatch(outputs.len()));
+        }
+
+        for (index, member) in self.members.iter_mut().enumerate() {
+            let target = &mut self.member_outputs[index * width..(index + 1) * width];
+            if let Err(err) = member.calculate(inputs, target) {
+                return Err(EnsembleError::Member(index, err));
+            }
+        }
+
+        self.combine(outputs);
+        self.measure_disagreement(width);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Network ignoring its single input, always returning `outputs`
+    fn constant(outputs: &[f64]) -> NeuralNetwork {
+        NeuralNetwork::from_layers(1, vec![outputs.iter().map(|o| Neuron::new(vec![0.0], *o, NeuronType::Identity)).collect()])
+    }
+
+    fn members() -> Vec<NeuralNetwork> {
+        vec![
+            constant(&[0.1, 0.7, 0.2]),
+            constant(&[0.5, 0.3, 0.2]),
+            constant(&[0.2, 0.6, 0.9]),
+            constant(&[0.0, 0.8, 0.4])
+        ]
+    }
+
+    fn calculate(networks: &[NeuralNetwork], combination: Combination) -> (Vec<f64>, Disagreement) {
+        let mut ensemble = EnsembleInstance::from_networks(networks, combination).unwrap();
+        let mut outputs = vec![0.0; ensemble.output_len()];
+        ensemble.calculate(&[1.0], &mut outputs).unwrap();
+        (outputs, ensemble.disagreement().clone())
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1e-12, "{:?} vs {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn mean() {
+        assert_close(&calculate(&members(), Combination::Mean).0, &[0.2, 0.6, 0.425]);
+    }
+
+    #[test]
+    fn weighted_mean() {
+        // (0.1 + 0.2 + 2 * 0.0) / 4, (0.7 + 0.6 + 2 * 0.8) / 4, (0.2 + 0.9 + 2 * 0.4) / 4
+        let (outputs, _) = calculate(&members(), Combination::WeightedMean(vec![1.0, 0.0, 1.0, 2.0]));
+        assert_close(&outputs, &[0.075, 0.725, 0.475]);
+    }
+
+    #[test]
+    fn median() {
+        // even amount of members, the two middle values are averaged
+        assert_close(&calculate(&members(), Combination::Median).0, &[0.15, 0.65, 0.3]);
+        assert_close(&calculate(&members()[..3], Combination::Median).0, &[0.2, 0.6, 0.2]);
+    }
+
+    #[test]
+    fn majority_vote() {
+        // the members vote for the outputs 1, 0, 2 and 1
+        let (outputs, disagreement) = calculate(&members(), Combination::MajorityVote);
+        assert_close(&outputs, &[0.25, 0.5, 0.25]);
+        assert_close(&[disagreement.vote_agreement], &[0.5]);
+
+        // a single output votes yes from 0.5 on, no votes agree as well
+        let networks = vec![constant(&[0.7]), constant(&[0.2]), constant(&[0.5])];
+        let (outputs, disagreement) = calculate(&networks, Combination::MajorityVote);
+        assert_close(&outputs, &[2.0 / 3.0]);
+        assert_close(&[disagreement.vote_agreement], &[2.0 / 3.0]);
+
+        let networks = vec![constant(&[0.1]), constant(&[0.2]), constant(&[0.9])];
+        assert_close(&[calculate(&networks, Combination::MajorityVote).1.vote_agreement], &[2.0 / 3.0]);
+    }
+
+    #[test]
+    fn disagreement() {
+        // population variances 0.14 / 4, 0.14 / 4 and 0.3275 / 4
+        let (_, disagreement) = calculate(&members(), Combination::Mean);
+        let std_dev = [0.035f64.sqrt(), 0.035f64.sqrt(), 0.081875f64.sqrt()];
+        assert_close(&disagreement.std_dev, &std_dev);
+        assert_close(&[disagreement.mean_std_dev], &[(std_dev[0] + std_dev[1] + std_dev[2]) / 3.0]);
+
+        let networks = vec![constant(&[0.3, 0.4]), constant(&[0.3, 0.4])];
+        let (_, agreement) = calculate(&networks, Combination::Mean);
+        assert_close(&agreement.std_dev, &[0.0, 0.0]);
+        assert_close(&[agreement.vote_agreement], &[1.0]);
+    }
+
+    #[test]
+    fn rejects_invalid_weights() {
+        let networks = members();
+        let invalid = vec![
+            vec![1.0, 1.0, 1.0],
+            vec![0.0, 0.0, 0.0, 0.0],
+            vec![-1.0, 2.0, 1.0, 1.0],
+            vec![::std::f64::NAN, 1.0, 1.0, 1.0],
+            vec![::std::f64::INFINITY, 1.0, 1.0, 1.0]
+        ];
+
+        for weights in invalid {
+            match EnsembleInstance::from_networks(&networks, Combination::WeightedMean(weights.clone())) {
+                Err(EnsembleError::InvalidWeights) => { },
+                Err(err) => panic!("{:?}: {}", weights, err),
+                Ok(_) => panic!("{:?} accepted", weights)
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_mismatching_members() {
+        match EnsembleInstance::<f64>::from_networks(&[], Combination::Mean) {
+            Err(EnsembleError::NoMembers) => { },
+            _ => panic!("empty ensemble accepted")
+        }
+
+        let networks = vec![constant(&[0.1, 0.2]), constant(&[0.1])];
+        match EnsembleInstance::from_networks(&networks, Combination::Mean) {
+            Err(EnsembleError::ShapeMismatch(1)) => { },
+            _ => panic!("members of different shapes accepted")
+        };
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt::Debug;
+use std::ops::{Add, Sub, Mul, Div, Neg, AddAssign, SubAssign, MulAssign};
+use rustc_serialize::{Encodable, Decodable};
+
+use neural_network::simd;
+
+/// Floating point precision of a network
+#[derive(RustcEncodable, RustcDecodable, Copy, Clone, Debug, PartialEq)]
+pub enum Precision {
+    /// 32 bit, f32
+    Single,
+    /// 64 bit, f64
+    Double
+}
+
+/// Floating point type a network can be stored and calculated in
+pub trait Float: Copy + Debug + Default + PartialOrd + Send + Sync + Encodable + Decodable + 'static
+    + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> + Neg<Output = Self>
+    + AddAssign + SubAssign + MulAssign
+{
+    /// Precision tag of this type
+    fn precision() -> Precision;
+
+    fn from_f64(value: f64) -> Self;
+    fn to_f64(self) -> f64;
+
+    #[inline]
+    fn zero() -> Self {
+        Self::from_f64(0.0)
+    }
+
+    #[inline]
+    fn one() -> Self {
+        Self::from_f64(1.0)
+    }
+
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn tanh(self) -> Self;
+    fn abs(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn min(self, other: Self) -> Self;
+
+    /// Dot-product kernel selected for this type
+    fn dot_kernel() -> fn(&[Self], &[Self]) -> Self;
+}
+
+impl Float for f64 {
+    fn precision() -> Precision { Precision::Double }
+
+    #[inline] fn from_f64(value: f64) -> Self { value }
+    #[inline] fn to_f64(self) -> f64 { self }
+
+    #[inline] fn exp(self) -> Self { f64::exp(self) }
+    #[inline] fn ln(self) -> Self { f64::ln(self) }
+    #[inline] fn tanh(self) -> Self { f64::tanh(self) }
+    #[inline] fn abs(self) -> Self { f64::abs(self) }
+    #[inline] fn sqrt(self) -> Self { f64::sqrt(self) }
+    #[inline] fn max(self, other: Self) -> Self { f64::max(self, other) }
+    #[inline] fn min(self, other: Self) -> Self { f64::min(self, other) }
+
+    #[inline]
+    fn dot_kernel() -> fn(&[f64], &[f64]) -> f64 {
+        simd::dot_kernel()
+    }
+}
+
+impl Float for f32 {
+    fn precision() -> Precision { Precision::Single }
+
+    #[inline] fn from_f64(value: f64) -> Self { value as f32 }
+    #[inline] fn to_f64(self) -> f64 { self as f64 }
+
+    #[inline] fn exp(self) -> Self { f32::exp(self) }
+    #[inline] fn ln(self) -> Self { f32::ln(self) }
+    #[inline] fn tanh(self) -> Self { f32::tanh(self) }
+    #[inline] fn abs(self) -> Self { f32::abs(self) }
+    #[inline] fn sqrt(self) -> Self { f32::sqrt(self) }
+    #[inline] fn max(self, other: Self) -> Self { f32::max(self, other) }
+    #[inline] fn min(self, other: Self) -> Self { f32::min(self, other) }
+
+    #[inline]
+    fn dot_kernel() -> fn(&[f32], &[f32]) -> f32 {
+        simd::dot_kernel_f32()
+    }
+}
@@ -0,0 +1,262 @@
+//! Reverse-mode gradients of a loss with respect to every weight and bias
+
+use std::error::Error;
+use std::fmt;
+
+use neural_network::*;
+use neural_network::float::Float;
+
+/// Errors for gradient computation
+#[derive(Debug, Clone, PartialEq)]
+pub enum GradientError {
+    /// Amount of given inputs does not match the network
+    ///  (amount_of_inputs)
+    InputMismatch(usize),
+
+    /// Amount of output gradients does not match the network
+    ///  (amount_of_output_gradients)
+    OutputMismatch(usize),
+
+    /// A neuron has another amount of weights than its previous layer has values,
+    /// or a gradient is not shaped like the network
+    ///  (layer_number)
+    ShapeMismatch(usize)
+}
+
+impl fmt::Display for GradientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GradientError::InputMismatch(len) => write!(f, "got {} inputs", len),
+            GradientError::OutputMismatch(len) => write!(f, "got {} output gradients", len),
+            GradientError::ShapeMismatch(layer) => write!(f, "layer {} does not match the network", layer)
+        }
+    }
+}
+
+impl Error for GradientError {
+    fn description(&self) -> &str {
+        "gradient error"
+    }
+}
+
+impl NeuronType {
+    /// Derivative of the activation function, given the value before and after activation
+    #[inline]
+    pub fn derivative<F: Float>(&self, pre_activation: F, post_activation: F) -> F {
+        match *self {
+            NeuronType::Identity => F::one(),
+            NeuronType::SigMoid => post_activation * (F::one() - post_activation),
+            NeuronType::TanH => F::one() - post_activation * post_activation,
+            NeuronType::DeLu => if pre_activation > F::zero() { F::one() } else { F::zero() },
+        }
+    }
+}
+
+/// Gradient of a single neuron
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct NeuronGradient<F: Float = f64> {
+    pub weights: Vec<F>,
+    pub bias: F
+}
+
+/// Gradient shaped like the layers of a network, `layers[l][n]` belongs to neuron `n` of layer `l`
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct Gradient<F: Float = f64> {
+    pub layers: Vec<Vec<NeuronGradient<F>>>
+}
+
+impl<F: Float> Gradient<F> {
+    /// Zero gradient shaped like the network
+    pub fn zeros(network: &NeuralNetwork<F>) -> Self {
+        Gradient {
+            layers: network.hidden_layers.iter().map(|layer| {
+                layer.iter().map(|neuron| NeuronGradient {
+                    weights: vec![F::zero(); neuron.weights.len()],
+                    bias: F::zero()
+                }).collect()
+            }).collect()
+        }
+    }
+
+    /// Checks whether the gradient is shaped like the network
+    pub fn matches(&self, network: &NeuralNetwork<F>) -> Result<(), GradientError> {
+        if self.layers.len() != network.hidden_layers.len() {
+            return Err(GradientError::ShapeMismatch(::std::cmp::min(self.layers.len(), network.hidden_layers.len())));
+        }
+
+        for (index, (layer, neurons)) in self.layers.iter().zip(network.hidden_layers.iter()).enumerate() {
+            if layer.len() != neurons.len() || layer.iter().zip(neurons.iter()).any(|(g, n)| g.weights.len() != n.weights.len()) {
+                return Err(GradientError::ShapeMismatch(index));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets every entry to zero, keeping the shape
+    pub fn clear(&mut self) {
+        self.map(|_| F::zero());
+    }
+
+    /// Applies `f` to every entry
+    pub fn map<M: FnMut(F) -> F>(&mut self, mut f: M) {
+        for neuron in self.layers.iter_mut().flat_map(|layer| layer.iter_mut()) {
+            for w in neuron.weights.iter_mut() {
+                *w = f(*w);
+            }
+            neuron.bias = f(neuron.bias);
+        }
+    }
+
+    /// Multiplies every entry with `factor`
+    pub fn scale(&mut self, factor: F) {
+        self.map(|v| v * factor);
+    }
+
+    /// Adds another gradient of the same shape
+    pub fn add(&mut self, other: &Gradient<F>) {
+        for (a, b) in self.layers.iter_mut().flat_map(|layer| layer.iter_mut()).zip(other.layers.iter().flat_map(|layer| layer.iter())) {
+            for (wa, wb) in a.weights.iter_mut().zip(b.weights.iter()) {
+                *wa += *wb;
+            }
+            a.bias += b.bias;
+        }
+    }
+
+    /// Euclidean norm over all entries
+    pub fn norm(&self) -> F {
+        let mut sum = F::zero();
+        for neuron in self.layers.iter().flat_map(|layer| layer.iter()) {
+            for w in &neuron.weights {
+                sum += *w * *w;
+            }
+            sum += neuron.bias * neuron.bias;
+        }
+        sum.sqrt()
+    }
+
+    /// Amount of entries
+    pub fn len(&self) -> usize {
+        self.layers.iter().flat_map(|layer| layer.iter()).fold(0, |len, neuron| len + neuron.weights.len() + 1)
+    }
+}
+
+/// Values of every layer recorded during a forward pass, needed for backpropagation
+#[derive(Clone, Debug)]
+pub struct ForwardTrace<F: Float = f64> {
+    pub inputs: Vec<F>,
+
+    /// Weighted sums per layer
+    pub pre_activation: Vec<Vec<F>>,
+
+    /// Activated values per layer, the last entry are the network outputs
+    pub post_activation: Vec<Vec<F>>
+}
+
+impl<F: Float> ForwardTrace<F> {
+    /// Outputs of the network
+    pub fn outputs(&self) -> &[F] {
+        &self.post_activation[self.post_activation.len() - 1]
+    }
+}
+
+impl<F: Float> NeuralNetwork<F> {
+    /// Calculates the network neuron by neuron while recording every layer
+    pub fn forward_trace(&self, inputs: &[F]) -> Result<ForwardTrace<F>, GradientError> {
+        if inputs.len() != self.inputs {
+            return Err(GradientError::InputMismatch(inputs.len()));
+        }
+
+        let mut pre_activation: Vec<Vec<F>> = Vec::with_capacity(self.hidden_layers.len());
+        let mut post_activation: Vec<Vec<F>> = Vec::with_capacity(self.hidden_layers.len());
+
+        for (layer_index, layer) in self.hidden_layers.iter().enumerate() {
+            let (pre, post) = {
+                let source: &[F] = if layer_index == 0 { inputs } else { &post_activation[layer_index - 1] };
+
+                let mut pre = Vec::with_capacity(layer.len());
+                let mut post = Vec::with_capacity(layer.len());
+
+                for neuron in layer {
+                    if neuron.weights.len() != source.len() {
+                        return Err(GradientError::ShapeMismatch(layer_index));
+                    }
+
+                    let mut sum = neuron.bias;
+                    for (w, x) in neuron.weights.iter().zip(source.iter()) {
+                        sum += *w * *x;
+                    }
+
+                    pre.push(sum);
+                    post.push(neuron.neuron_type.activate(sum));
+                }
+
+                (pre, post)
+            };
+
+            pre_activation.push(pre);
+            post_activation.push(post);
+        }
+
+        Ok(ForwardTrace {
+            inputs: inputs.to_vec(),
+            pre_activation: pre_activation,
+            post_activation: post_activation
+        })
+    }
+
+    /// Backpropagates the gradient of the loss with respect to the outputs
+    ///
+    /// The gradients of all weights and biases are added to `gradient`, so several samples can
+    /// be accumulated. Returns the gradient with respect to the inputs.
+    pub fn backpropagate(&self, trace: &ForwardTrace<F>, output_gradient: &[F], gradient: &mut Gradient<F>) -> Result<Vec<F>, GradientError> {
+        if output_gradient.len() != trace.outputs().len() {
+            return Err(GradientError::OutputMismatch(output_gradient.len()));
+        }
+        try!(gradient.matches(self));
+
+        // gradient with respect to the activated values of the current layer
+        let mut upstream = output_gradient.to_vec();
+
+        for layer_index in (0..self.hidden_layers.len()).rev() {
+            let layer = &self.hidden_layers[layer_index];
+            let source: &[F] = if layer_index == 0 { &trace.inputs } else { &trace.post_activation[layer_index - 1] };
+            let mut downstream = vec![F::zero(); source.len()];
+
+            for (n, neuron) in layer.iter().enumerate() {
+                let pre = trace.pre_activation[layer_index][n];
+                let post = trace.post_activation[layer_index][n];
+                let delta = upstream[n] * neuron.neuron_type.derivative(pre, post);
+
+                let target = &mut gradient.layers[layer_index][n];
+                for i in 0..source.len() {
+                    target.weights[i] += delta * source[i];
+                    downstream[i] += delta * neuron.weights[i];
+                }
+                target.bias += delta;
+            }
+
+            upstream = downstream;
+        }
+
+        Ok(upstream)
+    }
+
+    /// Computes the loss and its gradient for a single sample
+    ///
+    /// `loss` receives the network outputs and a buffer for the gradient of the loss with
+    /// respect to those outputs, it returns the loss value.
+    pub fn gradient<L>(&self, inputs: &[F], loss: L) -> Result<(F, Gradient<F>), GradientError>
+        where L: FnOnce(&[F], &mut [F]) -> F
+    {
+        let trace = try!(self.forward_trace(inputs));
+
+        let mut output_gradient = vec![F::zero(); trace.outputs().len()];
+        let value = loss(trace.outputs(), &mut output_gradient);
+
+        let mut gradient = Gradient::zeros(self);
+        try!(self.backpropagate(&trace, &output_gradient, &mut gradient));
+
+        Ok((value, gradient))
+    }
+}
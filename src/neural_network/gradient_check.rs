@@ -0,0 +1,261 @@
+//! Checking backpropagation against central finite differences
+//!
+//! Random networks and inputs are generated from a seed like in `differential`. The loss is a
+//! random linear combination of the outputs, so every output receives some gradient.
+//!
+//! DeLu has no derivative at 0, so a parameter is skipped for a sample when one of its
+//! perturbations moves the weighted sum of any DeLu neuron to the other side of 0.
+
+use rand::{Rng, SeedableRng, StdRng};
+
+use neural_network::*;
+use neural_network::float::{Float, Precision};
+use neural_network::differential::random_network;
+use neural_network::gradient::ForwardTrace;
+
+pub struct GradientCheckOptions {
+    /// Amount of random networks
+    pub networks: usize,
+
+    /// Amount of random inputs per network
+    pub samples: usize,
+
+    /// Maximum amount of layers of a network (including the output layer)
+    pub max_layers: usize,
+
+    /// Maximum amount of inputs and neurons per layer
+    pub max_width: usize,
+
+    /// Step of the central differences, `None` uses `default_epsilon`
+    pub epsilon: Option<f64>,
+
+    /// Seed for networks and inputs
+    pub seed: usize
+}
+
+impl GradientCheckOptions {
+    pub fn defaults() -> Self {
+        GradientCheckOptions {
+            networks: 20,
+            samples: 3,
+            max_layers: 4,
+            max_width: 8,
+            epsilon: None,
+            seed: 1
+        }
+    }
+}
+
+/// Result for a single layer of a generated network
+#[derive(Clone, Debug)]
+pub struct LayerCheck {
+    /// Index of the generated network
+    pub network: usize,
+    pub layer: usize,
+    pub neuron_type: NeuronType,
+
+    /// Amount of weights and biases checked per sample
+    pub parameters: usize,
+
+    /// Amount of checks skipped over all samples because of the DeLu kink
+    pub skipped: usize,
+
+    pub max_relative_error: f64
+}
+
+#[derive(Clone, Debug)]
+pub struct GradientCheckReport {
+    pub tolerance: f64,
+    pub layers: Vec<LayerCheck>,
+
+    /// Largest error of all layers
+    pub max_relative_error: f64,
+
+    /// Amount of checks skipped because of the DeLu kink
+    pub skipped: usize,
+
+    /// Amount of layers exceeding the tolerance
+    pub failures: usize
+}
+
+impl GradientCheckReport {
+    pub fn passed(&self) -> bool {
+        self.failures == 0
+    }
+
+    /// Largest error per neuron type
+    pub fn max_error_by_type(&self) -> Vec<(NeuronType, f64)> {
+        let mut result: Vec<(NeuronType, f64)> = Vec::new();
+        for check in &self.layers {
+            match result.iter().position(|&(neuron_type, _)| neuron_type == check.neuron_type) {
+                Some(index) => result[index].1 = result[index].1.max(check.max_relative_error),
+                None => result.push((check.neuron_type, check.max_relative_error))
+            }
+        }
+        result
+    }
+}
+
+/// Tolerance backpropagation is expected to keep for the float type `F`
+///
+/// With `f32` the differences themselves are off by up to about 2% at `default_epsilon`.
+pub fn default_tolerance<F: Float>() -> f64 {
+    match F::precision() {
+        Precision::Double => 1e-5,
+        Precision::Single => 3e-2
+    }
+}
+
+/// Step of the central differences for the float type `F`
+///
+/// Smaller steps drown in the rounding of `f32`, larger ones in the curvature of the activations.
+pub fn default_epsilon<F: Float>() -> f64 {
+    match F::precision() {
+        Precision::Double => 1e-6,
+        Precision::Single => 1e-2
+    }
+}
+
+/// Relative error, gradients below 1e-3 are compared absolutely to ignore rounding noise
+pub fn relative_error(analytic: f64, numeric: f64) -> f64 {
+    (analytic - numeric).abs() / analytic.abs().max(numeric.abs()).max(1e-3)
+}
+
+fn projected_loss<F: Float>(network: &NeuralNetwork<F>, inputs: &[F], projection: &[f64]) -> (f64, ForwardTrace<F>) {
+    let trace = network.forward_trace(inputs).unwrap();
+    let loss = trace.outputs().iter().zip(projection.iter()).fold(0.0, |sum, (o, c)| sum + o.to_f64() * c);
+    (loss, trace)
+}
+
+/// Whether the weighted sum of a DeLu neuron lies on different sides of 0 in both traces
+fn crosses_kink<F: Float>(network: &NeuralNetwork<F>, a: &ForwardTrace<F>, b: &ForwardTrace<F>) -> bool {
+    network.layers().iter().enumerate().any(|(l, layer)| {
+        layer.iter().enumerate().any(|(n, neuron)| {
+            neuron.neuron_type() == NeuronType::DeLu && (a.pre_activation[l][n] > F::zero()) != (b.pre_activation[l][n] > F::zero())
+        })
+    })
+}
+
+/// Checks the gradient of every weight and bias of random networks
+pub fn check<F: Float>(tolerance: f64, options: &GradientCheckOptions) -> GradientCheckReport {
+    let seed: &[usize] = &[options.seed];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let epsilon = options.epsilon.unwrap_or_else(default_epsilon::<F>);
+
+    let mut report = GradientCheckReport {
+        tolerance: tolerance,
+        layers: Vec::new(),
+        max_relative_error: 0.0,
+        skipped: 0,
+        failures: 0
+    };
+
+    for network_index in 0..options.networks {
+        let network: NeuralNetwork<F> = random_network(&mut rng, options.max_layers, options.max_width);
+        let mut perturbed = network.clone();
+
+        let mut checks: Vec<LayerCheck> = network.layers().iter().enumerate().map(|(layer, neurons)| LayerCheck {
+            network: network_index,
+            layer: layer,
+            neuron_type: neurons[0].neuron_type(),
+            parameters: neurons.iter().fold(0, |len, neuron| len + neuron.weights().len() + 1),
+            skipped: 0,
+            max_relative_error: 0.0
+        }).collect();
+
+        for _ in 0..options.samples {
+            let inputs: Vec<F> = (0..network.inputs()).map(|_| F::from_f64(rng.gen_range(-2.0, 2.0))).collect();
+            let outputs = network.layers()[network.layers().len() - 1].len();
+            let projection: Vec<f64> = (0..outputs).map(|_| rng.gen_range(-1.0, 1.0)).collect();
+            let (_, trace) = projected_loss(&network, &inputs, &projection);
+
+            let (_, analytic) = network.gradient(&inputs, |_, gradient| {
+                for (g, c) in gradient.iter_mut().zip(projection.iter()) {
+                    *g = F::from_f64(*c);
+                }
+                F::zero()
+            }).unwrap();
+
+            for (l, check) in checks.iter_mut().enumerate() {
+                for n in 0..network.layers()[l].len() {
+                    let weights = network.layers()[l][n].weights().len();
+
+                    for i in 0..weights + 1 {
+                        let original = if i < weights { network.layers()[l][n].weights()[i] } else { network.layers()[l][n].bias() };
+                        let set = |perturbed: &mut NeuralNetwork<F>, value: F| {
+                            let neuron = &mut perturbed.layers_mut()[l][n];
+                            if i < weights { neuron.weights_mut()[i] = value; } else { *neuron.bias_mut() = value; }
+                        };
+
+                        // perturb the parameter in both directions and restore it, the step is
+                        // measured after rounding to `F`
+                        let (up, down) = (F::from_f64(original.to_f64() + epsilon), F::from_f64(original.to_f64() - epsilon));
+                        set(&mut perturbed, up);
+                        let (plus, plus_trace) = projected_loss(&perturbed, &inputs, &projection);
+                        set(&mut perturbed, down);
+                        let (minus, minus_trace) = projected_loss(&perturbed, &inputs, &projection);
+                        set(&mut perturbed, original);
+
+                        if crosses_kink(&network, &trace, &plus_trace) || crosses_kink(&network, &trace, &minus_trace) {
+                            check.skipped += 1;
+                            continue;
+                        }
+
+                        let numeric = (plus - minus) / (up.to_f64() - down.to_f64());
+                        let grad = &analytic.layers[l][n];
+                        let exact = if i < weights { grad.weights[i] } else { grad.bias };
+
+                        check.max_relative_error = check.max_relative_error.max(relative_error(exact.to_f64(), numeric));
+                    }
+                }
+            }
+        }
+
+        for check in checks {
+            report.skipped += check.skipped;
+            report.max_relative_error = report.max_relative_error.max(check.max_relative_error);
+            if !(check.max_relative_error <= tolerance) {
+                report.failures += 1;
+            }
+            report.layers.push(check);
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_seeds<F: Float>() {
+        for seed in 1..4 {
+            let mut options = GradientCheckOptions::defaults();
+            options.seed = seed;
+
+            let report = check::<F>(default_tolerance::<F>(), &options);
+            assert!(report.passed(), "seed {}: {:?}", seed, report.max_error_by_type());
+        }
+    }
+
+    #[test]
+    fn backpropagation_matches_differences_f64() {
+        check_seeds::<f64>();
+    }
+
+    #[test]
+    fn backpropagation_matches_differences_f32() {
+        check_seeds::<f32>();
+    }
+
+    #[test]
+    fn detects_delu_kink() {
+        let network: NeuralNetwork = NeuralNetwork::from_layers(1, vec![vec![Neuron::new(vec![1.0], 0.0, NeuronType::DeLu)]]);
+        let below = network.forward_trace(&[-1e-7]).unwrap();
+        let above = network.forward_trace(&[1e-7]).unwrap();
+        let further = network.forward_trace(&[1.0]).unwrap();
+
+        assert!(crosses_kink(&network, &below, &above));
+        assert!(!crosses_kink(&network, &above, &further));
+    }
+}
@@ -0,0 +1,120 @@
+//! Inspecting and modifying layer values during a forward pass
+//!
+//! Hooks are resolved at compile time, running without a hook (`NoHook`) compiles down to
+//! the plain forward pass.
+
+use neural_network::float::Float;
+
+/// Receives the values of every layer during a forward pass
+pub trait ForwardHook<F: Float = f64> {
+    /// Called with the weighted sums of a layer, before its activation is applied
+    #[inline]
+    fn pre_activation(&mut self, _layer: usize, _values: &mut [F]) {
+    }
+
+    /// Called with the activated values of a layer, before they are passed on
+    #[inline]
+    fn post_activation(&mut self, _layer: usize, _values: &mut [F]) {
+    }
+}
+
+/// Hook that does nothing
+#[derive(Copy, Clone, Debug)]
+pub struct NoHook;
+
+impl<F: Float> ForwardHook<F> for NoHook {
+}
+
+impl<'a, F: Float, H: ForwardHook<F>> ForwardHook<F> for &'a mut H {
+    #[inline]
+    fn pre_activation(&mut self, layer: usize, values: &mut [F]) {
+        (**self).pre_activation(layer, values);
+    }
+
+    #[inline]
+    fn post_activation(&mut self, layer: usize, values: &mut [F]) {
+        (**self).post_activation(layer, values);
+    }
+}
+
+/// Runs two hooks one after another
+impl<F: Float, A: ForwardHook<F>, B: ForwardHook<F>> ForwardHook<F> for (A, B) {
+    #[inline]
+    fn pre_activation(&mut self, layer: usize, values: &mut [F]) {
+        self.0.pre_activation(layer, values);
+        self.1.pre_activation(layer, values);
+    }
+
+    #[inline]
+    fn post_activation(&mut self, layer: usize, values: &mut [F]) {
+        self.0.post_activation(layer, values);
+        self.1.post_activation(layer, values);
+    }
+}
+
+/// Point of a layer a hook is called at
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stage {
+    PreActivation,
+    PostActivation
+}
+
+/// Turns a closure `(layer_index, stage, values)` into a hook
+pub struct FnHook<T>(pub T);
+
+impl<F: Float, T: FnMut(usize, Stage, &mut [F])> ForwardHook<F> for FnHook<T> {
+    #[inline]
+    fn pre_activation(&mut self, layer: usize, values: &mut [F]) {
+        (self.0)(layer, Stage::PreActivation, values);
+    }
+
+    #[inline]
+    fn post_activation(&mut self, layer: usize, values: &mut [F]) {
+        (self.0)(layer, Stage::PostActivation, values);
+    }
+}
+
+/// Records the values of every layer
+#[derive(Clone, Debug)]
+pub struct ActivationCapture<F: Float = f64> {
+    /// Weighted sums per layer
+    pub pre_activation: Vec<Vec<F>>,
+
+    /// Activated values per layer, the last entry equals the network output
+    pub post_activation: Vec<Vec<F>>
+}
+
+impl<F: Float> ActivationCapture<F> {
+    pub fn new() -> Self {
+        ActivationCapture {
+            pre_activation: Vec::new(),
+            post_activation: Vec::new()
+        }
+    }
+
+    /// Forgets all recorded values, keeping allocations for the next pass
+    pub fn clear(&mut self) {
+        for values in self.pre_activation.iter_mut().chain(self.post_activation.iter_mut()) {
+            values.clear();
+        }
+    }
+}
+
+fn record<F: Float>(target: &mut Vec<Vec<F>>, layer: usize, values: &[F]) {
+    while target.len() <= layer {
+        target.push(Vec::new());
+    }
+
+    target[layer].clear();
+    target[layer].extend_from_slice(values);
+}
+
+impl<F: Float> ForwardHook<F> for ActivationCapture<F> {
+    fn pre_activation(&mut self, layer: usize, values: &mut [F]) {
+        record(&mut self.pre_activation, layer, values);
+    }
+
+    fn post_activation(&mut self, layer: usize, values: &mut [F]) {
+        record(&mut self.post_activation, layer, values);
+    }
+}
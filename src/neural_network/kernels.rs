@@ -0,0 +1,101 @@
+//! Dense kernels used by compiled execution plans
+//!
+//! All matrices are stored row-major in one contiguous slice. Inner products are delegated
+//! to the dot-product kernel the `simd` module selected for the float type.
+
+use neural_network::float::Float;
+
+/// Amount of columns processed per block, sized so a block of the input vector stays in L1
+const BLOCK_COLS: usize = 512;
+
+/// Amount of weight rows processed per block in matrix-matrix products
+const BLOCK_ROWS: usize = 32;
+
+/// Amount of batch rows processed per block in matrix-matrix products
+const BLOCK_BATCH: usize = 16;
+
+/// Dot product of two equally sized slices
+#[inline]
+pub fn dot<F: Float>(a: &[F], b: &[F]) -> F {
+    debug_assert_eq!(a.len(), b.len());
+    F::dot_kernel()(a, b)
+}
+
+/// Matrix-vector product `y = W * x + bias`
+///
+/// `weights` is a `rows x cols` matrix, `x` has `cols` and `bias` and `y` have `rows` entries.
+pub fn gemv<F: Float>(weights: &[F], rows: usize, cols: usize, x: &[F], bias: &[F], y: &mut [F]) {
+    gemv_with(F::dot_kernel(), weights, rows, cols, x, bias, y);
+}
+
+/// Matrix-vector product using the given dot-product kernel
+pub fn gemv_with<F: Float>(dot: fn(&[F], &[F]) -> F, weights: &[F], rows: usize, cols: usize, x: &[F], bias: &[F], y: &mut [F]) {
+    debug_assert_eq!(weights.len(), rows * cols);
+    debug_assert_eq!(x.len(), cols);
+    debug_assert_eq!(bias.len(), rows);
+    debug_assert_eq!(y.len(), rows);
+
+    y.copy_from_slice(bias);
+
+    let mut col = 0;
+    while col < cols {
+        let end = ::std::cmp::min(col + BLOCK_COLS, cols);
+        let x_block = &x[col..end];
+
+        for row in 0..rows {
+            let offset = row * cols;
+            y[row] += dot(&weights[offset + col..offset + end], x_block);
+        }
+
+        col = end;
+    }
+}
+
+/// Matrix-matrix product `Y = X * W^T + bias`
+///
+/// `x` holds `batch` rows of `cols` inputs, `weights` is a `rows x cols` matrix and
+/// `y` receives `batch` rows of `rows` outputs. The bias is added to every output row.
+pub fn gemm<F: Float>(weights: &[F], rows: usize, cols: usize, x: &[F], batch: usize, bias: &[F], y: &mut [F]) {
+    gemm_with(F::dot_kernel(), weights, rows, cols, x, batch, bias, y);
+}
+
+/// Matrix-matrix product using the given dot-product kernel
+pub fn gemm_with<F: Float>(dot: fn(&[F], &[F]) -> F, weights: &[F], rows: usize, cols: usize, x: &[F], batch: usize, bias: &[F], y: &mut [F]) {
+    debug_assert_eq!(weights.len(), rows * cols);
+    debug_assert_eq!(x.len(), batch * cols);
+    debug_assert_eq!(bias.len(), rows);
+    debug_assert_eq!(y.len(), batch * rows);
+
+    for b in 0..batch {
+        y[b * rows..(b + 1) * rows].copy_from_slice(bias);
+    }
+
+    let mut col = 0;
+    while col < cols {
+        let col_end = ::std::cmp::min(col + BLOCK_COLS, cols);
+
+        let mut row = 0;
+        while row < rows {
+            let row_end = ::std::cmp::min(row + BLOCK_ROWS, rows);
+
+            let mut b = 0;
+            while b < batch {
+                let b_end = ::std::cmp::min(b + BLOCK_BATCH, batch);
+
+                // the weight block is reused for every sample of the batch block
+                for i in b..b_end {
+                    let x_row = &x[i * cols + col..i * cols + col_end];
+                    for r in row..row_end {
+                        y[i * rows + r] += dot(&weights[r * cols + col..r * cols + col_end], x_row);
+                    }
+                }
+
+                b = b_end;
+            }
+
+            row = row_end;
+        }
+
+        col = col_end;
+    }
+}
@@ -4,7 +4,23 @@ use rustc_serialize::{Encodable, Decodable, Encoder, Decoder};
 
 use evolution::*;
 
+pub mod boxed;
 pub mod cpu;
+pub mod differential;
+pub mod ensemble;
+pub mod float;
+pub mod gradient;
+pub mod gradient_check;
+pub mod hooks;
+pub mod kernels;
+pub mod plan;
+pub mod quantized;
+pub mod reference;
+pub mod simd;
+pub mod storage;
+
+use self::float::Float;
+use self::plan::{ExecutionPlan, PlanError};
 
 struct RngWrapper(OsRng);
 
@@ -28,16 +44,16 @@ impl Clone for RngWrapper {
 
 /// Structure that describes a neural network
 #[derive(RustcEncodable, RustcDecodable, Clone)]
-pub struct NeuralNetwork {
+pub struct NeuralNetwork<F: Float = f64> {
     inputs: usize,
 
-    hidden_layers: Vec<Vec<Neuron>>,
+    hidden_layers: Vec<Vec<Neuron<F>>>,
     neuron_count: usize,
 
     random_generator: RngWrapper
 }
 
-impl Evolvable for NeuralNetwork {
+impl<F: Float> Evolvable for NeuralNetwork<F> {
     fn cross_over(&self, other: &Self) -> Self {
         self.clone()
     }
@@ -51,62 +67,134 @@ impl Evolvable for NeuralNetwork {
 
         // find random neuron to adjust bias
         {
-            let mut randomLayer: &mut Vec<Neuron> = &mut self.hidden_layers[randomIndex];
+            let mut randomLayer: &mut Vec<Neuron<F>> = &mut self.hidden_layers[randomIndex];
             let maxLen = randomLayer.len();
-            let mut randomNeuron: &mut Neuron = &mut randomLayer[self.random_generator.0.gen_range(0, maxLen - 1)];
+            let mut randomNeuron: &mut Neuron<F> = &mut randomLayer[self.random_generator.0.gen_range(0, maxLen - 1)];
 
             // randomize its bias
-            randomNeuron.bias = self.random_generator.0.gen_range(-1.0, 1.0);
+            randomNeuron.bias = F::from_f64(self.random_generator.0.gen_range(-1.0, 1.0));
         }
 
         // find random connection to adjust weight
-        let mut randomLayer2: &mut Vec<Neuron> = &mut self.hidden_layers[randomIndex2];
+        let mut randomLayer2: &mut Vec<Neuron<F>> = &mut self.hidden_layers[randomIndex2];
         let maxLen2 = randomLayer2.len();
-        let mut randomNeuron2: &mut Neuron = &mut randomLayer2[self.random_generator.0.gen_range(0, maxLen2 - 1)];
+        let mut randomNeuron2: &mut Neuron<F> = &mut randomLayer2[self.random_generator.0.gen_range(0, maxLen2 - 1)];
         let mut randomWeightIndex = self.random_generator.0.gen_range(0, randomNeuron2.weights.len() - 1);
 
         // randmoize its weight
-        randomNeuron2.weights[randomWeightIndex] = self.random_generator.0.gen_range(-1.0, 1.0);
+        randomNeuron2.weights[randomWeightIndex] = F::from_f64(self.random_generator.0.gen_range(-1.0, 1.0));
     }
 
 }
 
 /// Describes an neuron type
-#[derive(RustcEncodable, RustcDecodable, Copy, Clone, Debug)]
+#[derive(RustcEncodable, RustcDecodable, Copy, Clone, Debug, PartialEq)]
 pub enum NeuronType {
     Identity,
     SigMoid,
     TanH,
+    /// Rectified linear unit
     DeLu,
 }
 
+impl NeuronType {
+    /// Applies the activation function to a single value
+    #[inline]
+    pub fn activate<F: Float>(&self, x: F) -> F {
+        match *self {
+            NeuronType::Identity => x,
+            NeuronType::SigMoid => F::one() / (F::one() + (-x).exp()),
+            NeuronType::TanH => x.tanh(),
+            NeuronType::DeLu => if x > F::zero() { x } else { F::zero() },
+        }
+    }
+
+    /// Applies the activation function over an set of values
+    pub fn activate_all<F: Float>(&self, values: &mut [F]) {
+        if let NeuronType::Identity = *self {
+            return;
+        }
+
+        for value in values.iter_mut() {
+            *value = self.activate(*value);
+        }
+    }
+}
+
 /// A neuron
 #[derive(RustcEncodable, RustcDecodable, Clone)]
-pub struct Neuron {
-    weights: Vec<f64>,
-    bias: f64,
-    //neuron_type: NeuronType
+pub struct Neuron<F: Float = f64> {
+    weights: Vec<F>,
+    bias: F,
+    neuron_type: NeuronType
+}
+
+impl<F: Float> Neuron<F> {
+    /// Creates a neuron from its weights and bias
+    pub fn new(weights: Vec<F>, bias: F, neuron_type: NeuronType) -> Self {
+        Neuron {
+            weights: weights,
+            bias: bias,
+            neuron_type: neuron_type
+        }
+    }
+
+    /// Incoming weights, one per neuron of the previous layer
+    pub fn weights(&self) -> &[F] {
+        &self.weights
+    }
+
+    /// Mutable incoming weights
+    pub fn weights_mut(&mut self) -> &mut [F] {
+        &mut self.weights
+    }
+
+    /// Bias added before activation
+    pub fn bias(&self) -> F {
+        self.bias
+    }
+
+    /// Mutable bias
+    pub fn bias_mut(&mut self) -> &mut F {
+        &mut self.bias
+    }
+
+    /// Activation function of this neuron
+    pub fn neuron_type(&self) -> NeuronType {
+        self.neuron_type
+    }
 }
 
 /// Trait for neural network instances
-pub trait Instance<'a, Err>: Sized {
+///
+/// The trait is object safe, see `boxed::BoxedInstance` for choosing a backend at runtime.
+pub trait Instance<'a, F: Float = f64> {
+    /// Error the backend reports
+    type Error;
+
     /// Creates a new instance by a given neural network
-    fn new(nn: &'a NeuralNetwork) -> Result<Self, Err>;
+    fn new(nn: &'a NeuralNetwork<F>) -> Result<Self, Self::Error> where Self: Sized;
+
+    /// Amount of inputs `calculate` expects
+    fn input_len(&self) -> usize;
+
+    /// Amount of outputs `calculate` writes
+    fn output_len(&self) -> usize;
 
-    /// Calulates using the neural network by given inputs
-    fn calculate(&mut self, inputs: &Vec<f64>, outputs: &mut Vec<f64>) -> Result<(), Err>;
+    /// Calulates using the neural network by given inputs, `outputs` must hold `output_len()` values
+    fn calculate(&mut self, inputs: &[F], outputs: &mut [F]) -> Result<(), Self::Error>;
 }
 
 /// Iterator that iterates through all neurons within a neural network
-pub struct NeuronIterator<'a> {
-    network: &'a NeuralNetwork,
+pub struct NeuronIterator<'a, F: Float + 'a = f64> {
+    network: &'a NeuralNetwork<F>,
     current_layer: usize,
     current_neuron: usize
 }
 
-impl<'a> Iterator for NeuronIterator<'a> {
+impl<'a, F: Float> Iterator for NeuronIterator<'a, F> {
     /// Layer index, Reference to neuron
-    type Item = (usize, &'a Neuron);
+    type Item = (usize, &'a Neuron<F>);
 
     fn next(&mut self) -> Option<Self::Item> {
 
@@ -129,7 +217,7 @@ impl<'a> Iterator for NeuronIterator<'a> {
     }
 }
 
-impl NeuralNetwork {
+impl<F: Float> NeuralNetwork<F> {
     /// Creates a new instance of an neural network
     pub fn new() -> Self {
         NeuralNetwork {
@@ -140,8 +228,20 @@ impl NeuralNetwork {
         }
     }
 
+    /// Creates a neural network from already initialized layers
+    pub fn from_layers(inputs: usize, layers: Vec<Vec<Neuron<F>>>) -> Self {
+        let neuron_count = layers.iter().fold(0, |count, layer| count + layer.len());
+
+        NeuralNetwork {
+            inputs: inputs,
+            hidden_layers: layers,
+            neuron_count: neuron_count,
+            random_generator: RngWrapper(OsRng::new().unwrap())
+        }
+    }
+
     /// Creates an iterator over all neurons
-    pub fn iter(&self) -> NeuronIterator {
+    pub fn iter(&self) -> NeuronIterator<F> {
         NeuronIterator {
             network: self,
             current_layer: 0,
@@ -178,24 +278,63 @@ impl NeuralNetwork {
 
         // create neurons
         for _ in 0..amount {
-            let mut weights: Vec<f64> = Vec::new();
+            let mut weights: Vec<F> = Vec::new();
 
             for _ in 0..weightsAmount {
-                weights.push(self.random(min, max));
+                weights.push(F::from_f64(self.random(min, max)));
             }
 
             // save neurons
             let neuron = Neuron {
                 weights: weights.clone(),
-                bias: self.random(min, max),
-                //neuron_type: neuron_type
+                bias: F::from_f64(self.random(min, max)),
+                neuron_type: neuron_type
             };
 
             self.hidden_layers[layer_index].push(neuron);
         }
     }
 
-    /// Finalizes the neural networks strcuture
-    pub fn build(&self) {
+    /// Amount of inputs the nn expects
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    /// Hidden layers including the output layer
+    pub fn layers(&self) -> &Vec<Vec<Neuron<F>>> {
+        &self.hidden_layers
+    }
+
+    /// Mutable access to the layers, used by optimizers
+    pub fn layers_mut(&mut self) -> &mut [Vec<Neuron<F>>] {
+        &mut self.hidden_layers
+    }
+
+    /// Finalizes the neural networks strcuture by compiling it into an execution plan
+    pub fn build(&self) -> Result<ExecutionPlan<F>, PlanError> {
+        ExecutionPlan::compile(self)
+    }
+
+    /// Precision the network is stored in
+    pub fn precision(&self) -> float::Precision {
+        F::precision()
+    }
+
+    /// Converts the network into another floating point precision
+    pub fn cast<G: Float>(&self) -> NeuralNetwork<G> {
+        let layers = self.hidden_layers.iter().map(|layer| {
+            layer.iter().map(|neuron| Neuron {
+                weights: neuron.weights.iter().map(|w| G::from_f64(w.to_f64())).collect(),
+                bias: G::from_f64(neuron.bias.to_f64()),
+                neuron_type: neuron.neuron_type
+            }).collect()
+        }).collect();
+
+        NeuralNetwork {
+            inputs: self.inputs,
+            hidden_layers: layers,
+            neuron_count: self.neuron_count,
+            random_generator: self.random_generator.clone()
+        }
     }
 }
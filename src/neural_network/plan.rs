@@ -0,0 +1,213 @@
+use std::error::Error;
+use std::fmt;
+
+use neural_network::*;
+use neural_network::kernels;
+use neural_network::float::Float;
+use neural_network::hooks::{ForwardHook, NoHook};
+
+/// Errors that can occur while compiling a neural network
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlanError {
+    /// The network has no inputs or no layers
+    EmptyNetwork,
+
+    /// Amount of weights of a neuron does not match the width of the previous layer
+    ///  (layer_number)
+    ShapeMismatch(usize),
+
+    /// Neurons within one layer use different activation functions
+    ///  (layer_number)
+    ActivationMix(usize)
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PlanError::EmptyNetwork => write!(f, "network has no inputs or an empty layer"),
+            PlanError::ShapeMismatch(layer) => write!(f, "weights of layer {} do not match the previous layer", layer),
+            PlanError::ActivationMix(layer) => write!(f, "layer {} mixes activation functions", layer)
+        }
+    }
+}
+
+impl Error for PlanError {
+    fn description(&self) -> &str {
+        "invalid network structure"
+    }
+}
+
+/// Compiled representation of a single layer
+#[derive(Clone, Debug)]
+pub struct LayerPlan<F: Float = f64> {
+    inputs: usize,
+    outputs: usize,
+
+    /// Row-major `outputs x inputs` matrix
+    weights: Vec<F>,
+    biases: Vec<F>,
+
+    /// Activation fused into the layer
+    activation: NeuronType
+}
+
+impl<F: Float> LayerPlan<F> {
+    /// Amount of values the layer consumes
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    /// Amount of values the layer produces
+    pub fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    /// Row-major weight matrix, one row per neuron
+    pub fn weights(&self) -> &[F] {
+        &self.weights
+    }
+
+    /// Bias vector, one entry per neuron
+    pub fn biases(&self) -> &[F] {
+        &self.biases
+    }
+
+    /// Activation applied after the weighted sum
+    pub fn activation(&self) -> NeuronType {
+        self.activation
+    }
+
+    /// Calculates the layer for a single sample
+    pub fn forward(&self, input: &[F], output: &mut [F]) {
+        self.forward_batch_with(0, input, 1, output, &mut NoHook);
+    }
+
+    /// Calculates the layer for `batch` samples stored row after row
+    pub fn forward_batch(&self, input: &[F], batch: usize, output: &mut [F]) {
+        self.forward_batch_with(0, input, batch, output, &mut NoHook);
+    }
+
+    /// Calculates the layer and passes its values to the hook as layer number `index`
+    pub fn forward_batch_with<H: ForwardHook<F>>(&self, index: usize, input: &[F], batch: usize, output: &mut [F], hook: &mut H) {
+        if batch == 1 {
+            kernels::gemv(&self.weights, self.outputs, self.inputs, input, &self.biases, output);
+        } else {
+            kernels::gemm(&self.weights, self.outputs, self.inputs, input, batch, &self.biases, output);
+        }
+
+        hook.pre_activation(index, output);
+        self.activation.activate_all(output);
+        hook.post_activation(index, output);
+    }
+}
+
+/// Neural network compiled into contiguous per-layer matrices
+#[derive(Clone, Debug)]
+pub struct ExecutionPlan<F: Float = f64> {
+    inputs: usize,
+    layers: Vec<LayerPlan<F>>
+}
+
+impl<F: Float> ExecutionPlan<F> {
+    /// Compiles a neural network into an execution plan
+    pub fn compile(network: &NeuralNetwork<F>) -> Result<Self, PlanError> {
+        if network.inputs == 0 || network.hidden_layers.is_empty() {
+            return Err(PlanError::EmptyNetwork);
+        }
+
+        let mut layers = Vec::with_capacity(network.hidden_layers.len());
+        let mut width = network.inputs;
+
+        for (layer_index, layer) in network.hidden_layers.iter().enumerate() {
+            if layer.is_empty() {
+                return Err(PlanError::EmptyNetwork);
+            }
+
+            let activation = layer[0].neuron_type;
+            let mut weights = Vec::with_capacity(layer.len() * width);
+            let mut biases = Vec::with_capacity(layer.len());
+
+            for neuron in layer {
+                if neuron.weights.len() != width {
+                    return Err(PlanError::ShapeMismatch(layer_index));
+                }
+                if neuron.neuron_type != activation {
+                    return Err(PlanError::ActivationMix(layer_index));
+                }
+
+                weights.extend_from_slice(&neuron.weights);
+                biases.push(neuron.bias);
+            }
+
+            layers.push(LayerPlan {
+                inputs: width,
+                outputs: layer.len(),
+                weights: weights,
+                biases: biases,
+                activation: activation
+            });
+
+            width = layer.len();
+        }
+
+        Ok(ExecutionPlan {
+            inputs: network.inputs,
+            layers: layers
+        })
+    }
+
+    /// Amount of inputs the plan expects
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    /// Amount of outputs the plan produces
+    pub fn outputs(&self) -> usize {
+        self.layers[self.layers.len() - 1].outputs
+    }
+
+    /// Compiled layers
+    pub fn layers(&self) -> &[LayerPlan<F>] {
+        &self.layers
+    }
+
+    /// Widest layer, used to size scratch buffers
+    pub fn max_width(&self) -> usize {
+        self.layers.iter().fold(self.inputs, |width, layer| ::std::cmp::max(width, layer.outputs))
+    }
+
+    /// Runs the plan for a single sample, `scratch` is resized as needed
+    pub fn execute(&self, input: &[F], scratch: &mut (Vec<F>, Vec<F>), output: &mut [F]) {
+        self.execute_with(input, 1, scratch, output, &mut NoHook);
+    }
+
+    /// Runs the plan for `batch` samples stored row after row
+    pub fn execute_batch(&self, input: &[F], batch: usize, scratch: &mut (Vec<F>, Vec<F>), output: &mut [F]) {
+        self.execute_with(input, batch, scratch, output, &mut NoHook);
+    }
+
+    /// Runs the plan for `batch` samples, passing the values of every layer to the hook
+    ///
+    /// For batches the hook receives the values of all samples, row after row.
+    pub fn execute_with<H: ForwardHook<F>>(&self, input: &[F], batch: usize, scratch: &mut (Vec<F>, Vec<F>), output: &mut [F], hook: &mut H) {
+        let size = self.max_width() * batch;
+        scratch.0.resize(size, F::zero());
+        scratch.1.resize(size, F::zero());
+
+        let (ref mut front, ref mut back) = *scratch;
+        let last = self.layers.len() - 1;
+
+        for (index, layer) in self.layers.iter().enumerate() {
+            let out_len = layer.outputs * batch;
+
+            {
+                let source: &[F] = if index == 0 { input } else { &front[..layer.inputs * batch] };
+                let target: &mut [F] = if index == last { &mut *output } else { &mut back[..out_len] };
+
+                layer.forward_batch_with(index, source, batch, target, hook);
+            }
+
+            ::std::mem::swap(front, back);
+        }
+    }
+}
@@ -0,0 +1,545 @@
+//! Int8 quantized inference
+//!
+//! Weights are stored as symmetric int8 values with a scale per layer or per output channel.
+//! Layer inputs are quantized to int8 as well, using scales found by calibrating over a sample
+//! dataset. Without calibration the input scales are computed dynamically for every sample.
+
+use std::error::Error;
+use std::fmt;
+
+use neural_network::*;
+use neural_network::float::Float;
+use neural_network::plan::{ExecutionPlan, LayerPlan, PlanError};
+
+/// How weight scales are shared
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Granularity {
+    /// One scale for all weights of a layer
+    PerLayer,
+    /// One scale per neuron (output channel)
+    PerChannel
+}
+
+/// Errors for QuantizedInstance
+#[derive(Debug)]
+pub enum QuantizedInstanceError {
+    /// The network could not be compiled
+    InvalidNetwork(PlanError),
+
+    /// Calibration requires at least one sample
+    EmptyCalibration,
+
+    /// Amount of given inputs does not match the network
+    ///  (amount_of_inputs)
+    InputMismatch(usize),
+
+    /// Size of the given output buffer does not match the network
+    ///  (size_of_buffer)
+    OutputMismatch(usize)
+}
+
+impl fmt::Display for QuantizedInstanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QuantizedInstanceError::InvalidNetwork(ref err) => write!(f, "invalid network: {}", err),
+            QuantizedInstanceError::EmptyCalibration => write!(f, "calibration requires at least one sample"),
+            QuantizedInstanceError::InputMismatch(len) => write!(f, "got {} inputs", len),
+            QuantizedInstanceError::OutputMismatch(len) => write!(f, "output buffer holds {} values", len)
+        }
+    }
+}
+
+impl Error for QuantizedInstanceError {
+    fn description(&self) -> &str {
+        "quantized instance error"
+    }
+}
+
+impl From<PlanError> for QuantizedInstanceError {
+    fn from(err: PlanError) -> Self {
+        QuantizedInstanceError::InvalidNetwork(err)
+    }
+}
+
+/// A single int8 layer
+#[derive(Clone, Debug)]
+pub struct QuantizedLayer {
+    inputs: usize,
+    outputs: usize,
+
+    /// Row-major `outputs x inputs` matrix
+    weights: Vec<i8>,
+
+    /// One entry for PerLayer, `outputs` entries for PerChannel
+    weight_scales: Vec<f64>,
+
+    biases: Vec<f64>,
+    activation: NeuronType,
+
+    /// Scale of the layer input found by calibration
+    input_scale: Option<f64>
+}
+
+impl QuantizedLayer {
+    fn from_plan<F: Float>(layer: &LayerPlan<F>, granularity: Granularity) -> Self {
+        let (inputs, outputs) = (layer.inputs(), layer.outputs());
+        let weights = layer.weights();
+
+        let weight_scales: Vec<f64> = match granularity {
+            Granularity::PerLayer => vec![scale_of(weights.iter().map(|w| w.to_f64()))],
+            Granularity::PerChannel => (0..outputs).map(|row| {
+                scale_of(weights[row * inputs..(row + 1) * inputs].iter().map(|w| w.to_f64()))
+            }).collect()
+        };
+
+        let mut quantized = Vec::with_capacity(weights.len());
+        for row in 0..outputs {
+            let scale = weight_scales[if weight_scales.len() == 1 { 0 } else { row }];
+            for w in &weights[row * inputs..(row + 1) * inputs] {
+                quantized.push(quantize(w.to_f64(), scale));
+            }
+        }
+
+        QuantizedLayer {
+            inputs: inputs,
+            outputs: outputs,
+            weights: quantized,
+            weight_scales: weight_scales,
+            biases: layer.biases().iter().map(|b| b.to_f64()).collect(),
+            activation: layer.activation(),
+            input_scale: None
+        }
+    }
+
+    /// Weight scale of the given neuron
+    pub fn weight_scale(&self, neuron: usize) -> f64 {
+        self.weight_scales[if self.weight_scales.len() == 1 { 0 } else { neuron }]
+    }
+
+    /// Calibrated input scale, None when scales are computed per sample
+    pub fn input_scale(&self) -> Option<f64> {
+        self.input_scale
+    }
+
+    fn forward(&self, input: &[f64], quantized_input: &mut Vec<i8>, output: &mut [f64]) {
+        let input_scale = match self.input_scale {
+            Some(scale) => scale,
+            None => scale_of(input.iter().cloned())
+        };
+
+        quantized_input.clear();
+        for x in input {
+            quantized_input.push(quantize(*x, input_scale));
+        }
+
+        for row in 0..self.outputs {
+            let weights = &self.weights[row * self.inputs..(row + 1) * self.inputs];
+
+            let mut acc: i32 = 0;
+            for i in 0..self.inputs {
+                acc += weights[i] as i32 * quantized_input[i] as i32;
+            }
+
+            let value = acc as f64 * input_scale * self.weight_scale(row) + self.biases[row];
+            output[row] = self.activation.activate(value);
+        }
+    }
+}
+
+/// Neural network with int8 weights
+#[derive(Clone, Debug)]
+pub struct QuantizedNetwork {
+    inputs: usize,
+    granularity: Granularity,
+    layers: Vec<QuantizedLayer>
+}
+
+impl QuantizedNetwork {
+    /// Quantizes the weights of a network, input scales are computed dynamically
+    pub fn quantize<F: Float>(network: &NeuralNetwork<F>, granularity: Granularity) -> Result<Self, QuantizedInstanceError> {
+        let plan = try!(network.build());
+        Ok(QuantizedNetwork::from_plan(&plan, granularity))
+    }
+
+    fn from_plan<F: Float>(plan: &ExecutionPlan<F>, granularity: Granularity) -> Self {
+        QuantizedNetwork {
+            inputs: plan.inputs(),
+            granularity: granularity,
+            layers: plan.layers().iter().map(|layer| QuantizedLayer::from_plan(layer, granularity)).collect()
+        }
+    }
+
+    /// Quantizes a network and calibrates the input scale of every layer over a sample dataset
+    ///
+    /// The float network is run over all samples and the largest absolute input of each layer
+    /// is mapped onto the int8 range.
+    pub fn calibrate<F: Float>(network: &NeuralNetwork<F>, samples: &[Vec<F>], granularity: Granularity) -> Result<Self, QuantizedInstanceError> {
+        if samples.is_empty() {
+            return Err(QuantizedInstanceError::EmptyCalibration);
+        }
+
+        let plan = try!(network.build());
+        let mut quantized = QuantizedNetwork::from_plan(&plan, granularity);
+
+        let mut ranges = vec![0.0f64; plan.layers().len()];
+        let mut current: Vec<F> = Vec::new();
+        let mut next: Vec<F> = Vec::new();
+
+        for sample in samples {
+            if sample.len() != plan.inputs() {
+                return Err(QuantizedInstanceError::InputMismatch(sample.len()));
+            }
+
+            current.clear();
+            current.extend_from_slice(sample);
+
+            for (index, layer) in plan.layers().iter().enumerate() {
+                for x in &current {
+                    ranges[index] = ranges[index].max(x.to_f64().abs());
+                }
+
+                next.clear();
+                next.resize(layer.outputs(), F::zero());
+                layer.forward(&current, &mut next);
+                ::std::mem::swap(&mut current, &mut next);
+            }
+        }
+
+        for (layer, range) in quantized.layers.iter_mut().zip(ranges) {
+            layer.input_scale = Some(if range > 0.0 { range / 127.0 } else { 1.0 });
+        }
+
+        Ok(quantized)
+    }
+
+    /// Amount of inputs the network expects
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    /// Amount of outputs the network produces
+    pub fn outputs(&self) -> usize {
+        self.layers[self.layers.len() - 1].outputs
+    }
+
+    /// Granularity the weights have been quantized with
+    pub fn granularity(&self) -> Granularity {
+        self.granularity
+    }
+
+    /// Quantized layers
+    pub fn layers(&self) -> &[QuantizedLayer] {
+        &self.layers
+    }
+
+    /// Whether the input scales have been calibrated
+    pub fn is_calibrated(&self) -> bool {
+        self.layers.iter().all(|layer| layer.input_scale.is_some())
+    }
+}
+
+/// Instance that calculates using int8 weights and activations
+pub struct QuantizedInstance {
+    network: QuantizedNetwork,
+
+    /// Buffers for intermediate layer values
+    current: Vec<f64>,
+    next: Vec<f64>,
+    quantized_input: Vec<i8>
+}
+
+impl QuantizedInstance {
+    /// Creates an instance from an already quantized (and possibly calibrated) network
+    pub fn from_quantized(network: QuantizedNetwork) -> Self {
+        QuantizedInstance {
+            network: network,
+            current: Vec::new(),
+            next: Vec::new(),
+            quantized_input: Vec::new()
+        }
+    }
+
+    /// Quantized network used for inference
+    pub fn network(&self) -> &QuantizedNetwork {
+        &self.network
+    }
+}
+
+impl<'a, F: Float> Instance<'a, F> for QuantizedInstance {
+    type Error = QuantizedInstanceError;
+
+    /// Quantizes per channel without calibration, use `from_quantized` for calibrated networks
+    fn new(network: &'a NeuralNetwork<F>) -> Result<Self, QuantizedInstanceError> {
+        let quantized = try!(QuantizedNetwork::quantize(network, Granularity::PerChannel));
+        Ok(QuantizedInstance::from_quantized(quantized))
+    }
+
+    fn input_len(&self) -> usize {
+        self.network.inputs()
+    }
+
+    fn output_len(&self) -> usize {
+        self.network.outputs()
+    }
+
+    fn calculate(&mut self, inputs: &[F], outputs: &mut [F]) -> Result<(), QuantizedInstanceError> {
+        if inputs.len() != self.network.inputs {
+            return Err(QuantizedInstanceError::InputMismatch(inputs.len()));
+        }
+        if outputs.len() != self.network.outputs() {
+            return Err(QuantizedInstanceError::OutputMismatch(outputs.len()));
+        }
+
+        self.current.clear();
+        self.current.extend(inputs.iter().map(|x| x.to_f64()));
+
+        for layer in &self.network.layers {
+            self.next.clear();
+            self.next.resize(layer.outputs, 0.0);
+            layer.forward(&self.current, &mut self.quantized_input, &mut self.next);
+            ::std::mem::swap(&mut self.current, &mut self.next);
+        }
+
+        for (output, value) in outputs.iter_mut().zip(self.current.iter()) {
+            *output = F::from_f64(*value);
+        }
+        Ok(())
+    }
+}
+
+/// Accuracy difference between a quantized network and its float original
+#[derive(Clone, Debug)]
+pub struct QuantizationReport {
+    /// Amount of samples compared
+    pub samples: usize,
+
+    /// Largest absolute output difference
+    pub max_abs_error: f64,
+
+    /// Mean absolute output difference
+    pub mean_abs_error: f64,
+
+    /// Root mean squared output difference
+    pub rms_error: f64,
+
+    /// Share of samples where both networks pick the same largest output (0.0 to 1.0)
+    pub argmax_agreement: f64
+}
+
+/// Compares a quantized network against the compiled float network over a dataset
+pub fn compare<F: Float>(network: &NeuralNetwork<F>, quantized: &QuantizedNetwork, samples: &[Vec<F>]) -> Result<QuantizationReport, QuantizedInstanceError> {
+    let reference = try!(network.build());
+    if reference.outputs() != quantized.outputs() {
+        return Err(QuantizedInstanceError::OutputMismatch(quantized.outputs()));
+    }
+
+    let mut scratch = (Vec::new(), Vec::new());
+    let mut instance = QuantizedInstance::from_quantized(quantized.clone());
+
+    let (mut max_abs, mut sum_abs, mut sum_sq, mut count, mut agreements) = (0.0f64, 0.0, 0.0, 0usize, 0usize);
+    let mut expected: Vec<F> = vec![F::zero(); quantized.outputs()];
+    let mut actual: Vec<F> = vec![F::zero(); quantized.outputs()];
+
+    for sample in samples {
+        if sample.len() != reference.inputs() {
+            return Err(QuantizedInstanceError::InputMismatch(sample.len()));
+        }
+        reference.execute(sample, &mut scratch, &mut expected);
+        try!(instance.calculate(sample, &mut actual));
+
+        for (e, a) in expected.iter().zip(actual.iter()) {
+            let diff = (e.to_f64() - a.to_f64()).abs();
+            max_abs = max_abs.max(diff);
+            sum_abs += diff;
+            sum_sq += diff * diff;
+            count += 1;
+        }
+
+        if argmax(&expected) == argmax(&actual) {
+            agreements += 1;
+        }
+    }
+
+    let count = ::std::cmp::max(count, 1) as f64;
+    Ok(QuantizationReport {
+        samples: samples.len(),
+        max_abs_error: max_abs,
+        mean_abs_error: sum_abs / count,
+        rms_error: (sum_sq / count).sqrt(),
+        argmax_agreement: if samples.is_empty() { 1.0 } else { agreements as f64 / samples.len() as f64 }
+    })
+}
+
+/// Symmetric scale mapping the largest absolute value onto 127
+fn scale_of<I: Iterator<Item = f64>>(values: I) -> f64 {
+    let range = values.fold(0.0f64, |range, v| range.max(v.abs()));
+    if range > 0.0 { range / 127.0 } else { 1.0 }
+}
+
+#[inline]
+fn quantize(value: f64, scale: f64) -> i8 {
+    let q = (value / scale).round();
+    if q > 127.0 { 127 } else if q < -127.0 { -127 } else { q as i8 }
+}
+
+fn argmax<F: Float>(values: &[F]) -> usize {
+    let mut best = 0;
+    for i in 1..values.len() {
+        if values[i] > values[best] {
+            best = i;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, StdRng};
+
+    use neural_network::cpu::CpuInstance;
+    use neural_network::differential::random_network;
+
+    fn rng(seed: usize) -> StdRng {
+        let seed: &[usize] = &[seed];
+        SeedableRng::from_seed(seed)
+    }
+
+    fn samples(rng: &mut StdRng, count: usize, inputs: usize) -> Vec<Vec<f64>> {
+        (0..count).map(|_| (0..inputs).map(|_| rng.gen_range(-2.0, 2.0)).collect()).collect()
+    }
+
+    #[test]
+    fn calibration_maps_largest_layer_input_onto_127() {
+        let network: NeuralNetwork = NeuralNetwork::from_layers(2, vec![
+            vec![Neuron::new(vec![1.0, -2.0], 0.1, NeuronType::Identity), Neuron::new(vec![0.5, 0.5], 0.0, NeuronType::Identity)],
+            vec![Neuron::new(vec![1.0, 1.0], 0.0, NeuronType::Identity)]
+        ]);
+
+        // the hidden layer computes (0.1, 0.75) and (-4.9, -1.0)
+        let quantized = QuantizedNetwork::calibrate(&network, &[vec![1.0, 0.5], vec![-3.0, 1.0]], Granularity::PerLayer).unwrap();
+        assert!(quantized.is_calibrated());
+        assert!((quantized.layers()[0].input_scale().unwrap() - 3.0 / 127.0).abs() < 1e-15);
+        assert!((quantized.layers()[1].input_scale().unwrap() - 4.9 / 127.0).abs() < 1e-15);
+
+        assert!(!QuantizedNetwork::quantize(&network, Granularity::PerLayer).unwrap().is_calibrated());
+
+        match QuantizedNetwork::calibrate::<f64>(&network, &[], Granularity::PerLayer) {
+            Err(QuantizedInstanceError::EmptyCalibration) => { },
+            result => panic!("expected EmptyCalibration, got {:?}", result)
+        }
+        match QuantizedNetwork::calibrate(&network, &[vec![1.0]], Granularity::PerLayer) {
+            Err(QuantizedInstanceError::InputMismatch(1)) => { },
+            result => panic!("expected InputMismatch, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn weights_are_within_half_a_step() {
+        let mut rng = rng(1);
+
+        for _ in 0..20 {
+            let network: NeuralNetwork = random_network(&mut rng, 3, 6);
+            let plan = network.build().unwrap();
+
+            for &granularity in &[Granularity::PerLayer, Granularity::PerChannel] {
+                let quantized = QuantizedNetwork::quantize(&network, granularity).unwrap();
+
+                for (layer, original) in quantized.layers().iter().zip(plan.layers()) {
+                    let largest = original.weights().iter().fold(0.0f64, |max, w| max.max(w.abs()));
+                    let mut saturated = false;
+
+                    for (index, (q, w)) in layer.weights.iter().zip(original.weights()).enumerate() {
+                        let scale = layer.weight_scale(index / layer.inputs);
+                        assert!(*q >= -127);
+                        assert!((*q as f64 * scale - w).abs() <= scale / 2.0 + 1e-15);
+                        saturated |= q.abs() == 127;
+                    }
+
+                    // the largest weight defines its scale and is stored exactly at the end of the range
+                    assert!(saturated || largest == 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_layer_error_is_bounded() {
+        let mut rng = rng(2);
+
+        for _ in 0..20 {
+            let inputs = rng.gen_range(1, 8);
+            let weights: Vec<f64> = (0..inputs).map(|_| rng.gen_range(-1.0, 1.0)).collect();
+            let network: NeuralNetwork = NeuralNetwork::from_layers(inputs, vec![vec![Neuron::new(weights.clone(), 0.3, NeuronType::Identity)]]);
+
+            let mut instance: QuantizedInstance = Instance::new(&network).unwrap();
+            let weight_scale = instance.network().layers()[0].weight_scale(0);
+
+            for sample in samples(&mut rng, 10, inputs) {
+                let input_scale = scale_of(sample.iter().cloned());
+                let mut output = [0.0];
+                instance.calculate(&sample, &mut output).unwrap();
+
+                // |w x - w' x'| <= |w| |x - x'| + |w - w'| |x'| with both differences at most half a step
+                let (mut exact, mut bound) = (0.3, 0.0);
+                for (w, x) in weights.iter().zip(sample.iter()) {
+                    exact += w * x;
+                    bound += w.abs() * input_scale / 2.0 + weight_scale / 2.0 * (x.abs() + input_scale / 2.0);
+                }
+                assert!((output[0] - exact).abs() <= bound + 1e-12, "{} vs {}, bound {}", output[0], exact, bound);
+            }
+        }
+    }
+
+    #[test]
+    fn report_covers_instance_errors() {
+        let mut rng = rng(3);
+
+        for _ in 0..10 {
+            let network: NeuralNetwork = random_network(&mut rng, 3, 6);
+            let data = samples(&mut rng, 20, network.inputs());
+
+            for &granularity in &[Granularity::PerLayer, Granularity::PerChannel] {
+                let quantized = QuantizedNetwork::calibrate(&network, &data, granularity).unwrap();
+                let report = compare(&network, &quantized, &data).unwrap();
+
+                assert_eq!(report.samples, data.len());
+                assert!(report.mean_abs_error <= report.rms_error + 1e-15);
+                assert!(report.rms_error <= report.max_abs_error + 1e-15);
+                assert!(report.argmax_agreement >= 0.0 && report.argmax_agreement <= 1.0);
+
+                let mut instance = QuantizedInstance::from_quantized(quantized);
+                let mut cpu = CpuInstance::new(&network).unwrap();
+                let mut expected = vec![0.0; cpu.output_len()];
+                let mut actual = vec![0.0; expected.len()];
+
+                let mut max_error = 0.0f64;
+                for sample in &data {
+                    cpu.calculate(sample, &mut expected).unwrap();
+                    instance.calculate(sample, &mut actual).unwrap();
+                    for (e, a) in expected.iter().zip(actual.iter()) {
+                        assert!((e - a).abs() <= report.max_abs_error + 1e-12);
+                        max_error = max_error.max((e - a).abs());
+                    }
+                }
+                assert!((max_error - report.max_abs_error).abs() <= 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn report_of_exact_network() {
+        // weights and inputs are multiples of their scales, so int8 is exact
+        let network: NeuralNetwork = NeuralNetwork::from_layers(2, vec![vec![
+            Neuron::new(vec![1.0, -1.0], 0.25, NeuronType::Identity),
+            Neuron::new(vec![0.0, 1.0], 0.0, NeuronType::Identity)
+        ]]);
+        let data = vec![vec![127.0, -64.0], vec![3.0, 127.0]];
+        let quantized = QuantizedNetwork::calibrate(&network, &data, Granularity::PerLayer).unwrap();
+        let report = compare(&network, &quantized, &data).unwrap();
+
+        assert_eq!(report.max_abs_error, 0.0);
+        assert_eq!(report.rms_error, 0.0);
+        assert_eq!(report.argmax_agreement, 1.0);
+    }
+}
@@ -0,0 +1,118 @@
+//! Slow but trusted reference backend
+//!
+//! Evaluates every neuron straight from its definition `activation(sum(w_i * x_i) + bias)`,
+//! always in double precision. Optimized backends are checked against it.
+
+use std::error::Error;
+use std::fmt;
+
+use neural_network::*;
+use neural_network::float::Float;
+
+/// Errors for ReferenceInstance
+#[derive(Debug)]
+pub enum ReferenceInstanceError {
+    /// The network has no inputs or no layers
+    EmptyNetwork,
+
+    /// A neuron has another amount of weights than its previous layer has values
+    ///  (layer_number)
+    ShapeMismatch(usize),
+
+    /// Amount of given inputs does not match the network
+    ///  (amount_of_inputs)
+    InputMismatch(usize),
+
+    /// Size of the given output buffer does not match the network
+    ///  (size_of_buffer)
+    OutputMismatch(usize)
+}
+
+impl fmt::Display for ReferenceInstanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReferenceInstanceError::EmptyNetwork => write!(f, "network has no inputs or no layers"),
+            ReferenceInstanceError::ShapeMismatch(layer) => write!(f, "weights of layer {} do not match the previous layer", layer),
+            ReferenceInstanceError::InputMismatch(len) => write!(f, "got {} inputs", len),
+            ReferenceInstanceError::OutputMismatch(len) => write!(f, "output buffer holds {} values", len)
+        }
+    }
+}
+
+impl Error for ReferenceInstanceError {
+    fn description(&self) -> &str {
+        "reference instance error"
+    }
+}
+
+/// Instance that evaluates the network neuron by neuron
+pub struct ReferenceInstance<'a, F: Float + 'a = f64> {
+    network: &'a NeuralNetwork<F>
+}
+
+impl<'a, F: Float> ReferenceInstance<'a, F> {
+    /// Calulates all outputs in double precision
+    pub fn calculate_f64(&self, inputs: &[F]) -> Result<Vec<f64>, ReferenceInstanceError> {
+        if inputs.len() != self.network.inputs {
+            return Err(ReferenceInstanceError::InputMismatch(inputs.len()));
+        }
+
+        let mut values: Vec<f64> = inputs.iter().map(|x| x.to_f64()).collect();
+
+        for (layer_index, layer) in self.network.hidden_layers.iter().enumerate() {
+            let mut next = Vec::with_capacity(layer.len());
+
+            for neuron in layer {
+                if neuron.weights.len() != values.len() {
+                    return Err(ReferenceInstanceError::ShapeMismatch(layer_index));
+                }
+
+                let mut sum = neuron.bias.to_f64();
+                for (weight, value) in neuron.weights.iter().zip(values.iter()) {
+                    sum += weight.to_f64() * value;
+                }
+
+                next.push(neuron.neuron_type.activate(sum));
+            }
+
+            values = next;
+        }
+
+        Ok(values)
+    }
+}
+
+impl<'a, F: Float> Instance<'a, F> for ReferenceInstance<'a, F> {
+    type Error = ReferenceInstanceError;
+
+    fn new(network: &'a NeuralNetwork<F>) -> Result<Self, ReferenceInstanceError> {
+        if network.inputs == 0 || network.hidden_layers.is_empty() {
+            return Err(ReferenceInstanceError::EmptyNetwork);
+        }
+
+        Ok(ReferenceInstance {
+            network: network
+        })
+    }
+
+    fn input_len(&self) -> usize {
+        self.network.inputs
+    }
+
+    fn output_len(&self) -> usize {
+        self.network.hidden_layers[self.network.hidden_layers.len() - 1].len()
+    }
+
+    fn calculate(&mut self, inputs: &[F], outputs: &mut [F]) -> Result<(), ReferenceInstanceError> {
+        if outputs.len() != self.output_len() {
+            return Err(ReferenceInstanceError::OutputMismatch(outputs.len()));
+        }
+
+        let values = try!(self.calculate_f64(inputs));
+        for (output, value) in outputs.iter_mut().zip(values) {
+            *output = F::from_f64(value);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,541 @@
+//! Vectorised dot-product kernels with runtime cpu feature detection
+//!
+//! The best kernel supported by the running cpu is detected once and then used by
+//! all dense kernels. Every vectorised kernel has the scalar kernel as its reference.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use neural_network::float::Float;
+use neural_network::kernels;
+
+/// Signature of a dot-product kernel
+pub type DotKernel = fn(&[f64], &[f64]) -> f64;
+
+/// Signature of a single precision dot-product kernel
+pub type DotKernelF32 = fn(&[f32], &[f32]) -> f32;
+
+/// Instruction set extensions a kernel can be built upon
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SimdLevel {
+    /// Portable fallback, works everywhere
+    Scalar,
+    Sse2,
+    /// AVX2 together with FMA
+    Avx2,
+    Avx512,
+    Neon
+}
+
+const ALL_LEVELS: [SimdLevel; 5] = [SimdLevel::Scalar, SimdLevel::Sse2, SimdLevel::Avx2, SimdLevel::Avx512, SimdLevel::Neon];
+
+/// Selected level, 0 means not detected yet otherwise index + 1 into ALL_LEVELS
+static SELECTED: AtomicUsize = AtomicUsize::new(0);
+
+impl SimdLevel {
+    /// Checks whether the running cpu supports this level
+    pub fn is_supported(&self) -> bool {
+        match *self {
+            SimdLevel::Scalar => true,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Sse2 => is_x86_feature_detected!("sse2"),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Avx2 => is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma"),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Avx512 => is_x86_feature_detected!("avx512f"),
+            #[cfg(target_arch = "aarch64")]
+            SimdLevel::Neon => ::std::arch::is_aarch64_feature_detected!("neon"),
+            _ => false
+        }
+    }
+
+    /// Dot-product kernel of this level or None if the cpu does not support it
+    pub fn dot_kernel(&self) -> Option<DotKernel> {
+        if !self.is_supported() {
+            return None;
+        }
+
+        match *self {
+            SimdLevel::Scalar => Some(dot_scalar as DotKernel),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Sse2 => Some(x86::dot_sse2 as DotKernel),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Avx2 => Some(x86::dot_avx2 as DotKernel),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Avx512 => Some(x86::dot_avx512 as DotKernel),
+            #[cfg(target_arch = "aarch64")]
+            SimdLevel::Neon => Some(arm::dot_neon as DotKernel),
+            _ => None
+        }
+    }
+
+    /// Single precision dot-product kernel of this level or None if the cpu does not support it
+    pub fn dot_kernel_f32(&self) -> Option<DotKernelF32> {
+        if !self.is_supported() {
+            return None;
+        }
+
+        match *self {
+            SimdLevel::Scalar => Some(dot_scalar_f32 as DotKernelF32),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Sse2 => Some(x86::dot_sse2_f32 as DotKernelF32),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Avx2 => Some(x86::dot_avx2_f32 as DotKernelF32),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdLevel::Avx512 => Some(x86::dot_avx512_f32 as DotKernelF32),
+            #[cfg(target_arch = "aarch64")]
+            SimdLevel::Neon => Some(arm::dot_neon_f32 as DotKernelF32),
+            _ => None
+        }
+    }
+}
+
+/// Detects the best level supported by the running cpu
+pub fn detect() -> SimdLevel {
+    let mut best = SimdLevel::Scalar;
+    for level in ALL_LEVELS.iter() {
+        if level.is_supported() {
+            best = *level;
+        }
+    }
+    best
+}
+
+/// Level used by the dense kernels
+pub fn level() -> SimdLevel {
+    let selected = SELECTED.load(Ordering::Relaxed);
+    if selected != 0 {
+        return ALL_LEVELS[selected - 1];
+    }
+
+    let detected = detect();
+    SELECTED.store(index_of(detected) + 1, Ordering::Relaxed);
+    detected
+}
+
+/// Forces the dense kernels to use the given level, returns false if the cpu does not support it
+pub fn force(level: SimdLevel) -> bool {
+    if !level.is_supported() {
+        return false;
+    }
+
+    SELECTED.store(index_of(level) + 1, Ordering::Relaxed);
+    true
+}
+
+/// Dot-product kernel used by the dense kernels
+#[inline]
+pub fn dot_kernel() -> DotKernel {
+    level().dot_kernel().unwrap_or(dot_scalar)
+}
+
+/// Single precision dot-product kernel used by the dense kernels
+#[inline]
+pub fn dot_kernel_f32() -> DotKernelF32 {
+    level().dot_kernel_f32().unwrap_or(dot_scalar_f32)
+}
+
+/// Dot product using the selected kernel
+#[inline]
+pub fn dot(a: &[f64], b: &[f64]) -> f64 {
+    dot_kernel()(a, b)
+}
+
+fn index_of(level: SimdLevel) -> usize {
+    ALL_LEVELS.iter().position(|l| *l == level).unwrap()
+}
+
+/// Scalar reference kernel
+pub fn dot_scalar(a: &[f64], b: &[f64]) -> f64 {
+    let len = ::std::cmp::min(a.len(), b.len());
+
+    let mut sum = 0.0;
+    for i in 0..len {
+        sum += a[i] * b[i];
+    }
+    sum
+}
+
+/// Single precision scalar reference kernel
+pub fn dot_scalar_f32(a: &[f32], b: &[f32]) -> f32 {
+    let len = ::std::cmp::min(a.len(), b.len());
+
+    let mut sum = 0.0;
+    for i in 0..len {
+        sum += a[i] * b[i];
+    }
+    sum
+}
+
+/// Result of comparing one kernel against the scalar reference
+#[derive(Clone, Debug)]
+pub struct KernelCheck {
+    pub level: SimdLevel,
+
+    /// Largest absolute difference of all dot products
+    pub dot_error: f64,
+
+    /// Largest absolute difference of all gemv outputs
+    pub gemv_error: f64,
+
+    /// Largest absolute difference of all gemm outputs
+    pub gemm_error: f64,
+
+    /// Largest absolute difference of all single precision dot products
+    pub dot_error_f32: f64,
+
+    /// Largest absolute difference of all single precision gemv outputs
+    pub gemv_error_f32: f64,
+
+    /// Largest absolute difference of all single precision gemm outputs
+    pub gemm_error_f32: f64,
+
+    /// Whether all errors stay within the tolerance
+    pub passed: bool
+}
+
+/// Length of the longest dot product compared by `verify`
+pub const VERIFY_COLS: usize = 1037;
+
+/// Largest absolute differences of the dot products, gemv and gemm outputs of two kernels
+fn compare<F: Float>(kernel: fn(&[F], &[F]) -> F, reference: fn(&[F], &[F]) -> F, weights: &[F], rows: usize, cols: usize, x: &[F], batch: usize, bias: &[F]) -> (f64, f64, f64) {
+    let max_error = |a: &[F], b: &[F]| a.iter().zip(b.iter()).fold(0.0f64, |max, (a, b)| max.max((a.to_f64() - b.to_f64()).abs()));
+
+    let mut dot_error: f64 = 0.0;
+    for len in (0..67).chain(cols..cols + 1) {
+        dot_error = dot_error.max((kernel(&weights[..len], &x[..len]).to_f64() - reference(&weights[..len], &x[..len]).to_f64()).abs());
+    }
+
+    let (mut expected, mut result) = (vec![F::zero(); rows], vec![F::zero(); rows]);
+    kernels::gemv_with(reference, weights, rows, cols, &x[..cols], bias, &mut expected);
+    kernels::gemv_with(kernel, weights, rows, cols, &x[..cols], bias, &mut result);
+    let gemv_error = max_error(&expected, &result);
+
+    let (mut expected, mut result) = (vec![F::zero(); batch * rows], vec![F::zero(); batch * rows]);
+    kernels::gemm_with(reference, weights, rows, cols, x, batch, bias, &mut expected);
+    kernels::gemm_with(kernel, weights, rows, cols, x, batch, bias, &mut result);
+    let gemm_error = max_error(&expected, &result);
+
+    (dot_error, gemv_error, gemm_error)
+}
+
+/// Compares every supported kernel against the scalar reference on pseudo random data
+///
+/// Lengths are chosen so that every remainder path of the vectorised kernels is hit, the
+/// longest product has `VERIFY_COLS` terms. Reordered sums differ by a few units in the last
+/// place per term, so `EPSILON * VERIFY_COLS` of the respective type is a tight tolerance.
+pub fn verify(tolerance: f64, f32_tolerance: f64) -> Vec<KernelCheck> {
+    let mut state: u64 = 0x2545f4914f6cdd1d;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % 20000) as f64 / 10000.0 - 1.0
+    };
+
+    let (rows, cols, batch) = (19, VERIFY_COLS, 5);
+    let weights: Vec<f64> = (0..rows * cols).map(|_| next()).collect();
+    let bias: Vec<f64> = (0..rows).map(|_| next()).collect();
+    let x: Vec<f64> = (0..batch * cols).map(|_| next()).collect();
+
+    let weights_f32: Vec<f32> = weights.iter().map(|w| *w as f32).collect();
+    let bias_f32: Vec<f32> = bias.iter().map(|b| *b as f32).collect();
+    let x_f32: Vec<f32> = x.iter().map(|v| *v as f32).collect();
+
+    let mut checks = Vec::new();
+    for level in ALL_LEVELS.iter() {
+        let (kernel, kernel_f32) = match (level.dot_kernel(), level.dot_kernel_f32()) {
+            (Some(kernel), Some(kernel_f32)) => (kernel, kernel_f32),
+            _ => continue
+        };
+
+        let (dot_error, gemv_error, gemm_error) = compare(kernel, dot_scalar, &weights, rows, cols, &x, batch, &bias);
+        let (dot_error_f32, gemv_error_f32, gemm_error_f32) = compare(kernel_f32, dot_scalar_f32, &weights_f32, rows, cols, &x_f32, batch, &bias_f32);
+
+        checks.push(KernelCheck {
+            level: *level,
+            dot_error: dot_error,
+            gemv_error: gemv_error,
+            gemm_error: gemm_error,
+            dot_error_f32: dot_error_f32,
+            gemv_error_f32: gemv_error_f32,
+            gemm_error_f32: gemm_error_f32,
+            passed: dot_error <= tolerance && gemv_error <= tolerance && gemm_error <= tolerance
+                && dot_error_f32 <= f32_tolerance && gemv_error_f32 <= f32_tolerance && gemm_error_f32 <= f32_tolerance
+        });
+    }
+
+    checks
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    pub fn dot_sse2(a: &[f64], b: &[f64]) -> f64 {
+        let len = ::std::cmp::min(a.len(), b.len());
+        // only handed out after sse2 has been detected
+        unsafe { sse2(&a[..len], &b[..len]) }
+    }
+
+    pub fn dot_avx2(a: &[f64], b: &[f64]) -> f64 {
+        let len = ::std::cmp::min(a.len(), b.len());
+        // only handed out after avx2 and fma have been detected
+        unsafe { avx2(&a[..len], &b[..len]) }
+    }
+
+    pub fn dot_avx512(a: &[f64], b: &[f64]) -> f64 {
+        let len = ::std::cmp::min(a.len(), b.len());
+        // only handed out after avx512f has been detected
+        unsafe { avx512(&a[..len], &b[..len]) }
+    }
+
+    pub fn dot_sse2_f32(a: &[f32], b: &[f32]) -> f32 {
+        let len = ::std::cmp::min(a.len(), b.len());
+        // only handed out after sse2 has been detected
+        unsafe { sse2_f32(&a[..len], &b[..len]) }
+    }
+
+    pub fn dot_avx2_f32(a: &[f32], b: &[f32]) -> f32 {
+        let len = ::std::cmp::min(a.len(), b.len());
+        // only handed out after avx2 and fma have been detected
+        unsafe { avx2_f32(&a[..len], &b[..len]) }
+    }
+
+    pub fn dot_avx512_f32(a: &[f32], b: &[f32]) -> f32 {
+        let len = ::std::cmp::min(a.len(), b.len());
+        // only handed out after avx512f has been detected
+        unsafe { avx512_f32(&a[..len], &b[..len]) }
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn sse2(a: &[f64], b: &[f64]) -> f64 {
+        let len = a.len();
+        let end = len - len % 4;
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+
+        let mut acc0 = _mm_setzero_pd();
+        let mut acc1 = _mm_setzero_pd();
+        let mut i = 0;
+        while i < end {
+            acc0 = _mm_add_pd(acc0, _mm_mul_pd(_mm_loadu_pd(pa.add(i)), _mm_loadu_pd(pb.add(i))));
+            acc1 = _mm_add_pd(acc1, _mm_mul_pd(_mm_loadu_pd(pa.add(i + 2)), _mm_loadu_pd(pb.add(i + 2))));
+            i += 4;
+        }
+
+        let mut lanes = [0.0; 2];
+        _mm_storeu_pd(lanes.as_mut_ptr(), _mm_add_pd(acc0, acc1));
+
+        let mut sum = lanes[0] + lanes[1];
+        for j in end..len {
+            sum += a[j] * b[j];
+        }
+        sum
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn avx2(a: &[f64], b: &[f64]) -> f64 {
+        let len = a.len();
+        let end = len - len % 8;
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+
+        let mut acc0 = _mm256_setzero_pd();
+        let mut acc1 = _mm256_setzero_pd();
+        let mut i = 0;
+        while i < end {
+            acc0 = _mm256_fmadd_pd(_mm256_loadu_pd(pa.add(i)), _mm256_loadu_pd(pb.add(i)), acc0);
+            acc1 = _mm256_fmadd_pd(_mm256_loadu_pd(pa.add(i + 4)), _mm256_loadu_pd(pb.add(i + 4)), acc1);
+            i += 8;
+        }
+
+        let mut lanes = [0.0; 4];
+        _mm256_storeu_pd(lanes.as_mut_ptr(), _mm256_add_pd(acc0, acc1));
+
+        let mut sum = lanes[0] + lanes[1] + lanes[2] + lanes[3];
+        for j in end..len {
+            sum += a[j] * b[j];
+        }
+        sum
+    }
+
+    #[target_feature(enable = "avx512f")]
+    unsafe fn avx512(a: &[f64], b: &[f64]) -> f64 {
+        let len = a.len();
+        let end = len - len % 16;
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+
+        let mut acc0 = _mm512_setzero_pd();
+        let mut acc1 = _mm512_setzero_pd();
+        let mut i = 0;
+        while i < end {
+            acc0 = _mm512_fmadd_pd(_mm512_loadu_pd(pa.add(i)), _mm512_loadu_pd(pb.add(i)), acc0);
+            acc1 = _mm512_fmadd_pd(_mm512_loadu_pd(pa.add(i + 8)), _mm512_loadu_pd(pb.add(i + 8)), acc1);
+            i += 16;
+        }
+
+        let mut sum = _mm512_reduce_add_pd(_mm512_add_pd(acc0, acc1));
+        for j in end..len {
+            sum += a[j] * b[j];
+        }
+        sum
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn sse2_f32(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let end = len - len % 8;
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+
+        let mut acc0 = _mm_setzero_ps();
+        let mut acc1 = _mm_setzero_ps();
+        let mut i = 0;
+        while i < end {
+            acc0 = _mm_add_ps(acc0, _mm_mul_ps(_mm_loadu_ps(pa.add(i)), _mm_loadu_ps(pb.add(i))));
+            acc1 = _mm_add_ps(acc1, _mm_mul_ps(_mm_loadu_ps(pa.add(i + 4)), _mm_loadu_ps(pb.add(i + 4))));
+            i += 8;
+        }
+
+        let mut lanes = [0.0; 4];
+        _mm_storeu_ps(lanes.as_mut_ptr(), _mm_add_ps(acc0, acc1));
+
+        let mut sum = lanes[0] + lanes[1] + lanes[2] + lanes[3];
+        for j in end..len {
+            sum += a[j] * b[j];
+        }
+        sum
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn avx2_f32(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let end = len - len % 16;
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+
+        let mut acc0 = _mm256_setzero_ps();
+        let mut acc1 = _mm256_setzero_ps();
+        let mut i = 0;
+        while i < end {
+            acc0 = _mm256_fmadd_ps(_mm256_loadu_ps(pa.add(i)), _mm256_loadu_ps(pb.add(i)), acc0);
+            acc1 = _mm256_fmadd_ps(_mm256_loadu_ps(pa.add(i + 8)), _mm256_loadu_ps(pb.add(i + 8)), acc1);
+            i += 16;
+        }
+
+        let mut lanes = [0.0; 8];
+        _mm256_storeu_ps(lanes.as_mut_ptr(), _mm256_add_ps(acc0, acc1));
+
+        let mut sum = lanes.iter().fold(0.0, |sum, lane| sum + lane);
+        for j in end..len {
+            sum += a[j] * b[j];
+        }
+        sum
+    }
+
+    #[target_feature(enable = "avx512f")]
+    unsafe fn avx512_f32(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let end = len - len % 32;
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+
+        let mut acc0 = _mm512_setzero_ps();
+        let mut acc1 = _mm512_setzero_ps();
+        let mut i = 0;
+        while i < end {
+            acc0 = _mm512_fmadd_ps(_mm512_loadu_ps(pa.add(i)), _mm512_loadu_ps(pb.add(i)), acc0);
+            acc1 = _mm512_fmadd_ps(_mm512_loadu_ps(pa.add(i + 16)), _mm512_loadu_ps(pb.add(i + 16)), acc1);
+            i += 32;
+        }
+
+        let mut sum = _mm512_reduce_add_ps(_mm512_add_ps(acc0, acc1));
+        for j in end..len {
+            sum += a[j] * b[j];
+        }
+        sum
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arm {
+    use std::arch::aarch64::*;
+
+    pub fn dot_neon(a: &[f64], b: &[f64]) -> f64 {
+        let len = ::std::cmp::min(a.len(), b.len());
+        // only handed out after neon has been detected
+        unsafe { neon(&a[..len], &b[..len]) }
+    }
+
+    pub fn dot_neon_f32(a: &[f32], b: &[f32]) -> f32 {
+        let len = ::std::cmp::min(a.len(), b.len());
+        // only handed out after neon has been detected
+        unsafe { neon_f32(&a[..len], &b[..len]) }
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn neon(a: &[f64], b: &[f64]) -> f64 {
+        let len = a.len();
+        let end = len - len % 4;
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+
+        let mut acc0 = vdupq_n_f64(0.0);
+        let mut acc1 = vdupq_n_f64(0.0);
+        let mut i = 0;
+        while i < end {
+            acc0 = vfmaq_f64(acc0, vld1q_f64(pa.add(i)), vld1q_f64(pb.add(i)));
+            acc1 = vfmaq_f64(acc1, vld1q_f64(pa.add(i + 2)), vld1q_f64(pb.add(i + 2)));
+            i += 4;
+        }
+
+        let mut sum = vaddvq_f64(vaddq_f64(acc0, acc1));
+        for j in end..len {
+            sum += a[j] * b[j];
+        }
+        sum
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn neon_f32(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let end = len - len % 8;
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+
+        let mut acc0 = vdupq_n_f32(0.0);
+        let mut acc1 = vdupq_n_f32(0.0);
+        let mut i = 0;
+        while i < end {
+            acc0 = vfmaq_f32(acc0, vld1q_f32(pa.add(i)), vld1q_f32(pb.add(i)));
+            acc1 = vfmaq_f32(acc1, vld1q_f32(pa.add(i + 4)), vld1q_f32(pb.add(i + 4)));
+            i += 8;
+        }
+
+        let mut sum = vaddvq_f32(vaddq_f32(acc0, acc1));
+        for j in end..len {
+            sum += a[j] * b[j];
+        }
+        sum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernels_match_scalar_reference() {
+        let tolerance = ::std::f64::EPSILON * VERIFY_COLS as f64;
+        let f32_tolerance = ::std::f32::EPSILON as f64 * VERIFY_COLS as f64;
+
+        let checks = verify(tolerance, f32_tolerance);
+        assert!(checks.iter().any(|check| check.level == SimdLevel::Scalar));
+
+        for check in &checks {
+            assert!(check.dot_error <= tolerance, "{:?} dot: {}", check.level, check.dot_error);
+            assert!(check.gemv_error <= tolerance, "{:?} gemv: {}", check.level, check.gemv_error);
+            assert!(check.gemm_error <= tolerance, "{:?} gemm: {}", check.level, check.gemm_error);
+            assert!(check.dot_error_f32 <= f32_tolerance, "{:?} f32 dot: {}", check.level, check.dot_error_f32);
+            assert!(check.gemv_error_f32 <= f32_tolerance, "{:?} f32 gemv: {}", check.level, check.gemv_error_f32);
+            assert!(check.gemm_error_f32 <= f32_tolerance, "{:?} f32 gemm: {}", check.level, check.gemm_error_f32);
+            assert!(check.passed, "{:?}", check);
+        }
+    }
+}
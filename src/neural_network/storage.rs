@@ -0,0 +1,145 @@
+//! Saving and loading of neural networks
+//!
+//! Every saved model starts with the magic bytes `DLNN`, the format version and its `Precision`,
+//! so a model is never decoded as the wrong float type.
+//!
+//! Models written before this header existed are plain bincode of an f64 network whose neurons
+//! had no `neuron_type`; back then every neuron used TanH. `load` and `load_converted` detect
+//! them by the missing magic bytes and decode them as all-TanH networks.
+
+use std::io::{Read, Write};
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode_into, decode_from, EncodingError, DecodingError};
+
+use neural_network::{NeuralNetwork, Neuron, NeuronType};
+use neural_network::float::{Float, Precision};
+
+/// Bytes every saved model starts with
+pub const MAGIC: [u8; 4] = [b'D', b'L', b'N', b'N'];
+
+/// Version of the format written by `save`
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Errors for saving and loading networks
+#[derive(Debug)]
+pub enum StorageError {
+    /// Writing the model failed
+    Encoding(EncodingError),
+
+    /// Reading the model failed
+    Decoding(DecodingError),
+
+    /// The saved model has another precision than requested
+    ///  (expected, found)
+    PrecisionMismatch(Precision, Precision),
+
+    /// The model was saved by a newer version of this crate
+    ///  (version)
+    UnsupportedVersion(u32)
+}
+
+impl From<EncodingError> for StorageError {
+    fn from(err: EncodingError) -> Self {
+        StorageError::Encoding(err)
+    }
+}
+
+impl From<DecodingError> for StorageError {
+    fn from(err: DecodingError) -> Self {
+        StorageError::Decoding(err)
+    }
+}
+
+/// Neuron layout of models saved without header
+#[derive(RustcDecodable)]
+struct LegacyNeuron {
+    weights: Vec<f64>,
+    bias: f64
+}
+
+/// Network layout of models saved without header
+#[derive(RustcDecodable)]
+struct LegacyNetwork {
+    inputs: usize,
+    hidden_layers: Vec<Vec<LegacyNeuron>>,
+    #[allow(dead_code)]
+    neuron_count: usize
+}
+
+impl LegacyNetwork {
+    fn upgrade(self) -> NeuralNetwork<f64> {
+        let layers = self.hidden_layers.into_iter().map(|layer| {
+            layer.into_iter().map(|neuron| Neuron::new(neuron.weights, neuron.bias, NeuronType::TanH)).collect()
+        }).collect();
+
+        NeuralNetwork::from_layers(self.inputs, layers)
+    }
+}
+
+/// Saves a network together with its precision
+pub fn save<F: Float, W: Write>(network: &NeuralNetwork<F>, writer: &mut W) -> Result<(), StorageError> {
+    try!(writer.write_all(&MAGIC).map_err(|err| StorageError::Encoding(EncodingError::IoError(err))));
+    try!(encode_into(&FORMAT_VERSION, writer, SizeLimit::Infinite));
+    try!(encode_into(&F::precision(), writer, SizeLimit::Infinite));
+    try!(encode_into(network, writer, SizeLimit::Infinite));
+    Ok(())
+}
+
+/// Decoded model, in the requested precision when possible
+enum Saved<F: Float> {
+    Requested(NeuralNetwork<F>),
+    Double(NeuralNetwork<f64>),
+    Single(NeuralNetwork<f32>),
+
+    /// Model without header, always f64
+    Legacy(NeuralNetwork<f64>)
+}
+
+/// Reads the header and decodes the network, as `F` when the precision matches
+fn read<F: Float, R: Read>(reader: &mut R) -> Result<Saved<F>, StorageError> {
+    let mut magic = [0u8; 4];
+    try!(reader.read_exact(&mut magic).map_err(|err| StorageError::Decoding(DecodingError::IoError(err))));
+
+    if magic != MAGIC {
+        // the bytes read belong to the legacy network
+        let mut legacy = (&magic[..]).chain(reader);
+        let network: LegacyNetwork = try!(decode_from(&mut legacy, SizeLimit::Infinite));
+        return Ok(Saved::Legacy(network.upgrade()));
+    }
+
+    let version: u32 = try!(decode_from(reader, SizeLimit::Infinite));
+    if version > FORMAT_VERSION {
+        return Err(StorageError::UnsupportedVersion(version));
+    }
+
+    let precision: Precision = try!(decode_from(reader, SizeLimit::Infinite));
+    if precision == F::precision() {
+        return Ok(Saved::Requested(try!(decode_from(reader, SizeLimit::Infinite))));
+    }
+
+    match precision {
+        Precision::Single => Ok(Saved::Single(try!(decode_from(reader, SizeLimit::Infinite)))),
+        Precision::Double => Ok(Saved::Double(try!(decode_from(reader, SizeLimit::Infinite))))
+    }
+}
+
+/// Loads a network that has been saved with exactly the precision `F`
+///
+/// Legacy models count as f64.
+pub fn load<F: Float, R: Read>(reader: &mut R) -> Result<NeuralNetwork<F>, StorageError> {
+    match try!(read(reader)) {
+        Saved::Requested(network) => Ok(network),
+        Saved::Legacy(ref network) if F::precision() == Precision::Double => Ok(network.cast()),
+        Saved::Double(_) | Saved::Legacy(_) => Err(StorageError::PrecisionMismatch(F::precision(), Precision::Double)),
+        Saved::Single(_) => Err(StorageError::PrecisionMismatch(F::precision(), Precision::Single))
+    }
+}
+
+/// Loads a network of any precision and converts it to `F`
+pub fn load_converted<F: Float, R: Read>(reader: &mut R) -> Result<NeuralNetwork<F>, StorageError> {
+    match try!(read(reader)) {
+        Saved::Requested(network) => Ok(network),
+        Saved::Double(network) | Saved::Legacy(network) => Ok(network.cast()),
+        Saved::Single(network) => Ok(network.cast())
+    }
+}
@@ -0,0 +1,798 @@
+//! Tape-based reverse-mode automatic differentiation
+//!
+//! Every operation on a `Var` records its result on a `Tape`. `backward` walks the tape in
+//! reverse and returns the gradient of a scalar with respect to every recorded value. Values are
+//! `f64` matrices (`Tensor`), scalars being `1 x 1`. Elementwise operations broadcast dimensions
+//! of size 1, so a column of biases can be added to a batch stored as columns.
+//!
+//! Operations panic when shapes do not fit, like indexing out of bounds.
+//!
+//! ```ignore
+//! let tape = Tape::new();
+//! let params = try!(tape.network(&network));
+//! let x = tape.var(Tensor::column(&inputs));
+//! let loss = (params.forward(x) - tape.var(Tensor::column(&targets))).pow(2.0).mean();
+//! let gradient: Gradient = params.gradient(&loss.backward());
+//! ```
+
+use std::cell::RefCell;
+use std::ops::{Add, Sub, Mul, Div, Neg};
+
+use neural_network::*;
+use neural_network::float::Float;
+use neural_network::gradient::{Gradient, NeuronGradient};
+use neural_network::plan::PlanError;
+
+/// Row-major matrix of `f64`
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct Tensor {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>
+}
+
+impl Tensor {
+    /// Creates a `rows x cols` tensor from row-major data
+    pub fn new(rows: usize, cols: usize, data: Vec<f64>) -> Self {
+        assert!(data.len() == rows * cols, "tensor data does not match its shape");
+        Tensor { rows: rows, cols: cols, data: data }
+    }
+
+    pub fn scalar(value: f64) -> Self {
+        Tensor::new(1, 1, vec![value])
+    }
+
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Tensor::new(rows, cols, vec![0.0; rows * cols])
+    }
+
+    pub fn filled(rows: usize, cols: usize, value: f64) -> Self {
+        Tensor::new(rows, cols, vec![value; rows * cols])
+    }
+
+    /// Column vector
+    pub fn column<F: Float>(values: &[F]) -> Self {
+        Tensor::new(values.len(), 1, values.iter().map(|v| v.to_f64()).collect())
+    }
+
+    /// Row vector
+    pub fn row<F: Float>(values: &[F]) -> Self {
+        Tensor::new(1, values.len(), values.iter().map(|v| v.to_f64()).collect())
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    /// Row-major values
+    pub fn data(&self) -> &[f64] {
+        &self.data
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        self.data[row * self.cols + col]
+    }
+
+    /// Value of a `1 x 1` tensor
+    pub fn item(&self) -> f64 {
+        assert!(self.data.len() == 1, "tensor is not a scalar");
+        self.data[0]
+    }
+
+    /// Value at a position, broadcasting dimensions of size 1
+    fn broadcast_get(&self, row: usize, col: usize) -> f64 {
+        let row = if self.rows == 1 { 0 } else { row };
+        let col = if self.cols == 1 { 0 } else { col };
+        self.data[row * self.cols + col]
+    }
+
+    fn map<M: Fn(f64) -> f64>(&self, f: M) -> Tensor {
+        Tensor::new(self.rows, self.cols, self.data.iter().map(|v| f(*v)).collect())
+    }
+
+    /// Combines two tensors elementwise with broadcasting
+    fn zip<Z: Fn(f64, f64) -> f64>(&self, other: &Tensor, f: Z) -> Tensor {
+        let rows = broadcast_dim(self.rows, other.rows);
+        let cols = broadcast_dim(self.cols, other.cols);
+
+        let mut data = Vec::with_capacity(rows * cols);
+        for r in 0..rows {
+            for c in 0..cols {
+                data.push(f(self.broadcast_get(r, c), other.broadcast_get(r, c)));
+            }
+        }
+        Tensor::new(rows, cols, data)
+    }
+
+    /// Sums a broadcast gradient back down to the given shape
+    fn reduce_to(&self, rows: usize, cols: usize) -> Tensor {
+        if self.rows == rows && self.cols == cols {
+            return self.clone();
+        }
+
+        let mut result = Tensor::zeros(rows, cols);
+        for r in 0..self.rows {
+            for c in 0..self.cols {
+                let target = (if rows == 1 { 0 } else { r }) * cols + if cols == 1 { 0 } else { c };
+                result.data[target] += self.get(r, c);
+            }
+        }
+        result
+    }
+
+    fn add_assign(&mut self, other: &Tensor) {
+        for (a, b) in self.data.iter_mut().zip(other.data.iter()) {
+            *a += *b;
+        }
+    }
+
+    pub fn matmul(&self, other: &Tensor) -> Tensor {
+        assert!(self.cols == other.rows, "matmul of {:?} and {:?}", self.shape(), other.shape());
+
+        let mut result = Tensor::zeros(self.rows, other.cols);
+        for r in 0..self.rows {
+            for k in 0..self.cols {
+                let a = self.get(r, k);
+                for c in 0..other.cols {
+                    result.data[r * other.cols + c] += a * other.get(k, c);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn transpose(&self) -> Tensor {
+        let mut result = Tensor::zeros(self.cols, self.rows);
+        for r in 0..self.rows {
+            for c in 0..self.cols {
+                result.data[c * self.rows + r] = self.get(r, c);
+            }
+        }
+        result
+    }
+}
+
+fn broadcast_dim(a: usize, b: usize) -> usize {
+    if a == b || b == 1 {
+        a
+    } else if a == 1 {
+        b
+    } else {
+        panic!("can not broadcast dimensions {} and {}", a, b)
+    }
+}
+
+/// Axis of a reduction
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Axis {
+    /// Reduces every column to a single value, the result is a row
+    Rows,
+
+    /// Reduces every row to a single value, the result is a column
+    Cols
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Leaf,
+    Add(usize, usize),
+    Sub(usize, usize),
+    Mul(usize, usize),
+    Div(usize, usize),
+    Neg(usize),
+    Pow(usize, f64),
+    Exp(usize),
+    Ln(usize),
+    Abs(usize),
+    Activation(usize, NeuronType),
+    MatMul(usize, usize),
+    Transpose(usize),
+    Sum(usize),
+    Mean(usize),
+    SumAxis(usize, Axis),
+    Max(usize)
+}
+
+struct Node {
+    value: Tensor,
+    op: Op
+}
+
+/// Records operations for differentiation
+pub struct Tape {
+    nodes: RefCell<Vec<Node>>
+}
+
+/// Value recorded on a tape
+#[derive(Copy, Clone)]
+pub struct Var<'t> {
+    tape: &'t Tape,
+    index: usize
+}
+
+impl Tape {
+    pub fn new() -> Self {
+        Tape { nodes: RefCell::new(Vec::new()) }
+    }
+
+    /// Records a value, gradients are computed for it like for every other value
+    pub fn var(&self, value: Tensor) -> Var {
+        self.push(value, Op::Leaf)
+    }
+
+    pub fn scalar(&self, value: f64) -> Var {
+        self.var(Tensor::scalar(value))
+    }
+
+    /// Amount of recorded values
+    pub fn len(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+
+    /// Removes all recorded values, existing `Var`s become invalid
+    pub fn clear(&mut self) {
+        self.nodes.borrow_mut().clear();
+    }
+
+    fn push(&self, value: Tensor, op: Op) -> Var {
+        let mut nodes = self.nodes.borrow_mut();
+        nodes.push(Node { value: value, op: op });
+        Var { tape: self, index: nodes.len() - 1 }
+    }
+
+    /// Records the weights and biases of a network
+    pub fn network<F: Float>(&self, network: &NeuralNetwork<F>) -> Result<NetworkVars, PlanError> {
+        let plan = try!(network.build());
+
+        Ok(NetworkVars {
+            layers: plan.layers().iter().map(|layer| LayerVars {
+                weights: self.var(Tensor::new(layer.outputs(), layer.inputs(), layer.weights().iter().map(|w| w.to_f64()).collect())),
+                biases: self.var(Tensor::column(layer.biases())),
+                activation: layer.activation()
+            }).collect()
+        })
+    }
+}
+
+impl<'t> Var<'t> {
+    /// Current value
+    pub fn value(&self) -> Tensor {
+        self.tape.nodes.borrow()[self.index].value.clone()
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        self.tape.nodes.borrow()[self.index].value.shape()
+    }
+
+    fn unary<M: Fn(&Tensor) -> Tensor>(&self, op: Op, f: M) -> Var<'t> {
+        let value = f(&self.tape.nodes.borrow()[self.index].value);
+        self.tape.push(value, op)
+    }
+
+    fn binary<M: Fn(&Tensor, &Tensor) -> Tensor>(&self, other: Var<'t>, op: Op, f: M) -> Var<'t> {
+        assert!(self.tape as *const Tape == other.tape as *const Tape, "vars belong to different tapes");
+        let value = {
+            let nodes = self.tape.nodes.borrow();
+            f(&nodes[self.index].value, &nodes[other.index].value)
+        };
+        self.tape.push(value, op)
+    }
+
+    fn constant(&self, value: f64) -> Var<'t> {
+        self.tape.scalar(value)
+    }
+
+    pub fn pow(&self, exponent: f64) -> Var<'t> {
+        self.unary(Op::Pow(self.index, exponent), |x| x.map(|v| v.powf(exponent)))
+    }
+
+    pub fn exp(&self) -> Var<'t> {
+        self.unary(Op::Exp(self.index), |x| x.map(f64::exp))
+    }
+
+    pub fn ln(&self) -> Var<'t> {
+        self.unary(Op::Ln(self.index), |x| x.map(f64::ln))
+    }
+
+    pub fn sqrt(&self) -> Var<'t> {
+        self.pow(0.5)
+    }
+
+    pub fn abs(&self) -> Var<'t> {
+        self.unary(Op::Abs(self.index), |x| x.map(f64::abs))
+    }
+
+    /// Applies the activation function of a neuron type elementwise
+    pub fn activate(&self, neuron_type: NeuronType) -> Var<'t> {
+        self.unary(Op::Activation(self.index, neuron_type), |x| x.map(|v| neuron_type.activate(v)))
+    }
+
+    pub fn sigmoid(&self) -> Var<'t> {
+        self.activate(NeuronType::SigMoid)
+    }
+
+    pub fn tanh(&self) -> Var<'t> {
+        self.activate(NeuronType::TanH)
+    }
+
+    pub fn relu(&self) -> Var<'t> {
+        self.activate(NeuronType::DeLu)
+    }
+
+    pub fn matmul(&self, other: Var<'t>) -> Var<'t> {
+        self.binary(other, Op::MatMul(self.index, other.index), |a, b| a.matmul(b))
+    }
+
+    pub fn transpose(&self) -> Var<'t> {
+        self.unary(Op::Transpose(self.index), |x| x.transpose())
+    }
+
+    /// Sum of all values
+    pub fn sum(&self) -> Var<'t> {
+        self.unary(Op::Sum(self.index), |x| Tensor::scalar(x.data.iter().fold(0.0, |sum, v| sum + v)))
+    }
+
+    /// Mean of all values
+    pub fn mean(&self) -> Var<'t> {
+        self.unary(Op::Mean(self.index), |x| Tensor::scalar(x.data.iter().fold(0.0, |sum, v| sum + v) / x.data.len() as f64))
+    }
+
+    /// Sums along an axis
+    pub fn sum_axis(&self, axis: Axis) -> Var<'t> {
+        self.unary(Op::SumAxis(self.index, axis), |x| match axis {
+            Axis::Rows => x.reduce_to(1, x.cols),
+            Axis::Cols => x.reduce_to(x.rows, 1)
+        })
+    }
+
+    /// Largest value, its gradient flows to the first maximum
+    pub fn max(&self) -> Var<'t> {
+        self.unary(Op::Max(self.index), |x| Tensor::scalar(x.data.iter().fold(::std::f64::NEG_INFINITY, |max, v| max.max(*v))))
+    }
+
+    /// Softmax over every column
+    pub fn softmax(&self) -> Var<'t> {
+        // shifting by the maximum keeps exp finite and does not change the result
+        let shifted = *self - self.constant(self.value().data.iter().fold(::std::f64::NEG_INFINITY, |max, v| max.max(*v)));
+        let exp = shifted.exp();
+        exp / exp.sum_axis(Axis::Rows)
+    }
+
+    /// Gradients of this scalar with respect to every value on the tape
+    pub fn backward(&self) -> Gradients {
+        let nodes = self.tape.nodes.borrow();
+        assert!(nodes[self.index].value.data.len() == 1, "backward needs a scalar");
+
+        let mut grads: Vec<Option<Tensor>> = vec![None; nodes.len()];
+        grads[self.index] = Some(Tensor::scalar(1.0));
+
+        for index in (0..self.index + 1).rev() {
+            let grad = match grads[index].take() {
+                Some(grad) => grad,
+                None => continue
+            };
+
+            {
+                let node = &nodes[index];
+                let value = |i: usize| &nodes[i].value;
+                let mut accumulate = |i: usize, g: Tensor| {
+                    let g = g.reduce_to(nodes[i].value.rows, nodes[i].value.cols);
+                    match grads[i] {
+                        Some(ref mut existing) => existing.add_assign(&g),
+                        None => grads[i] = Some(g)
+                    }
+                };
+
+                match node.op {
+                    Op::Leaf => { },
+                    Op::Add(a, b) => {
+                        accumulate(a, grad.clone());
+                        accumulate(b, grad.clone());
+                    },
+                    Op::Sub(a, b) => {
+                        accumulate(a, grad.clone());
+                        accumulate(b, grad.map(|g| -g));
+                    },
+                    Op::Mul(a, b) => {
+                        accumulate(a, grad.zip(value(b), |g, y| g * y));
+                        accumulate(b, grad.zip(value(a), |g, x| g * x));
+                    },
+                    Op::Div(a, b) => {
+                        accumulate(a, grad.zip(value(b), |g, y| g / y));
+                        let db = grad.zip(&node.value, |g, z| g * z).zip(value(b), |gz, y| -gz / y);
+                        accumulate(b, db);
+                    },
+                    Op::Neg(a) => accumulate(a, grad.map(|g| -g)),
+                    Op::Pow(a, exponent) => {
+                        accumulate(a, grad.zip(value(a), |g, x| g * exponent * x.powf(exponent - 1.0)));
+                    },
+                    Op::Exp(a) => accumulate(a, grad.zip(&node.value, |g, y| g * y)),
+                    Op::Ln(a) => accumulate(a, grad.zip(value(a), |g, x| g / x)),
+                    Op::Abs(a) => accumulate(a, grad.zip(value(a), |g, x| if x > 0.0 { g } else if x < 0.0 { -g } else { 0.0 })),
+                    Op::Activation(a, neuron_type) => {
+                        let derivative = value(a).zip(&node.value, |pre, post| neuron_type.derivative(pre, post));
+                        accumulate(a, grad.zip(&derivative, |g, d| g * d));
+                    },
+                    Op::MatMul(a, b) => {
+                        accumulate(a, grad.matmul(&value(b).transpose()));
+                        accumulate(b, value(a).transpose().matmul(&grad));
+                    },
+                    Op::Transpose(a) => accumulate(a, grad.transpose()),
+                    Op::Sum(a) | Op::SumAxis(a, _) => {
+                        // broadcasting spreads the gradient over the reduced dimensions
+                        let (rows, cols) = value(a).shape();
+                        accumulate(a, Tensor::zeros(rows, cols).zip(&grad, |_, g| g));
+                    },
+                    Op::Mean(a) => {
+                        let (rows, cols) = value(a).shape();
+                        let g = grad.item() / (rows * cols) as f64;
+                        accumulate(a, Tensor::filled(rows, cols, g));
+                    },
+                    Op::Max(a) => {
+                        let input = value(a);
+                        let max = node.value.item();
+                        let position = input.data.iter().position(|v| *v == max).unwrap_or(0);
+
+                        let mut g = Tensor::zeros(input.rows, input.cols);
+                        g.data[position] = grad.item();
+                        accumulate(a, g);
+                    }
+                }
+            }
+
+            grads[index] = Some(grad);
+        }
+
+        Gradients { values: grads }
+    }
+}
+
+/// Gradients of a scalar with respect to the values of a tape
+pub struct Gradients {
+    values: Vec<Option<Tensor>>
+}
+
+impl Gradients {
+    /// Gradient with respect to `var`, `None` when the scalar does not depend on it
+    pub fn get(&self, var: Var) -> Option<&Tensor> {
+        self.values.get(var.index).and_then(|grad| grad.as_ref())
+    }
+
+    /// Gradient with respect to `var`, zeros when the scalar does not depend on it
+    pub fn wrt(&self, var: Var) -> Tensor {
+        match self.get(var) {
+            Some(grad) => grad.clone(),
+            None => {
+                let (rows, cols) = var.shape();
+                Tensor::zeros(rows, cols)
+            }
+        }
+    }
+}
+
+/// Weights and biases of a network layer on a tape
+#[derive(Copy, Clone)]
+pub struct LayerVars<'t> {
+    /// `outputs x inputs`
+    pub weights: Var<'t>,
+
+    /// `outputs x 1`
+    pub biases: Var<'t>,
+
+    pub activation: NeuronType
+}
+
+/// Parameters of a network on a tape, see `Tape::network`
+pub struct NetworkVars<'t> {
+    pub layers: Vec<LayerVars<'t>>
+}
+
+impl<'t> NetworkVars<'t> {
+    /// Calculates the network for inputs stored as columns, `inputs x batch`
+    pub fn forward(&self, inputs: Var<'t>) -> Var<'t> {
+        self.layers.iter().fold(inputs, |x, layer| {
+            (layer.weights.matmul(x) + layer.biases).activate(layer.activation)
+        })
+    }
+
+    /// Gradient shaped like the network, usable with the optimizers
+    pub fn gradient<F: Float>(&self, gradients: &Gradients) -> Gradient<F> {
+        Gradient {
+            layers: self.layers.iter().map(|layer| {
+                let weights = gradients.wrt(layer.weights);
+                let biases = gradients.wrt(layer.biases);
+
+                (0..weights.rows()).map(|n| NeuronGradient {
+                    weights: (0..weights.cols()).map(|i| F::from_f64(weights.get(n, i))).collect(),
+                    bias: F::from_f64(biases.get(n, 0))
+                }).collect()
+            }).collect()
+        }
+    }
+}
+
+impl<'t> Add for Var<'t> {
+    type Output = Var<'t>;
+
+    fn add(self, other: Var<'t>) -> Var<'t> {
+        self.binary(other, Op::Add(self.index, other.index), |a, b| a.zip(b, |x, y| x + y))
+    }
+}
+
+impl<'t> Sub for Var<'t> {
+    type Output = Var<'t>;
+
+    fn sub(self, other: Var<'t>) -> Var<'t> {
+        self.binary(other, Op::Sub(self.index, other.index), |a, b| a.zip(b, |x, y| x - y))
+    }
+}
+
+impl<'t> Mul for Var<'t> {
+    type Output = Var<'t>;
+
+    /// Elementwise product, see `matmul` for the matrix product
+    fn mul(self, other: Var<'t>) -> Var<'t> {
+        self.binary(other, Op::Mul(self.index, other.index), |a, b| a.zip(b, |x, y| x * y))
+    }
+}
+
+impl<'t> Div for Var<'t> {
+    type Output = Var<'t>;
+
+    fn div(self, other: Var<'t>) -> Var<'t> {
+        self.binary(other, Op::Div(self.index, other.index), |a, b| a.zip(b, |x, y| x / y))
+    }
+}
+
+impl<'t> Neg for Var<'t> {
+    type Output = Var<'t>;
+
+    fn neg(self) -> Var<'t> {
+        self.unary(Op::Neg(self.index), |x| x.map(|v| -v))
+    }
+}
+
+impl<'t> Add<f64> for Var<'t> {
+    type Output = Var<'t>;
+
+    fn add(self, other: f64) -> Var<'t> {
+        self + self.constant(other)
+    }
+}
+
+impl<'t> Sub<f64> for Var<'t> {
+    type Output = Var<'t>;
+
+    fn sub(self, other: f64) -> Var<'t> {
+        self - self.constant(other)
+    }
+}
+
+impl<'t> Mul<f64> for Var<'t> {
+    type Output = Var<'t>;
+
+    fn mul(self, other: f64) -> Var<'t> {
+        self * self.constant(other)
+    }
+}
+
+impl<'t> Div<f64> for Var<'t> {
+    type Output = Var<'t>;
+
+    fn div(self, other: f64) -> Var<'t> {
+        self / self.constant(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, StdRng};
+
+    use neural_network::differential::random_network;
+    use training::dataset::Dataset;
+    use training::loss::{self, MeanSquaredError};
+
+    const EPSILON: f64 = 1e-6;
+
+    fn random(rng: &mut StdRng, rows: usize, cols: usize, min: f64, max: f64) -> Tensor {
+        Tensor::new(rows, cols, (0..rows * cols).map(|_| rng.gen_range(min, max)).collect())
+    }
+
+    /// Records `f` on a fresh tape, mixes its outputs into a scalar and returns it with the gradients of the inputs
+    fn evaluate<G>(inputs: &[Tensor], f: &G) -> (f64, Vec<Tensor>) where G: for<'t> Fn(&[Var<'t>]) -> Var<'t> {
+        let tape = Tape::new();
+        let vars: Vec<Var> = inputs.iter().map(|input| tape.var(input.clone())).collect();
+
+        // distinct weights per output catch gradients sent to the wrong position
+        let output = f(&vars);
+        let (rows, cols) = output.shape();
+        let mix = tape.var(Tensor::new(rows, cols, (0..rows * cols).map(|i| (i as f64 + 1.0).sin()).collect()));
+        let scalar = (output * mix).sum();
+
+        let gradients = scalar.backward();
+        (scalar.value().item(), vars.iter().map(|var| gradients.wrt(*var)).collect())
+    }
+
+    /// Compares `backward` against central differences for every entry of every input
+    fn check<G>(name: &str, inputs: &[Tensor], f: G) where G: for<'t> Fn(&[Var<'t>]) -> Var<'t> {
+        let (_, analytic) = evaluate(inputs, &f);
+
+        for (i, input) in inputs.iter().enumerate() {
+            assert_eq!(analytic[i].shape(), input.shape(), "{}: gradient shape of input {}", name, i);
+
+            for j in 0..input.data.len() {
+                let mut shifted = inputs.to_vec();
+                shifted[i].data[j] = input.data[j] + EPSILON;
+                let (plus, _) = evaluate(&shifted, &f);
+                shifted[i].data[j] = input.data[j] - EPSILON;
+                let (minus, _) = evaluate(&shifted, &f);
+
+                let numeric = (plus - minus) / (2.0 * EPSILON);
+                let error = (numeric - analytic[i].data[j]).abs();
+                assert!(error <= 1e-6 * numeric.abs().max(1.0), "{}: input {} entry {}: {} vs {}", name, i, j, analytic[i].data[j], numeric);
+            }
+        }
+    }
+
+    /// Operand shapes that broadcast to `2 x 3`
+    fn broadcast_pairs(rng: &mut StdRng, min: f64, max: f64) -> Vec<(Tensor, Tensor)> {
+        let mut pairs: Vec<(Tensor, Tensor)> = vec![(2, 3), (1, 3), (2, 1), (1, 1)].into_iter().map(|(rows, cols)| {
+            (random(rng, 2, 3, min, max), random(rng, rows, cols, min, max))
+        }).collect();
+        pairs.push((random(rng, 1, 3, min, max), random(rng, 2, 1, min, max)));
+        pairs
+    }
+
+    fn rng(seed: usize) -> StdRng {
+        let seed: &[usize] = &[seed];
+        SeedableRng::from_seed(seed)
+    }
+
+    #[test]
+    fn elementwise_binary_with_broadcasting() {
+        let mut rng = rng(1);
+
+        for (a, b) in broadcast_pairs(&mut rng, -2.0, 2.0) {
+            check("add", &[a.clone(), b.clone()], |x| x[0] + x[1]);
+            check("add reversed", &[b.clone(), a.clone()], |x| x[0] + x[1]);
+            check("sub", &[a.clone(), b.clone()], |x| x[0] - x[1]);
+            check("sub reversed", &[b.clone(), a.clone()], |x| x[0] - x[1]);
+            check("mul", &[a.clone(), b.clone()], |x| x[0] * x[1]);
+            check("mul reversed", &[b.clone(), a.clone()], |x| x[0] * x[1]);
+        }
+
+        // denominators stay away from zero
+        for (a, b) in broadcast_pairs(&mut rng, 0.5, 2.0) {
+            check("div", &[a.clone(), b.clone()], |x| x[0] / x[1]);
+            check("div reversed", &[b.clone(), a.clone()], |x| x[0] / x[1]);
+        }
+
+        let a = random(&mut rng, 2, 3, 0.5, 2.0);
+        check("scalar constants", &[a], |x| (x[0] + 1.5) * 2.0 / 3.0 - 0.5);
+        check("same var twice", &[random(&mut rng, 2, 3, 0.5, 2.0)], |x| x[0] * x[0] + x[0] / x[0]);
+    }
+
+    #[test]
+    fn elementwise_unary() {
+        let mut rng = rng(2);
+
+        // no entry sits at the kinks of abs and relu
+        let mixed = Tensor::new(2, 3, vec![-1.5, -0.7, -0.2, 0.3, 0.9, 1.8]);
+        let positive = random(&mut rng, 3, 2, 0.5, 2.0);
+
+        check("neg", &[mixed.clone()], |x| -x[0]);
+        check("exp", &[mixed.clone()], |x| x[0].exp());
+        check("ln", &[positive.clone()], |x| x[0].ln());
+        check("abs", &[mixed.clone()], |x| x[0].abs());
+        check("sqrt", &[positive.clone()], |x| x[0].sqrt());
+
+        for &exponent in &[2.0, 3.0, 0.5, -1.5] {
+            check("pow", &[positive.clone()], |x| x[0].pow(exponent));
+        }
+        check("pow of negatives", &[mixed.clone()], |x| x[0].pow(3.0));
+
+        for &neuron_type in &[NeuronType::Identity, NeuronType::SigMoid, NeuronType::TanH, NeuronType::DeLu] {
+            check("activation", &[mixed.clone()], |x| x[0].activate(neuron_type));
+        }
+    }
+
+    #[test]
+    fn reductions() {
+        let mut rng = rng(3);
+        let a = random(&mut rng, 3, 4, -2.0, 2.0);
+
+        check("sum", &[a.clone()], |x| x[0].sum());
+        check("mean", &[a.clone()], |x| x[0].mean());
+        check("sum rows", &[a.clone()], |x| x[0].sum_axis(Axis::Rows));
+        check("sum cols", &[a.clone()], |x| x[0].sum_axis(Axis::Cols));
+
+        assert_eq!(evaluate(&[a.clone()], &|x: &[Var]| x[0].sum_axis(Axis::Rows)).1[0].shape(), (3, 4));
+
+        // a unique maximum keeps max differentiable
+        let mut b = a.clone();
+        b.data[5] = 3.0;
+        check("max", &[b.clone()], |x| x[0].max());
+        check("max of product", &[b, a], |x| (x[0] * x[1]).max());
+    }
+
+    #[test]
+    fn matrix_operations() {
+        let mut rng = rng(4);
+        let a = random(&mut rng, 2, 3, -2.0, 2.0);
+        let b = random(&mut rng, 3, 4, -2.0, 2.0);
+        let c = random(&mut rng, 4, 3, -2.0, 2.0);
+
+        check("matmul", &[a.clone(), b.clone()], |x| x[0].matmul(x[1]));
+        check("transpose", &[a.clone()], |x| x[0].transpose());
+        check("matmul of transpose", &[a.clone(), c], |x| x[1].matmul(x[0].transpose()));
+        check("gram", &[b], |x| x[0].matmul(x[0].transpose()));
+    }
+
+    #[test]
+    fn softmax() {
+        let mut rng = rng(5);
+        let a = random(&mut rng, 3, 2, -2.0, 2.0);
+
+        check("softmax", &[a.clone()], |x| x[0].softmax());
+        check("softmax of large values", &[Tensor::new(2, 2, vec![500.0, 501.0, 499.5, 502.0])], |x| x[0].softmax());
+
+        let tape = Tape::new();
+        let probabilities = tape.var(a).softmax().value();
+        for col in 0..2 {
+            let sum = (0..3).fold(0.0, |sum, row| sum + probabilities.get(row, col));
+            assert!((sum - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn network_gradient_matches_backpropagation() {
+        for seed in 1..6 {
+            let mut rng = rng(seed);
+            let network: NeuralNetwork = random_network(&mut rng, 3, 4);
+            let outputs = network.layers().last().unwrap().len();
+
+            let batch = 5;
+            let inputs: Vec<Vec<f64>> = (0..batch).map(|_| (0..network.inputs()).map(|_| rng.gen_range(-1.0, 1.0)).collect()).collect();
+            let targets: Vec<Vec<f64>> = (0..batch).map(|_| (0..outputs).map(|_| rng.gen_range(-1.0, 1.0)).collect()).collect();
+            let weights: Vec<f64> = (0..batch).map(|_| rng.gen_range(0.5, 2.0)).collect();
+            let data = Dataset::new(inputs.clone(), targets.clone()).unwrap().with_weights(weights.clone()).unwrap();
+            let indices: Vec<usize> = (0..batch).collect();
+
+            let (expected_loss, expected) = loss::gradient(&MeanSquaredError, &network, &data, &indices).unwrap();
+
+            // weighted mean over the batch of the mean squared error over the outputs
+            let tape = Tape::new();
+            let params = tape.network(&network).unwrap();
+            let x = tape.var(Tensor::new(batch, network.inputs(), inputs.concat()).transpose());
+            let t = tape.var(Tensor::new(batch, outputs, targets.concat()).transpose());
+            let w = tape.var(Tensor::row(&weights));
+            let per_sample = (params.forward(x) - t).pow(2.0).sum_axis(Axis::Rows) / outputs as f64;
+            let total = weights.iter().fold(0.0, |sum, w| sum + w);
+            let mse = (per_sample * w).sum() / total;
+
+            let gradient: Gradient = params.gradient(&mse.backward());
+
+            assert!((mse.value().item() - expected_loss).abs() < 1e-12, "seed {}", seed);
+            assert_eq!(gradient.layers.len(), expected.layers.len());
+            for (layer, expected_layer) in gradient.layers.iter().zip(expected.layers.iter()) {
+                assert_eq!(layer.len(), expected_layer.len());
+                for (neuron, expected_neuron) in layer.iter().zip(expected_layer.iter()) {
+                    assert_eq!(neuron.weights.len(), expected_neuron.weights.len());
+                    assert!((neuron.bias - expected_neuron.bias).abs() < 1e-12, "seed {}", seed);
+                    for (g, e) in neuron.weights.iter().zip(expected_neuron.weights.iter()) {
+                        assert!((g - e).abs() < 1e-12, "seed {}: {} vs {}", seed, g, e);
+                    }
+                }
+            }
+        }
+    }
+}
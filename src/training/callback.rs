@@ -0,0 +1,89 @@
+//! Callbacks called by the `Trainer`
+//!
+//! Every event defaults to doing nothing. Callbacks receive a context through which they can
+//! inspect the network and history and change the learning rate, returning `Control::Stop`
+//! ends training after the current epoch.
+
+use std::fs::File;
+use std::path::PathBuf;
+
+use evolution::Control;
+use neural_network::NeuralNetwork;
+use neural_network::float::Float;
+use neural_network::storage;
+
+use training::trainer::{EpochRecord, History};
+
+/// State of the trainer passed to callbacks
+pub struct CallbackContext<'a, F: Float + 'a = f64> {
+    /// Current epoch, counted from 0
+    pub epoch: usize,
+
+    pub network: &'a NeuralNetwork<F>,
+
+    /// Records of all finished epochs
+    pub history: &'a History,
+
+    /// Learning rate of the optimizer, changes are applied after the event. With a schedule
+    /// the change scales all following learning rates of the schedule.
+    pub learning_rate: f64
+}
+
+/// Trait for training callbacks
+pub trait Callback<F: Float = f64> {
+    fn epoch_start(&mut self, _context: &mut CallbackContext<F>) -> Control {
+        Control::Continue
+    }
+
+    /// Called after every optimizer step with the loss of the batch
+    fn batch_end(&mut self, _context: &mut CallbackContext<F>, _batch: usize, _loss: f64) -> Control {
+        Control::Continue
+    }
+
+    fn epoch_end(&mut self, _context: &mut CallbackContext<F>, _record: &EpochRecord) -> Control {
+        Control::Continue
+    }
+
+    /// Called after `epoch_end` when the epoch has the lowest validation loss so far (or
+    /// training loss without validation)
+    fn new_best(&mut self, _context: &mut CallbackContext<F>, _record: &EpochRecord) -> Control {
+        Control::Continue
+    }
+}
+
+/// Logs every epoch
+pub struct Logger;
+
+impl<F: Float> Callback<F> for Logger {
+    fn epoch_end(&mut self, _: &mut CallbackContext<F>, record: &EpochRecord) -> Control {
+        info!(target: "trainer", "Epoch {}: loss {}, validation loss {:?}, learning rate {}, metrics {:?}",
+            record.epoch, record.train_loss, record.validation_loss, record.learning_rate, record.metrics);
+        Control::Continue
+    }
+}
+
+/// Saves the network with `storage::save` whenever a new best epoch is reached
+pub struct SaveBest {
+    pub path: PathBuf
+}
+
+impl<F: Float> Callback<F> for SaveBest {
+    fn new_best(&mut self, context: &mut CallbackContext<F>, record: &EpochRecord) -> Control {
+        let result = File::create(&self.path).map_err(|err| format!("{}", err))
+            .and_then(|mut file| storage::save(context.network, &mut file).map_err(|err| format!("{:?}", err)));
+
+        if let Err(err) = result {
+            warn!(target: "trainer", "Could not save the network of epoch {} to {:?}: {}", record.epoch, self.path, err);
+        }
+        Control::Continue
+    }
+}
+
+/// Callback calling a closure at the end of every epoch
+pub struct EpochCallback<C>(pub C);
+
+impl<F: Float, C> Callback<F> for EpochCallback<C> where C: FnMut(&mut CallbackContext<F>, &EpochRecord) -> Control {
+    fn epoch_end(&mut self, context: &mut CallbackContext<F>, record: &EpochRecord) -> Control {
+        (self.0)(context, record)
+    }
+}
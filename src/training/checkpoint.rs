@@ -0,0 +1,407 @@
+//! Resumable training state
+//!
+//! A `Checkpoint` holds everything a `Trainer` changes while training: the network, the optimizer
+//! with its moments, the history (and with it the epoch counter), the state of the schedule and
+//! of early stopping. Shuffling is seeded by `(seed, epoch)`, so storing the seed restores the
+//! random state. Loss, options, schedule, regularization and callbacks are configuration and
+//! have to be set up again before calling `Trainer::resume`.
+//!
+//! Checkpoints are taken between epochs, so training resumes exactly only from epoch
+//! boundaries. Stop requests and the state of callbacks are not saved either.
+//!
+//! Files start with the precision of the network like `storage`. They are written to a temporary
+//! file first and renamed afterwards, so a crash never leaves a partial checkpoint behind. On
+//! Unix the directory is synced after the rename, so the new name survives a crash as well.
+
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode_into, decode_from, EncodingError, DecodingError};
+use rustc_serialize::{Encodable, Decodable};
+
+use neural_network::NeuralNetwork;
+use neural_network::float::{Float, Precision};
+use neural_network::storage::StorageError;
+
+use training::early_stopping::EarlyStopping;
+use training::trainer::History;
+
+/// Errors for saving and loading checkpoints
+#[derive(Debug)]
+pub enum CheckpointError {
+    /// Creating, renaming or deleting a file failed
+    Io(io::Error),
+
+    /// Encoding or decoding the checkpoint failed
+    Storage(StorageError)
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CheckpointError::Io(ref err) => write!(f, "{}", err),
+            CheckpointError::Storage(ref err) => write!(f, "{:?}", err)
+        }
+    }
+}
+
+impl Error for CheckpointError {
+    fn description(&self) -> &str {
+        "checkpoint error"
+    }
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+
+impl From<StorageError> for CheckpointError {
+    fn from(err: StorageError) -> Self {
+        CheckpointError::Storage(err)
+    }
+}
+
+impl From<EncodingError> for CheckpointError {
+    fn from(err: EncodingError) -> Self {
+        CheckpointError::Storage(StorageError::Encoding(err))
+    }
+}
+
+impl From<DecodingError> for CheckpointError {
+    fn from(err: DecodingError) -> Self {
+        CheckpointError::Storage(StorageError::Decoding(err))
+    }
+}
+
+/// Complete state of a training run
+#[derive(RustcEncodable, RustcDecodable, Clone)]
+pub struct Checkpoint<F: Float, O> {
+    pub network: NeuralNetwork<F>,
+    pub optimizer: O,
+
+    /// Seed of the shuffling
+    pub seed: usize,
+
+    /// Learning rate the schedule scales, changed by callbacks
+    pub base_learning_rate: f64,
+
+    /// See `Schedule::state`
+    pub schedule_state: Vec<f64>,
+
+    pub early_stopping: Option<EarlyStopping>,
+
+    /// Network of the best epoch kept for early stopping
+    pub best_network: Option<NeuralNetwork<F>>,
+
+    pub history: History
+}
+
+impl<F: Float, O> Checkpoint<F, O> {
+    /// Amount of finished epochs
+    pub fn epoch(&self) -> usize {
+        self.history.epochs.len()
+    }
+}
+
+impl<F: Float, O: Encodable + Decodable> Checkpoint<F, O> {
+    /// Writes the checkpoint atomically, replacing an existing file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), CheckpointError> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        {
+            let mut writer = BufWriter::new(try!(File::create(&temporary)));
+            try!(encode_into(&F::precision(), &mut writer, SizeLimit::Infinite));
+            try!(encode_into(self, &mut writer, SizeLimit::Infinite));
+
+            try!(writer.flush());
+            try!(writer.get_ref().sync_all());
+        }
+
+        try!(fs::rename(&temporary, path));
+        try!(sync_directory(path));
+        Ok(())
+    }
+
+    /// Loads a checkpoint saved with the precision `F`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CheckpointError> {
+        let mut reader = BufReader::new(try!(File::open(path)));
+
+        let precision: Precision = try!(decode_from(&mut reader, SizeLimit::Infinite));
+        if precision != F::precision() {
+            return Err(CheckpointError::Storage(StorageError::PrecisionMismatch(F::precision(), precision)));
+        }
+
+        Ok(try!(decode_from(&mut reader, SizeLimit::Infinite)))
+    }
+}
+
+/// Flushes the directory entry of a renamed file to disk
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new(".")
+    };
+    try!(File::open(directory)).sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Saves numbered checkpoints into a directory and deletes the oldest ones
+pub struct CheckpointManager {
+    pub directory: PathBuf,
+
+    /// File names are `<prefix>-<epoch>.checkpoint`
+    pub prefix: String,
+
+    /// Amount of checkpoints kept, at least the one just saved is always kept
+    pub keep: usize
+}
+
+impl CheckpointManager {
+    /// `keep` is raised to 1 so the newest checkpoint is never deleted
+    pub fn new<P: Into<PathBuf>>(directory: P, keep: usize) -> Self {
+        CheckpointManager {
+            directory: directory.into(),
+            prefix: "checkpoint".to_string(),
+            keep: cmp::max(keep, 1)
+        }
+    }
+
+    /// Path of the checkpoint after the given amount of epochs
+    pub fn path(&self, epoch: usize) -> PathBuf {
+        self.directory.join(format!("{}-{:08}.checkpoint", self.prefix, epoch))
+    }
+
+    /// Saved checkpoints, oldest first
+    pub fn checkpoints(&self) -> Result<Vec<(usize, PathBuf)>, CheckpointError> {
+        let start = format!("{}-", self.prefix);
+        let mut result = Vec::new();
+
+        for entry in try!(fs::read_dir(&self.directory)) {
+            let path = try!(entry).path();
+            let epoch = path.file_name().and_then(|name| name.to_str()).and_then(|name| {
+                if name.starts_with(&start) && name.ends_with(".checkpoint") {
+                    name[start.len()..name.len() - ".checkpoint".len()].parse::<usize>().ok()
+                } else {
+                    None
+                }
+            });
+
+            if let Some(epoch) = epoch {
+                result.push((epoch, path));
+            }
+        }
+
+        result.sort();
+        Ok(result)
+    }
+
+    /// Saves a checkpoint and deletes all but the newest `keep` ones
+    ///
+    /// Checkpoints of later epochs are deleted as well, they belong to a run that was resumed
+    /// from an earlier checkpoint. The saved checkpoint is therefore always the latest one.
+    pub fn save<F: Float, O: Encodable + Decodable>(&self, checkpoint: &Checkpoint<F, O>) -> Result<PathBuf, CheckpointError> {
+        try!(fs::create_dir_all(&self.directory));
+
+        let epoch = checkpoint.epoch();
+        let path = self.path(epoch);
+        try!(checkpoint.save(&path));
+
+        let mut kept = Vec::new();
+        for (saved, old) in try!(self.checkpoints()) {
+            if saved > epoch {
+                try!(fs::remove_file(&old));
+            } else {
+                kept.push(old);
+            }
+        }
+
+        let keep = cmp::max(self.keep, 1);
+        if kept.len() > keep {
+            for old in &kept[..kept.len() - keep] {
+                try!(fs::remove_file(old));
+            }
+        }
+
+        Ok(path)
+    }
+
+    /// Loads the newest checkpoint, `None` when there is none
+    pub fn load_latest<F: Float, O: Encodable + Decodable>(&self) -> Result<Option<Checkpoint<F, O>>, CheckpointError> {
+        if !self.directory.exists() {
+            return Ok(None);
+        }
+
+        match try!(self.checkpoints()).pop() {
+            Some((_, path)) => Checkpoint::load(path).map(Some),
+            None => Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use rand::{Rng, SeedableRng, StdRng};
+
+    use neural_network::differential::random_network;
+    use training::dataset::Dataset;
+    use training::early_stopping::EarlyStopping;
+    use training::loss::MeanSquaredError;
+    use training::optimizer::Adam;
+    use training::schedule::ReduceOnPlateau;
+    use training::trainer::{Trainer, TrainerOptions};
+
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("deeplearning-checkpoint-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn data(network: &NeuralNetwork, rng: &mut StdRng, samples: usize) -> Dataset<f64> {
+        let outputs = network.layers()[network.layers().len() - 1].len();
+        let inputs = (0..samples).map(|_| (0..network.inputs()).map(|_| rng.gen_range(-1.0, 1.0)).collect()).collect();
+        let targets = (0..samples).map(|_| (0..outputs).map(|_| rng.gen_range(-1.0, 1.0)).collect()).collect();
+        Dataset::new(inputs, targets).unwrap()
+    }
+
+    fn trainer(network: &NeuralNetwork, epochs: usize) -> Trainer<f64, MeanSquaredError, Adam> {
+        let mut options = TrainerOptions::defaults();
+        options.epochs = epochs;
+        options.batch_size = 4;
+        options.seed = Some(3);
+
+        let mut trainer = Trainer::new(network.clone(), MeanSquaredError, Adam::new(0.01), Some(options));
+        trainer.set_schedule(ReduceOnPlateau::new(0.5, 1));
+        trainer.set_early_stopping(EarlyStopping::new(100, 0.0));
+        trainer
+    }
+
+    fn assert_same_network(a: &NeuralNetwork, b: &NeuralNetwork) {
+        assert_eq!(a.layers().len(), b.layers().len());
+        for (layer_a, layer_b) in a.layers().iter().zip(b.layers().iter()) {
+            for (neuron_a, neuron_b) in layer_a.iter().zip(layer_b.iter()) {
+                assert_eq!(neuron_a.weights(), neuron_b.weights());
+                assert_eq!(neuron_a.bias(), neuron_b.bias());
+            }
+        }
+    }
+
+    fn losses(history: &History) -> Vec<(f64, Option<f64>, f64)> {
+        history.epochs.iter().map(|record| (record.train_loss, record.validation_loss, record.learning_rate)).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let seed: &[usize] = &[1];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let network: NeuralNetwork = random_network(&mut rng, 3, 4);
+        let train = data(&network, &mut rng, 16);
+
+        let mut trainer = trainer(&network, 3);
+        trainer.fit(&train, None).unwrap();
+        let checkpoint = trainer.checkpoint();
+
+        let directory = directory("round-trip");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("single.checkpoint");
+        checkpoint.save(&path).unwrap();
+
+        let loaded: Checkpoint<f64, Adam> = Checkpoint::load(&path).unwrap();
+        assert_same_network(&loaded.network, &checkpoint.network);
+        assert_eq!(loaded.optimizer.steps(), checkpoint.optimizer.steps());
+        assert_eq!(loaded.seed, checkpoint.seed);
+        assert_eq!(loaded.base_learning_rate, checkpoint.base_learning_rate);
+        assert_eq!(loaded.schedule_state, checkpoint.schedule_state);
+        assert_eq!(loaded.early_stopping.unwrap().best_epoch(), checkpoint.early_stopping.unwrap().best_epoch());
+        assert_eq!(losses(&loaded.history), losses(&checkpoint.history));
+
+        // the precision is checked before decoding
+        assert!(Checkpoint::<f32, Adam<f32>>::load(&path).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rotation() {
+        let seed: &[usize] = &[2];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let network: NeuralNetwork = random_network(&mut rng, 2, 4);
+        let train = data(&network, &mut rng, 8);
+
+        let mut trainer = trainer(&network, 5);
+        trainer.fit(&train, None).unwrap();
+        let full = trainer.checkpoint();
+        let at = |epoch: usize| {
+            let mut checkpoint = full.clone();
+            checkpoint.history.epochs.truncate(epoch);
+            checkpoint
+        };
+
+        let directory = directory("rotation");
+        let manager = CheckpointManager::new(&directory, 2);
+        for epoch in 1..5 {
+            manager.save(&at(epoch)).unwrap();
+        }
+        let epochs = |manager: &CheckpointManager| manager.checkpoints().unwrap().iter().map(|&(epoch, _)| epoch).collect::<Vec<_>>();
+        assert_eq!(epochs(&manager), vec![3, 4]);
+
+        // resumed from an earlier epoch, the later checkpoints are stale
+        manager.save(&at(2)).unwrap();
+        assert_eq!(epochs(&manager), vec![2]);
+        let latest: Checkpoint<f64, Adam> = manager.load_latest().unwrap().unwrap();
+        assert_eq!(latest.epoch(), 2);
+
+        // the checkpoint just saved survives keep == 0
+        let mut keep_none = CheckpointManager::new(&directory, 0);
+        keep_none.keep = 0;
+        keep_none.save(&at(5)).unwrap();
+        assert_eq!(epochs(&keep_none), vec![5]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn interrupted_run_matches_uninterrupted() {
+        let seed: &[usize] = &[3];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let network: NeuralNetwork = random_network(&mut rng, 3, 6);
+        let train = data(&network, &mut rng, 24);
+        let validation = data(&network, &mut rng, 8);
+
+        let straight_directory = directory("straight");
+        let mut straight = trainer(&network, 12);
+        straight.fit_checkpointed(&train, Some(&validation), &CheckpointManager::new(&straight_directory, 2), 1).unwrap();
+
+        // the first run stops after 5 epochs, the second one starts over with the same call
+        let interrupted_directory = directory("interrupted");
+        let manager = CheckpointManager::new(&interrupted_directory, 2);
+        trainer(&network, 5).fit_checkpointed(&train, Some(&validation), &manager, 1).unwrap();
+        let mut resumed = trainer(&network, 12);
+        resumed.fit_checkpointed(&train, Some(&validation), &manager, 1).unwrap();
+
+        assert_eq!(losses(resumed.history()), losses(straight.history()));
+        assert_same_network(resumed.network(), straight.network());
+
+        fs::remove_dir_all(&straight_directory).unwrap();
+        fs::remove_dir_all(&interrupted_directory).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+//! Samples used for training and evaluation
+
+use std::error::Error;
+use std::fmt;
+
+use neural_network::float::Float;
+
+/// Errors for Dataset
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatasetError {
+    /// Amount of inputs, targets or sample weights differ
+    ///  (amount_of_inputs, amount_of_targets_or_weights)
+    LengthMismatch(usize, usize),
+
+    /// A sample has another amount of inputs or targets than the first sample
+    ///  (sample_index)
+    ShapeMismatch(usize)
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DatasetError::LengthMismatch(inputs, other) => write!(f, "got {} inputs but {} targets or weights", inputs, other),
+            DatasetError::ShapeMismatch(sample) => write!(f, "sample {} has a different shape", sample)
+        }
+    }
+}
+
+impl Error for DatasetError {
+    fn description(&self) -> &str {
+        "dataset error"
+    }
+}
+
+/// Input and target vectors, optionally with a weight per sample
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct Dataset<F: Float = f64> {
+    inputs: Vec<Vec<F>>,
+    targets: Vec<Vec<F>>,
+    weights: Option<Vec<F>>
+}
+
+impl<F: Float> Dataset<F> {
+    /// Creates a dataset, every sample needs the same amount of inputs and targets
+    pub fn new(inputs: Vec<Vec<F>>, targets: Vec<Vec<F>>) -> Result<Self, DatasetError> {
+        if inputs.len() != targets.len() {
+            return Err(DatasetError::LengthMismatch(inputs.len(), targets.len()));
+        }
+
+        for index in 1..inputs.len() {
+            if inputs[index].len() != inputs[0].len() || targets[index].len() != targets[0].len() {
+                return Err(DatasetError::ShapeMismatch(index));
+            }
+        }
+
+        Ok(Dataset {
+            inputs: inputs,
+            targets: targets,
+            weights: None
+        })
+    }
+
+    /// Weights every sample, losses become weighted means
+    pub fn with_weights(mut self, weights: Vec<F>) -> Result<Self, DatasetError> {
+        if weights.len() != self.inputs.len() {
+            return Err(DatasetError::LengthMismatch(self.inputs.len(), weights.len()));
+        }
+
+        self.weights = Some(weights);
+        Ok(self)
+    }
+
+    /// Amount of samples
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Amount of inputs per sample
+    pub fn input_len(&self) -> usize {
+        self.inputs.first().map_or(0, |inputs| inputs.len())
+    }
+
+    /// Amount of targets per sample
+    pub fn target_len(&self) -> usize {
+        self.targets.first().map_or(0, |targets| targets.len())
+    }
+
+    pub fn inputs(&self, index: usize) -> &[F] {
+        &self.inputs[index]
+    }
+
+    pub fn targets(&self, index: usize) -> &[F] {
+        &self.targets[index]
+    }
+
+    /// Weight of a sample, 1 when no weights were given
+    pub fn weight(&self, index: usize) -> F {
+        match self.weights {
+            Some(ref weights) => weights[index],
+            None => F::one()
+        }
+    }
+
+    /// Copies the given samples into a new dataset
+    pub fn select(&self, indices: &[usize]) -> Self {
+        Dataset {
+            inputs: indices.iter().map(|i| self.inputs[*i].clone()).collect(),
+            targets: indices.iter().map(|i| self.targets[*i].clone()).collect(),
+            weights: self.weights.as_ref().map(|weights| indices.iter().map(|i| weights[*i]).collect())
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Stopping training once a monitored value stopped improving
+
+use std::cmp;
+
+use training::trainer::EpochRecord;
+
+/// Value watched by `EarlyStopping`
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub enum Monitor {
+    /// Validation loss, the training loss is used when training without validation
+    ValidationLoss,
+
+    TrainLoss,
+
+    /// A metric where lower values are better
+    ///  (metric_name)
+    Minimize(String),
+
+    /// A metric where higher values are better
+    ///  (metric_name)
+    Maximize(String)
+}
+
+impl Monitor {
+    /// Monitored value of an epoch, negated for `Maximize` so lower is always better
+    fn value(&self, record: &EpochRecord) -> Option<f64> {
+        let metric = |name: &str| record.metrics.iter().find(|metric| metric.0 == name).map(|metric| metric.1);
+
+        match *self {
+            Monitor::ValidationLoss => Some(record.validation_loss.unwrap_or(record.train_loss)),
+            Monitor::TrainLoss => Some(record.train_loss),
+            Monitor::Minimize(ref name) => metric(name),
+            Monitor::Maximize(ref name) => metric(name).map(|value| -value)
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct EarlyStopping {
+    pub monitor: Monitor,
+
+    /// Training stops once the monitored value has not improved for this many epochs in a row,
+    /// counted like `StopRule::HasNotImprovedSince`. 0 counts as 1.
+    pub patience: usize,
+
+    /// Smallest change of the monitored value that counts as improvement
+    pub min_delta: f64,
+
+    /// Restores the weights of the best epoch when training stops
+    pub restore_best: bool,
+
+    /// Best monitored value and its epoch
+    best: Option<(f64, usize)>,
+    waiting: usize
+}
+
+impl EarlyStopping {
+    /// Watches the validation loss and restores the best weights
+    pub fn new(patience: usize, min_delta: f64) -> Self {
+        EarlyStopping {
+            monitor: Monitor::ValidationLoss,
+            patience: patience,
+            min_delta: min_delta,
+            restore_best: true,
+            best: None,
+            waiting: 0
+        }
+    }
+
+    /// Best monitored value so far, as reported by the epoch
+    pub fn best_value(&self) -> Option<f64> {
+        self.best.map(|(value, _)| match self.monitor {
+            Monitor::Maximize(_) => -value,
+            _ => value
+        })
+    }
+
+    /// Epoch with the best monitored value so far
+    pub fn best_epoch(&self) -> Option<usize> {
+        self.best.map(|(_, epoch)| epoch)
+    }
+
+    /// Epochs since the last improvement
+    pub fn waiting(&self) -> usize {
+        self.waiting
+    }
+
+    /// Records an epoch, returns whether it is the best one so far
+    pub fn update(&mut self, record: &EpochRecord) -> bool {
+        let value = match self.monitor.value(record) {
+            Some(value) => value,
+            None => {
+                warn!(target: "early_stopping", "Epoch {} does not contain the monitored value {:?}", record.epoch, self.monitor);
+                self.waiting += 1;
+                return false;
+            }
+        };
+
+        let improved = match self.best {
+            Some((best, _)) => value < best - self.min_delta,
+            None => true
+        };
+
+        if improved {
+            self.best = Some((value, record.epoch));
+            self.waiting = 0;
+        } else {
+            self.waiting += 1;
+        }
+        improved
+    }
+
+    /// Whether `patience` epochs passed without improvement
+    pub fn should_stop(&self) -> bool {
+        self.waiting >= cmp::max(self.patience, 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use neural_network::NeuralNetwork;
+    use neural_network::differential::random_network;
+    use rand::{Rng, SeedableRng, StdRng};
+
+    use training::dataset::Dataset;
+    use training::loss::MeanSquaredError;
+    use training::optimizer::Sgd;
+    use training::trainer::{Trainer, TrainerOptions};
+
+    use super::*;
+
+    #[test]
+    fn stops_after_patience_flat_epochs() {
+        let seed: &[usize] = &[1];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let network: NeuralNetwork = random_network(&mut rng, 2, 4);
+        let outputs = network.layers()[network.layers().len() - 1].len();
+
+        let inputs = (0..8).map(|_| (0..network.inputs()).map(|_| rng.gen_range(-1.0, 1.0)).collect()).collect();
+        let targets = (0..8).map(|_| (0..outputs).map(|_| rng.gen_range(-1.0, 1.0)).collect()).collect();
+        let data = Dataset::new(inputs, targets).unwrap();
+
+        for patience in 1..5 {
+            let mut options = TrainerOptions::defaults();
+            options.seed = Some(1);
+
+            // a learning rate of 0 keeps the loss flat after the first epoch
+            let mut trainer = Trainer::new(network.clone(), MeanSquaredError, Sgd::new(0.0), Some(options));
+            trainer.set_early_stopping(EarlyStopping::new(patience, 0.0));
+            trainer.fit(&data, None).unwrap();
+
+            assert_eq!(trainer.history().epochs.len(), patience + 1);
+        }
+    }
+}
@@ -0,0 +1,475 @@
+//! Loss functions with their gradients
+//!
+//! A loss compares the outputs of a network for one sample against the targets. Element-wise
+//! losses average over the outputs, cross-entropies and KL divergence sum over them. Over a
+//! dataset losses are averaged, weighted by the sample weights. Since evolution maximizes
+//! fitness, `fitness` returns the negated loss.
+
+use std::error::Error;
+use std::fmt;
+
+use neural_network::*;
+use neural_network::float::Float;
+use neural_network::cpu::{CpuInstance, CpuInstanceError};
+use neural_network::gradient::{Gradient, GradientError};
+
+use training::dataset::Dataset;
+
+/// Trait for loss functions
+pub trait Loss<F: Float = f64> {
+    /// Loss of a single sample
+    fn value(&self, outputs: &[F], targets: &[F]) -> F;
+
+    /// Loss of a single sample, writes its gradient with respect to the outputs into `gradient`
+    fn gradient(&self, outputs: &[F], targets: &[F], gradient: &mut [F]) -> F;
+}
+
+/// Errors when applying a loss to a network
+#[derive(Debug)]
+pub enum LossError {
+    /// Amount of targets does not match the network outputs
+    ///  (amount_of_targets)
+    TargetMismatch(usize),
+
+    /// Sample weights sum up to zero
+    ZeroWeight,
+
+    /// The network could not be calculated
+    Instance(CpuInstanceError),
+
+    /// The gradient could not be calculated
+    Gradient(GradientError)
+}
+
+impl fmt::Display for LossError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LossError::TargetMismatch(len) => write!(f, "got {} targets", len),
+            LossError::ZeroWeight => write!(f, "sample weights sum up to zero"),
+            LossError::Instance(ref err) => write!(f, "{}", err),
+            LossError::Gradient(ref err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl Error for LossError {
+    fn description(&self) -> &str {
+        "loss error"
+    }
+}
+
+impl From<CpuInstanceError> for LossError {
+    fn from(err: CpuInstanceError) -> Self {
+        LossError::Instance(err)
+    }
+}
+
+impl From<GradientError> for LossError {
+    fn from(err: GradientError) -> Self {
+        LossError::Gradient(err)
+    }
+}
+
+/// Smallest probability passed to `ln`
+fn clamp<F: Float>(p: F) -> F {
+    p.max(F::from_f64(1e-12))
+}
+
+fn sigmoid<F: Float>(x: F) -> F {
+    F::one() / (F::one() + (-x).exp())
+}
+
+/// Mean squared error
+#[derive(Copy, Clone, Debug)]
+pub struct MeanSquaredError;
+
+impl<F: Float> Loss<F> for MeanSquaredError {
+    fn value(&self, outputs: &[F], targets: &[F]) -> F {
+        let mut sum = F::zero();
+        for (o, t) in outputs.iter().zip(targets.iter()) {
+            sum += (*o - *t) * (*o - *t);
+        }
+        sum / F::from_f64(outputs.len() as f64)
+    }
+
+    fn gradient(&self, outputs: &[F], targets: &[F], gradient: &mut [F]) -> F {
+        let n = F::from_f64(outputs.len() as f64);
+        for i in 0..outputs.len() {
+            gradient[i] = F::from_f64(2.0) * (outputs[i] - targets[i]) / n;
+        }
+        self.value(outputs, targets)
+    }
+}
+
+/// Mean absolute error
+#[derive(Copy, Clone, Debug)]
+pub struct MeanAbsoluteError;
+
+impl<F: Float> Loss<F> for MeanAbsoluteError {
+    fn value(&self, outputs: &[F], targets: &[F]) -> F {
+        let mut sum = F::zero();
+        for (o, t) in outputs.iter().zip(targets.iter()) {
+            sum += (*o - *t).abs();
+        }
+        sum / F::from_f64(outputs.len() as f64)
+    }
+
+    fn gradient(&self, outputs: &[F], targets: &[F], gradient: &mut [F]) -> F {
+        let n = F::from_f64(outputs.len() as f64);
+        for i in 0..outputs.len() {
+            let diff = outputs[i] - targets[i];
+            gradient[i] = if diff > F::zero() {
+                F::one() / n
+            } else if diff < F::zero() {
+                -F::one() / n
+            } else {
+                F::zero()
+            };
+        }
+        self.value(outputs, targets)
+    }
+}
+
+/// Huber loss, quadratic within `delta` of the target and linear beyond
+#[derive(Copy, Clone, Debug)]
+pub struct Huber {
+    pub delta: f64
+}
+
+impl<F: Float> Loss<F> for Huber {
+    fn value(&self, outputs: &[F], targets: &[F]) -> F {
+        let delta = F::from_f64(self.delta);
+        let half = F::from_f64(0.5);
+
+        let mut sum = F::zero();
+        for (o, t) in outputs.iter().zip(targets.iter()) {
+            let diff = (*o - *t).abs();
+            sum += if diff <= delta { half * diff * diff } else { delta * (diff - half * delta) };
+        }
+        sum / F::from_f64(outputs.len() as f64)
+    }
+
+    fn gradient(&self, outputs: &[F], targets: &[F], gradient: &mut [F]) -> F {
+        let delta = F::from_f64(self.delta);
+        let n = F::from_f64(outputs.len() as f64);
+        for i in 0..outputs.len() {
+            let diff = outputs[i] - targets[i];
+            gradient[i] = diff.max(-delta).min(delta) / n;
+        }
+        self.value(outputs, targets)
+    }
+}
+
+/// Binary cross-entropy, every output is an independent probability
+#[derive(Copy, Clone, Debug)]
+pub struct BinaryCrossEntropy {
+    /// Outputs are logits and the sigmoid is applied by the loss, the output layer should
+    /// use `NeuronType::Identity`. Fusing both is numerically stable.
+    pub from_logits: bool
+}
+
+impl BinaryCrossEntropy {
+    /// Expects probabilities, e.g. from a `NeuronType::SigMoid` output layer
+    pub fn new() -> Self {
+        BinaryCrossEntropy { from_logits: false }
+    }
+
+    /// Expects logits and applies the sigmoid itself
+    pub fn with_sigmoid() -> Self {
+        BinaryCrossEntropy { from_logits: true }
+    }
+}
+
+impl<F: Float> Loss<F> for BinaryCrossEntropy {
+    fn value(&self, outputs: &[F], targets: &[F]) -> F {
+        let mut sum = F::zero();
+        for (o, t) in outputs.iter().zip(targets.iter()) {
+            let (o, t) = (*o, *t);
+            sum += if self.from_logits {
+                // max(x, 0) - x t + ln(1 + e^-|x|)
+                o.max(F::zero()) - o * t + (F::one() + (-o.abs()).exp()).ln()
+            } else {
+                -(t * clamp(o).ln() + (F::one() - t) * clamp(F::one() - o).ln())
+            };
+        }
+        sum
+    }
+
+    fn gradient(&self, outputs: &[F], targets: &[F], gradient: &mut [F]) -> F {
+        for i in 0..outputs.len() {
+            let (o, t) = (outputs[i], targets[i]);
+            gradient[i] = if self.from_logits {
+                sigmoid(o) - t
+            } else {
+                -t / clamp(o) + (F::one() - t) / clamp(F::one() - o)
+            };
+        }
+        self.value(outputs, targets)
+    }
+}
+
+/// Categorical cross-entropy, the outputs are a distribution over classes
+#[derive(Copy, Clone, Debug)]
+pub struct CategoricalCrossEntropy {
+    /// Outputs are logits and the softmax is applied by the loss, the output layer should use
+    /// `NeuronType::Identity`. Fusing both is numerically stable.
+    pub from_logits: bool
+}
+
+impl CategoricalCrossEntropy {
+    /// Expects probabilities that sum up to one
+    pub fn new() -> Self {
+        CategoricalCrossEntropy { from_logits: false }
+    }
+
+    /// Expects logits and applies the softmax itself
+    pub fn with_softmax() -> Self {
+        CategoricalCrossEntropy { from_logits: true }
+    }
+}
+
+/// Logarithm of the sum of exponentials, shifted by the maximum
+fn log_sum_exp<F: Float>(values: &[F]) -> F {
+    let max = values.iter().fold(values[0], |max, v| max.max(*v));
+    let sum = values.iter().fold(F::zero(), |sum, v| sum + (*v - max).exp());
+    max + sum.ln()
+}
+
+/// Softmax of the given logits
+pub fn softmax<F: Float>(logits: &[F], outputs: &mut [F]) {
+    let lse = log_sum_exp(logits);
+    for (o, x) in outputs.iter_mut().zip(logits.iter()) {
+        *o = (*x - lse).exp();
+    }
+}
+
+impl<F: Float> Loss<F> for CategoricalCrossEntropy {
+    fn value(&self, outputs: &[F], targets: &[F]) -> F {
+        let lse = if self.from_logits { log_sum_exp(outputs) } else { F::zero() };
+
+        let mut sum = F::zero();
+        for (o, t) in outputs.iter().zip(targets.iter()) {
+            let log_p = if self.from_logits { *o - lse } else { clamp(*o).ln() };
+            sum -= *t * log_p;
+        }
+        sum
+    }
+
+    fn gradient(&self, outputs: &[F], targets: &[F], gradient: &mut [F]) -> F {
+        if self.from_logits {
+            // softmax(x) * sum(t) - t, reduces to softmax(x) - t for distributions
+            let total = targets.iter().fold(F::zero(), |sum, t| sum + *t);
+            softmax(outputs, gradient);
+            for i in 0..outputs.len() {
+                gradient[i] = gradient[i] * total - targets[i];
+            }
+        } else {
+            for i in 0..outputs.len() {
+                gradient[i] = -targets[i] / clamp(outputs[i]);
+            }
+        }
+        self.value(outputs, targets)
+    }
+}
+
+/// Hinge loss for targets of -1 and 1, targets of 0 count as -1
+#[derive(Copy, Clone, Debug)]
+pub struct Hinge;
+
+fn sign<F: Float>(target: F) -> F {
+    if target > F::zero() { F::one() } else { -F::one() }
+}
+
+impl<F: Float> Loss<F> for Hinge {
+    fn value(&self, outputs: &[F], targets: &[F]) -> F {
+        let mut sum = F::zero();
+        for (o, t) in outputs.iter().zip(targets.iter()) {
+            sum += (F::one() - sign(*t) * *o).max(F::zero());
+        }
+        sum / F::from_f64(outputs.len() as f64)
+    }
+
+    fn gradient(&self, outputs: &[F], targets: &[F], gradient: &mut [F]) -> F {
+        let n = F::from_f64(outputs.len() as f64);
+        for i in 0..outputs.len() {
+            let t = sign(targets[i]);
+            gradient[i] = if t * outputs[i] < F::one() { -t / n } else { F::zero() };
+        }
+        self.value(outputs, targets)
+    }
+}
+
+/// Kullback-Leibler divergence of the outputs from the target distribution
+#[derive(Copy, Clone, Debug)]
+pub struct KlDivergence;
+
+impl<F: Float> Loss<F> for KlDivergence {
+    fn value(&self, outputs: &[F], targets: &[F]) -> F {
+        let mut sum = F::zero();
+        for (o, t) in outputs.iter().zip(targets.iter()) {
+            if *t > F::zero() {
+                sum += *t * (t.ln() - clamp(*o).ln());
+            }
+        }
+        sum
+    }
+
+    fn gradient(&self, outputs: &[F], targets: &[F], gradient: &mut [F]) -> F {
+        for i in 0..outputs.len() {
+            gradient[i] = -targets[i] / clamp(outputs[i]);
+        }
+        self.value(outputs, targets)
+    }
+}
+
+/// Weighted mean loss of the network over a dataset
+pub fn mean<F: Float, L: Loss<F>>(loss: &L, network: &NeuralNetwork<F>, data: &Dataset<F>) -> Result<F, LossError> {
+    let mut instance: CpuInstance<F> = try!(Instance::new(network));
+    if data.target_len() != instance.output_len() && !data.is_empty() {
+        return Err(LossError::TargetMismatch(data.target_len()));
+    }
+
+    let mut outputs = vec![F::zero(); instance.output_len()];
+    let (mut sum, mut total) = (F::zero(), F::zero());
+
+    for index in 0..data.len() {
+        try!(instance.calculate(data.inputs(index), &mut outputs));
+        let weight = data.weight(index);
+        sum += weight * loss.value(&outputs, data.targets(index));
+        total += weight;
+    }
+
+    if total == F::zero() {
+        return Err(LossError::ZeroWeight);
+    }
+    Ok(sum / total)
+}
+
+/// Weighted mean loss and its gradient over the given samples of a dataset
+pub fn gradient<F: Float, L: Loss<F>>(loss: &L, network: &NeuralNetwork<F>, data: &Dataset<F>, indices: &[usize]) -> Result<(F, Gradient<F>), LossError> {
+    let mut result = Gradient::zeros(network);
+    let mut output_gradient = vec![F::zero(); data.target_len()];
+    let (mut sum, mut total) = (F::zero(), F::zero());
+
+    for &index in indices {
+        let trace = try!(network.forward_trace(data.inputs(index)));
+        if trace.outputs().len() != data.target_len() {
+            return Err(LossError::TargetMismatch(data.target_len()));
+        }
+
+        let weight = data.weight(index);
+        sum += weight * loss.gradient(trace.outputs(), data.targets(index), &mut output_gradient);
+        for g in output_gradient.iter_mut() {
+            *g *= weight;
+        }
+
+        try!(network.backpropagate(&trace, &output_gradient, &mut result));
+        total += weight;
+    }
+
+    if total == F::zero() {
+        return Err(LossError::ZeroWeight);
+    }
+    result.scale(F::one() / total);
+    Ok((sum / total, result))
+}
+
+/// Negated mean loss, usable as rating in `genetic_evolution`
+///
+/// ```ignore
+/// let mut rate = |network: &mut NeuralNetwork| loss::fitness(&MeanSquaredError, network, &data).unwrap();
+/// ```
+pub fn fitness<F: Float, L: Loss<F>>(loss: &L, network: &NeuralNetwork<F>, data: &Dataset<F>) -> Result<f64, LossError> {
+    mean(loss, network, data).map(|value| -value.to_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, StdRng};
+
+    const EPSILON: f64 = 1e-6;
+
+    fn random(rng: &mut StdRng, len: usize, min: f64, max: f64) -> Vec<f64> {
+        (0..len).map(|_| rng.gen_range(min, max)).collect()
+    }
+
+    /// Compares `gradient` against central differences of `value`
+    fn check<L: Loss>(name: &str, loss: &L, outputs: &[f64], targets: &[f64]) {
+        let mut gradient = vec![0.0; outputs.len()];
+        let value = loss.gradient(outputs, targets, &mut gradient);
+        assert_eq!(value, loss.value(outputs, targets), "{}", name);
+
+        for i in 0..outputs.len() {
+            let mut shifted = outputs.to_vec();
+            shifted[i] = outputs[i] + EPSILON;
+            let plus = loss.value(&shifted, targets);
+            shifted[i] = outputs[i] - EPSILON;
+            let minus = loss.value(&shifted, targets);
+
+            let numeric = (plus - minus) / (2.0 * EPSILON);
+            assert!((numeric - gradient[i]).abs() <= 1e-6 * numeric.abs().max(1.0), "{}: output {}: {} vs {}", name, i, gradient[i], numeric);
+        }
+    }
+
+    /// Random outputs that keep a distance to the kinks at `targets[i] + kink`
+    fn away_from(rng: &mut StdRng, targets: &[f64], kinks: &[f64], min: f64, max: f64) -> Vec<f64> {
+        targets.iter().map(|t| loop {
+            let o = rng.gen_range(min, max);
+            if kinks.iter().all(|k| (o - t - k).abs() > 1e-3) {
+                break o;
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn gradients_match_differences() {
+        let seed: &[usize] = &[1];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+
+        for _ in 0..20 {
+            let len = rng.gen_range(1, 6);
+            let targets = random(&mut rng, len, -2.0, 2.0);
+
+            check("mse", &MeanSquaredError, &random(&mut rng, len, -2.0, 2.0), &targets);
+            check("mae", &MeanAbsoluteError, &away_from(&mut rng, &targets, &[0.0], -2.0, 2.0), &targets);
+            check("huber", &Huber { delta: 0.5 }, &away_from(&mut rng, &targets, &[-0.5, 0.5], -2.0, 2.0), &targets);
+
+            // hinge kinks where the output times the sign of its target reaches 1
+            let signs: Vec<f64> = targets.iter().map(|t| if *t > 0.0 { 1.0 } else { -1.0 }).collect();
+            check("hinge", &Hinge, &away_from(&mut rng, &signs, &[0.0], -2.0, 2.0), &targets);
+
+            let probabilities = random(&mut rng, len, 0.05, 0.95);
+            let labels = random(&mut rng, len, 0.0, 1.0);
+            check("bce", &BinaryCrossEntropy::new(), &probabilities, &labels);
+            check("bce with sigmoid", &BinaryCrossEntropy::with_sigmoid(), &random(&mut rng, len, -3.0, 3.0), &labels);
+
+            let mut distribution = random(&mut rng, len, 0.0, 1.0);
+            let total = distribution.iter().fold(0.0, |sum, t| sum + t);
+            for t in distribution.iter_mut() {
+                *t /= total;
+            }
+            distribution[0] = 0.0;
+
+            check("cce", &CategoricalCrossEntropy::new(), &probabilities, &distribution);
+            check("cce with softmax", &CategoricalCrossEntropy::with_softmax(), &random(&mut rng, len, -3.0, 3.0), &distribution);
+            check("cce with softmax, unnormalized targets", &CategoricalCrossEntropy::with_softmax(), &random(&mut rng, len, -3.0, 3.0), &labels);
+            check("kl", &KlDivergence, &probabilities, &distribution);
+        }
+    }
+
+    #[test]
+    fn fused_losses_match_explicit_activations() {
+        let logits = [-1.5, 0.2, 2.0];
+        let targets = [0.0, 1.0, 0.0];
+
+        let mut probabilities = [0.0; 3];
+        softmax(&logits, &mut probabilities);
+        let explicit: f64 = CategoricalCrossEntropy::new().value(&probabilities, &targets);
+        assert!((CategoricalCrossEntropy::with_softmax().value(&logits, &targets) - explicit).abs() < 1e-12);
+
+        let probabilities: Vec<f64> = logits.iter().map(|x| sigmoid(*x)).collect();
+        let explicit: f64 = BinaryCrossEntropy::new().value(&probabilities, &targets);
+        assert!((BinaryCrossEntropy::with_sigmoid().value(&logits, &targets) - explicit).abs() < 1e-12);
+    }
+}
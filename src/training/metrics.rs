@@ -0,0 +1,525 @@
+//! Evaluation metrics for classification and regression
+//!
+//! `predict` runs any `Instance` over a dataset and collects outputs and targets, all metrics
+//! are computed from the resulting `Predictions`. Sample weights are ignored.
+//!
+//! For classification a sample belongs to the class of its largest value. Networks with a
+//! single output are binary classifiers, where values of at least 0.5 mean class 1.
+//!
+//! Metrics averaging over samples return `None` when there is nothing to average.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use neural_network::*;
+use neural_network::float::Float;
+use neural_network::cpu::{CpuInstance, CpuInstanceError};
+
+use training::dataset::Dataset;
+
+/// Outputs and targets of every sample
+#[derive(Clone, Debug, Default)]
+pub struct Predictions {
+    pub outputs: Vec<Vec<f64>>,
+    pub targets: Vec<Vec<f64>>
+}
+
+/// Runs an instance over every sample of a dataset
+pub fn predict<'a, F: Float, I: Instance<'a, F>>(instance: &mut I, data: &Dataset<F>) -> Result<Predictions, I::Error> {
+    let mut outputs = vec![F::zero(); instance.output_len()];
+    let mut predictions = Predictions::default();
+
+    for index in 0..data.len() {
+        try!(instance.calculate(data.inputs(index), &mut outputs));
+        predictions.outputs.push(outputs.iter().map(|o| o.to_f64()).collect());
+        predictions.targets.push(data.targets(index).iter().map(|t| t.to_f64()).collect());
+    }
+
+    Ok(predictions)
+}
+
+/// Runs a network over a dataset with a `CpuInstance`
+pub fn evaluate<F: Float>(network: &NeuralNetwork<F>, data: &Dataset<F>) -> Result<Predictions, CpuInstanceError> {
+    let mut instance: CpuInstance<F> = try!(Instance::new(network));
+    predict(&mut instance, data)
+}
+
+/// Index of the largest value, or the binary decision of a single value
+fn class_of(values: &[f64]) -> usize {
+    if values.len() == 1 {
+        return if values[0] >= 0.5 { 1 } else { 0 };
+    }
+
+    let mut best = 0;
+    for index in 1..values.len() {
+        if values[index] > values[best] {
+            best = index;
+        }
+    }
+    best
+}
+
+/// Area under the ROC curve of scores for binary labels, ties count half
+fn auc(scores: &[(f64, bool)]) -> Option<f64> {
+    let positives = scores.iter().filter(|&&(_, label)| label).count();
+    let negatives = scores.len() - positives;
+    if positives == 0 || negatives == 0 {
+        return None;
+    }
+
+    let mut sorted = scores.to_vec();
+    sorted.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+    // sum of the (average) ranks of all positives
+    let mut rank_sum = 0.0;
+    let mut start = 0;
+    while start < sorted.len() {
+        let mut end = start;
+        while end < sorted.len() && sorted[end].0 == sorted[start].0 {
+            end += 1;
+        }
+
+        let rank = (start + end + 1) as f64 / 2.0;
+        rank_sum += rank * sorted[start..end].iter().filter(|&&(_, label)| label).count() as f64;
+        start = end;
+    }
+
+    let positives = positives as f64;
+    Some((rank_sum - positives * (positives + 1.0) / 2.0) / (positives * negatives as f64))
+}
+
+impl Predictions {
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    /// Amount of classes, 2 for single outputs
+    pub fn classes(&self) -> usize {
+        match self.outputs.first() {
+            Some(outputs) if outputs.len() == 1 => 2,
+            Some(outputs) => outputs.len(),
+            None => 0
+        }
+    }
+
+    /// Mean over all samples, `None` without samples
+    fn sample_mean(&self, sum: f64) -> Option<f64> {
+        if self.is_empty() { None } else { Some(sum / self.len() as f64) }
+    }
+
+    /// Share of samples whose predicted class matches the target class
+    pub fn accuracy(&self) -> Option<f64> {
+        let correct = self.outputs.iter().zip(self.targets.iter()).filter(|&(o, t)| class_of(o) == class_of(t)).count();
+        self.sample_mean(correct as f64)
+    }
+
+    /// Share of samples whose target class is among the `k` largest outputs
+    ///
+    /// Equal outputs rank by class index like in `accuracy`, so `top_k_accuracy(1)` equals it.
+    pub fn top_k_accuracy(&self, k: usize) -> Option<f64> {
+        let correct = self.outputs.iter().zip(self.targets.iter()).filter(|&(o, t)| {
+            if o.len() == 1 {
+                return k >= 2 || (k == 1 && class_of(o) == class_of(t));
+            }
+
+            let target = class_of(t);
+            let ahead = o.iter().enumerate().filter(|&(class, v)| *v > o[target] || (*v == o[target] && class < target)).count();
+            ahead < k
+        }).count();
+        self.sample_mean(correct as f64)
+    }
+
+    /// Counts of actual (rows) against predicted (columns) classes
+    pub fn confusion_matrix(&self) -> ConfusionMatrix {
+        let classes = self.classes();
+        let mut counts = vec![vec![0; classes]; classes];
+        for (o, t) in self.outputs.iter().zip(self.targets.iter()) {
+            counts[class_of(t)][class_of(o)] += 1;
+        }
+        ConfusionMatrix { counts: counts }
+    }
+
+    /// Area under the ROC curve, averaged one-vs-rest over all classes with both labels present
+    pub fn roc_auc(&self) -> Option<f64> {
+        if self.classes() == 2 && self.outputs[0].len() == 1 {
+            let scores: Vec<(f64, bool)> = self.outputs.iter().zip(self.targets.iter()).map(|(o, t)| (o[0], class_of(t) == 1)).collect();
+            return auc(&scores);
+        }
+
+        let areas: Vec<f64> = (0..self.classes()).filter_map(|class| {
+            let scores: Vec<(f64, bool)> = self.outputs.iter().zip(self.targets.iter()).map(|(o, t)| (o[class], class_of(t) == class)).collect();
+            auc(&scores)
+        }).collect();
+
+        if areas.is_empty() {
+            None
+        } else {
+            Some(areas.iter().fold(0.0, |sum, a| sum + a) / areas.len() as f64)
+        }
+    }
+
+    /// Mean negative log-likelihood of the target class, outputs must be probabilities
+    pub fn log_loss(&self) -> Option<f64> {
+        let clamp = |p: f64| p.max(1e-15).min(1.0 - 1e-15);
+
+        let sum = self.outputs.iter().zip(self.targets.iter()).fold(0.0, |sum, (o, t)| {
+            if o.len() == 1 {
+                let p = clamp(o[0]);
+                sum - (t[0] * p.ln() + (1.0 - t[0]) * (1.0 - p).ln())
+            } else {
+                sum - o.iter().zip(t.iter()).fold(0.0, |s, (p, t)| s + t * clamp(*p).ln())
+            }
+        });
+        self.sample_mean(sum)
+    }
+
+    /// Differences between outputs and targets over all values
+    fn errors(&self) -> Vec<(f64, f64)> {
+        self.outputs.iter().zip(self.targets.iter())
+            .flat_map(|(o, t)| o.iter().cloned().zip(t.iter().cloned()))
+            .collect()
+    }
+
+    /// Mean of `error` over output and target pairs, `None` without pairs
+    fn error_mean<E: Fn(f64, f64) -> f64>(errors: &[(f64, f64)], error: E) -> Option<f64> {
+        if errors.is_empty() {
+            None
+        } else {
+            Some(errors.iter().fold(0.0, |sum, &(o, t)| sum + error(o, t)) / errors.len() as f64)
+        }
+    }
+
+    /// Mean squared error
+    pub fn mse(&self) -> Option<f64> {
+        Predictions::error_mean(&self.errors(), |o, t| (o - t) * (o - t))
+    }
+
+    /// Mean absolute error
+    pub fn mae(&self) -> Option<f64> {
+        Predictions::error_mean(&self.errors(), |o, t| (o - t).abs())
+    }
+
+    /// Coefficient of determination, averaged over all outputs
+    pub fn r2(&self) -> Option<f64> {
+        let width = self.targets.first().map_or(0, |t| t.len());
+        let count = self.len() as f64;
+        if width == 0 {
+            return None;
+        }
+
+        let sum = (0..width).fold(0.0, |sum, column| {
+            let mean = self.targets.iter().fold(0.0, |s, t| s + t[column]) / count;
+            let total = self.targets.iter().fold(0.0, |s, t| s + (t[column] - mean) * (t[column] - mean));
+            let residual = self.outputs.iter().zip(self.targets.iter()).fold(0.0, |s, (o, t)| s + (t[column] - o[column]) * (t[column] - o[column]));
+
+            sum + if total > 0.0 { 1.0 - residual / total } else if residual == 0.0 { 1.0 } else { 0.0 }
+        });
+        Some(sum / width as f64)
+    }
+
+    /// Mean absolute percentage error in percent, targets of zero are skipped
+    ///
+    /// `None` when every target is zero.
+    pub fn mape(&self) -> Option<f64> {
+        let errors: Vec<(f64, f64)> = self.errors().into_iter().filter(|&(_, t)| t != 0.0).collect();
+        Predictions::error_mean(&errors, |o, t| 100.0 * ((t - o) / t).abs())
+    }
+
+    pub fn classification_report(&self) -> ClassificationReport {
+        let matrix = self.confusion_matrix();
+        ClassificationReport {
+            samples: self.len(),
+            accuracy: self.accuracy(),
+            macro_f1: matrix.macro_f1(),
+            micro_f1: matrix.micro_f1(),
+            roc_auc: self.roc_auc(),
+            log_loss: self.log_loss(),
+            confusion_matrix: matrix
+        }
+    }
+
+    pub fn regression_report(&self) -> RegressionReport {
+        RegressionReport {
+            samples: self.len(),
+            mse: self.mse(),
+            mae: self.mae(),
+            r2: self.r2(),
+            mape: self.mape()
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfusionMatrix {
+    /// `counts[actual][predicted]`
+    pub counts: Vec<Vec<usize>>
+}
+
+impl ConfusionMatrix {
+    pub fn classes(&self) -> usize {
+        self.counts.len()
+    }
+
+    fn predicted(&self, class: usize) -> usize {
+        self.counts.iter().fold(0, |sum, row| sum + row[class])
+    }
+
+    fn actual(&self, class: usize) -> usize {
+        self.counts[class].iter().fold(0, |sum, c| sum + c)
+    }
+
+    /// Share of samples predicted as `class` that belong to it
+    pub fn precision(&self, class: usize) -> f64 {
+        let predicted = self.predicted(class);
+        if predicted == 0 { 0.0 } else { self.counts[class][class] as f64 / predicted as f64 }
+    }
+
+    /// Share of samples of `class` that were predicted as it
+    pub fn recall(&self, class: usize) -> f64 {
+        let actual = self.actual(class);
+        if actual == 0 { 0.0 } else { self.counts[class][class] as f64 / actual as f64 }
+    }
+
+    pub fn f1(&self, class: usize) -> f64 {
+        let (p, r) = (self.precision(class), self.recall(class));
+        if p + r == 0.0 { 0.0 } else { 2.0 * p * r / (p + r) }
+    }
+
+    /// Unweighted mean of a score of all classes, 0.0 without classes
+    fn macro_mean<S: Fn(usize) -> f64>(&self, score: S) -> f64 {
+        if self.classes() == 0 { 0.0 } else { (0..self.classes()).fold(0.0, |sum, class| sum + score(class)) / self.classes() as f64 }
+    }
+
+    /// Unweighted mean of the precision of all classes
+    pub fn macro_precision(&self) -> f64 {
+        self.macro_mean(|class| self.precision(class))
+    }
+
+    /// Unweighted mean of the recall of all classes
+    pub fn macro_recall(&self) -> f64 {
+        self.macro_mean(|class| self.recall(class))
+    }
+
+    /// Unweighted mean of the F1 scores of all classes
+    pub fn macro_f1(&self) -> f64 {
+        self.macro_mean(|class| self.f1(class))
+    }
+
+    /// F1 score over all decisions, equals accuracy for single-label classification
+    pub fn micro_f1(&self) -> f64 {
+        let total = (0..self.classes()).fold(0, |sum, class| sum + self.actual(class));
+        let correct = (0..self.classes()).fold(0, |sum, class| sum + self.counts[class][class]);
+        if total == 0 { 0.0 } else { correct as f64 / total as f64 }
+    }
+}
+
+impl fmt::Display for ConfusionMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{:>10}", "act\\pred"));
+        for class in 0..self.classes() {
+            try!(write!(f, " {:>8}", class));
+        }
+        try!(writeln!(f, "   precision   recall       f1"));
+
+        for class in 0..self.classes() {
+            try!(write!(f, "{:>10}", class));
+            for count in &self.counts[class] {
+                try!(write!(f, " {:>8}", count));
+            }
+            try!(writeln!(f, " {:>11.4} {:>8.4} {:>8.4}", self.precision(class), self.recall(class), self.f1(class)));
+        }
+        Ok(())
+    }
+}
+
+/// Writes a line of a report, `-` for `None`
+fn write_metric(f: &mut fmt::Formatter, label: &str, value: Option<f64>, precision: usize, unit: &str) -> fmt::Result {
+    match value {
+        Some(value) => writeln!(f, "{}{:.*}{}", label, precision, value, unit),
+        None => writeln!(f, "{}-", label)
+    }
+}
+
+/// Metrics of a classifier, averages are `None` without samples
+#[derive(Clone, Debug)]
+pub struct ClassificationReport {
+    pub samples: usize,
+    pub accuracy: Option<f64>,
+    pub macro_f1: f64,
+    pub micro_f1: f64,
+
+    /// `None` when a class has no positive or no negative samples
+    pub roc_auc: Option<f64>,
+    pub log_loss: Option<f64>,
+    pub confusion_matrix: ConfusionMatrix
+}
+
+impl fmt::Display for ClassificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "samples          {}", self.samples));
+        try!(write_metric(f, "accuracy         ", self.accuracy, 4, ""));
+        try!(writeln!(f, "macro precision  {:.4}", self.confusion_matrix.macro_precision()));
+        try!(writeln!(f, "macro recall     {:.4}", self.confusion_matrix.macro_recall()));
+        try!(writeln!(f, "macro f1         {:.4}", self.macro_f1));
+        try!(writeln!(f, "micro f1         {:.4}", self.micro_f1));
+        try!(write_metric(f, "roc auc          ", self.roc_auc, 4, ""));
+        try!(write_metric(f, "log loss         ", self.log_loss, 4, ""));
+        try!(writeln!(f, ""));
+        write!(f, "{}", self.confusion_matrix)
+    }
+}
+
+/// Metrics of a regression, averages are `None` without samples
+#[derive(Clone, Debug)]
+pub struct RegressionReport {
+    pub samples: usize,
+    pub mse: Option<f64>,
+    pub mae: Option<f64>,
+    pub r2: Option<f64>,
+
+    /// In percent, `None` when every target is zero
+    pub mape: Option<f64>
+}
+
+impl fmt::Display for RegressionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "samples  {}", self.samples));
+        try!(write_metric(f, "mse      ", self.mse, 6, ""));
+        try!(write_metric(f, "mae      ", self.mae, 6, ""));
+        try!(write_metric(f, "r2       ", self.r2, 4, ""));
+        write_metric(f, "mape     ", self.mape, 2, "%")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn predictions(outputs: &[&[f64]], targets: &[&[f64]]) -> Predictions {
+        Predictions {
+            outputs: outputs.iter().map(|o| o.to_vec()).collect(),
+            targets: targets.iter().map(|t| t.to_vec()).collect()
+        }
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        match actual {
+            Some(actual) => assert!((actual - expected).abs() < 1e-12, "{} instead of {}", actual, expected),
+            None => panic!("None instead of {}", expected)
+        }
+    }
+
+    #[test]
+    fn accuracy_with_ties() {
+        // ties go to the lower class index, for targets as well as for outputs
+        let tied = predictions(
+            &[&[0.4, 0.4, 0.2], &[0.4, 0.4, 0.2], &[0.1, 0.3, 0.3], &[0.2, 0.5, 0.3]],
+            &[&[1.0, 0.0, 0.0], &[0.0, 1.0, 0.0], &[0.0, 0.5, 0.5], &[0.0, 0.0, 1.0]]
+        );
+        assert_close(tied.accuracy(), 0.5);
+        assert_close(tied.top_k_accuracy(1), 0.5);
+        assert_close(tied.top_k_accuracy(2), 1.0);
+        assert_close(tied.top_k_accuracy(3), 1.0);
+        assert_close(tied.top_k_accuracy(0), 0.0);
+
+        assert_eq!(tied.confusion_matrix().counts, vec![vec![1, 0, 0], vec![1, 1, 0], vec![0, 1, 0]]);
+
+        // single outputs decide for class 1 from 0.5 on
+        let binary = predictions(&[&[0.5], &[0.49], &[0.9], &[0.1]], &[&[1.0], &[1.0], &[0.0], &[0.0]]);
+        assert_eq!(binary.classes(), 2);
+        assert_close(binary.accuracy(), 0.5);
+        assert_close(binary.top_k_accuracy(1), 0.5);
+        assert_close(binary.top_k_accuracy(2), 1.0);
+    }
+
+    #[test]
+    fn roc_auc_with_ties() {
+        // positives score 0.8 and 0.5, negatives 0.5 and 0.2: three wins and a tie of four pairs
+        let binary = predictions(&[&[0.8], &[0.5], &[0.5], &[0.2]], &[&[1.0], &[1.0], &[0.0], &[0.0]]);
+        assert_close(binary.roc_auc(), 0.875);
+
+        let all_tied = predictions(&[&[0.3], &[0.3], &[0.3]], &[&[1.0], &[0.0], &[0.0]]);
+        assert_close(all_tied.roc_auc(), 0.5);
+
+        let one_label = predictions(&[&[0.3], &[0.6]], &[&[1.0], &[1.0]]);
+        assert_eq!(one_label.roc_auc(), None);
+
+        // class 2 has no positive samples and is left out of the average
+        let classes = predictions(
+            &[&[0.7, 0.2, 0.1], &[0.3, 0.6, 0.1], &[0.5, 0.2, 0.3]],
+            &[&[1.0, 0.0, 0.0], &[0.0, 1.0, 0.0], &[0.0, 1.0, 0.0]]
+        );
+        // class 0: 0.7 beats 0.3 and 0.5, class 1: the positives 0.6 and 0.2 beat and tie the negative 0.2
+        assert_close(classes.roc_auc(), (1.0 + 0.75) / 2.0);
+    }
+
+    #[test]
+    fn r2_with_constant_targets() {
+        let exact = predictions(&[&[2.0], &[2.0]], &[&[2.0], &[2.0]]);
+        assert_close(exact.r2(), 1.0);
+
+        let off = predictions(&[&[2.0], &[2.5]], &[&[2.0], &[2.0]]);
+        assert_close(off.r2(), 0.0);
+
+        // residual 1 of total 2 in the first column, constant and exact in the second
+        let mixed = predictions(&[&[1.0, 5.0], &[2.0, 5.0], &[4.0, 5.0]], &[&[1.0, 5.0], &[2.0, 5.0], &[3.0, 5.0]]);
+        assert_close(mixed.r2(), (0.5 + 1.0) / 2.0);
+    }
+
+    #[test]
+    fn mape_skips_zero_targets() {
+        // 50% and 25%, the zero target is skipped
+        let regression = predictions(&[&[1.0], &[1.0], &[-5.0]], &[&[0.0], &[2.0], &[-4.0]]);
+        assert_close(regression.mape(), 37.5);
+        assert_close(regression.mae(), 1.0);
+        assert_close(regression.mse(), 1.0);
+
+        let zeros = predictions(&[&[1.0], &[2.0]], &[&[0.0], &[0.0]]);
+        assert_eq!(zeros.mape(), None);
+        assert!(format!("{}", zeros.regression_report()).contains("mape     -"));
+    }
+
+    #[test]
+    fn empty_predictions() {
+        let empty = Predictions::default();
+        assert!(empty.is_empty());
+        assert_eq!(empty.classes(), 0);
+
+        assert_eq!(empty.accuracy(), None);
+        assert_eq!(empty.top_k_accuracy(2), None);
+        assert_eq!(empty.roc_auc(), None);
+        assert_eq!(empty.log_loss(), None);
+        assert_eq!(empty.mse(), None);
+        assert_eq!(empty.mae(), None);
+        assert_eq!(empty.r2(), None);
+        assert_eq!(empty.mape(), None);
+
+        let report = empty.classification_report();
+        assert_eq!(report.samples, 0);
+        assert_eq!(report.macro_f1, 0.0);
+        assert_eq!(report.micro_f1, 0.0);
+        assert_eq!(report.confusion_matrix.classes(), 0);
+        assert!(format!("{}", report).contains("accuracy         -"));
+
+        let report = empty.regression_report();
+        assert_eq!((report.mse, report.mae, report.r2, report.mape), (None, None, None, None));
+    }
+
+    #[test]
+    fn confusion_matrix_scores() {
+        let matrix = ConfusionMatrix { counts: vec![vec![3, 1], vec![2, 4]] };
+
+        assert_close(Some(matrix.precision(0)), 0.6);
+        assert_close(Some(matrix.recall(0)), 0.75);
+        assert_close(Some(matrix.f1(0)), 2.0 * 0.6 * 0.75 / 1.35);
+        assert_close(Some(matrix.micro_f1()), 0.7);
+
+        let unpredicted = ConfusionMatrix { counts: vec![vec![2, 0], vec![1, 0]] };
+        assert_eq!(unpredicted.precision(1), 0.0);
+        assert_eq!(unpredicted.f1(1), 0.0);
+    }
+}
@@ -0,0 +1,15 @@
+//! Gradient based training of neural networks
+
+pub mod autodiff;
+pub mod callback;
+pub mod checkpoint;
+pub mod dataset;
+pub mod early_stopping;
+pub mod loss;
+pub mod metrics;
+pub mod optimizer;
+pub mod parallel;
+pub mod regularization;
+pub mod schedule;
+pub mod search;
+pub mod trainer;
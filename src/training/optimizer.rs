@@ -0,0 +1,626 @@
+//! Optimizers updating a network in place from its gradient
+//!
+//! Every optimizer keeps its per-parameter state shaped like the network (see `Gradient`) and
+//! derives `RustcEncodable`/`RustcDecodable`, so a training run can be resumed with its state.
+//! Hyperparameters are configured per parameter group, a group being a set of layers.
+
+use neural_network::NeuralNetwork;
+use neural_network::float::Float;
+use neural_network::gradient::{Gradient, GradientError};
+
+/// Trait for optimizers
+pub trait Optimizer<F: Float = f64> {
+    /// Updates the network with one step along the gradient
+    ///
+    /// Fails without updating when the gradient or the state of earlier steps is not shaped like
+    /// the network, call `reset` before stepping another network.
+    fn step(&mut self, network: &mut NeuralNetwork<F>, gradient: &Gradient<F>) -> Result<(), GradientError>;
+
+    /// Learning rate of the default parameter group
+    fn learning_rate(&self) -> f64;
+
+    /// Sets the learning rate of the default group, other groups are scaled by the same factor
+    fn set_learning_rate(&mut self, learning_rate: f64);
+
+    /// Forgets all per-parameter state
+    fn reset(&mut self);
+}
+
+/// Hyperparameters of one parameter group
+pub trait GroupParams {
+    /// Configured learning rate
+    fn learning_rate(&self) -> f64;
+}
+
+/// Hyperparameters for a default group and overrides for single layers
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct ParamGroups<H> {
+    default: H,
+    overrides: Vec<(usize, H)>,
+
+    /// Learning rate of the default group set by `set_learning_rate`, `None` until then
+    learning_rate: Option<f64>
+}
+
+impl<H> ParamGroups<H> {
+    pub fn new(default: H) -> Self {
+        ParamGroups {
+            default: default,
+            overrides: Vec::new(),
+            learning_rate: None
+        }
+    }
+
+    /// Uses other hyperparameters for the given layer
+    pub fn set_layer(&mut self, layer: usize, params: H) {
+        self.overrides.retain(|&(l, _)| l != layer);
+        self.overrides.push((layer, params));
+    }
+
+    /// Hyperparameters of the default group
+    pub fn default(&self) -> &H {
+        &self.default
+    }
+
+    /// Hyperparameters used for a layer
+    pub fn for_layer(&self, layer: usize) -> &H {
+        for &(l, ref params) in &self.overrides {
+            if l == layer {
+                return params;
+            }
+        }
+        &self.default
+    }
+
+}
+
+impl<H: GroupParams> ParamGroups<H> {
+    /// Learning rate used for the default group
+    pub fn learning_rate(&self) -> f64 {
+        self.learning_rate.unwrap_or(self.default.learning_rate())
+    }
+
+    /// Learning rate used for a layer
+    ///
+    /// After `set_learning_rate` the groups keep the ratio of their configured learning rates. If
+    /// the default group is configured with 0.0 there is no ratio and every group uses the new one.
+    pub fn learning_rate_for(&self, layer: usize) -> f64 {
+        let configured = self.for_layer(layer).learning_rate();
+        match self.learning_rate {
+            None => configured,
+            Some(learning_rate) => {
+                let default = self.default.learning_rate();
+                if default == 0.0 { learning_rate } else { configured * learning_rate / default }
+            }
+        }
+    }
+
+    /// Sets the learning rate of the default group
+    pub fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = Some(learning_rate);
+    }
+}
+
+/// Walks over all parameters together with their gradient and optimizer state
+///
+/// `update` receives the layer index, the parameter, its gradient and its state values. Nothing
+/// is updated when the gradient or a state is not shaped like the network.
+fn visit<F, U>(network: &mut NeuralNetwork<F>, gradient: &Gradient<F>, states: &mut [Gradient<F>], mut update: U) -> Result<(), GradientError>
+    where F: Float, U: FnMut(usize, &mut F, F, &mut [F])
+{
+    try!(gradient.matches(network));
+    for state in states.iter() {
+        try!(state.matches(network));
+    }
+
+    let count = states.len();
+    let mut values = vec![F::zero(); count];
+
+    for (l, layer) in network.layers_mut().iter_mut().enumerate() {
+        for (n, neuron) in layer.iter_mut().enumerate() {
+            let grad = &gradient.layers[l][n];
+
+            for i in 0..grad.weights.len() {
+                for s in 0..count {
+                    values[s] = states[s].layers[l][n].weights[i];
+                }
+                update(l, &mut neuron.weights_mut()[i], grad.weights[i], &mut values);
+                for s in 0..count {
+                    states[s].layers[l][n].weights[i] = values[s];
+                }
+            }
+
+            for s in 0..count {
+                values[s] = states[s].layers[l][n].bias;
+            }
+            update(l, neuron.bias_mut(), grad.bias, &mut values);
+            for s in 0..count {
+                states[s].layers[l][n].bias = values[s];
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Hyperparameters of `Sgd`
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct SgdParams {
+    pub learning_rate: f64,
+
+    /// 0.0 disables momentum
+    pub momentum: f64,
+
+    /// Uses Nesterov momentum, looking ahead along the velocity
+    pub nesterov: bool
+}
+
+impl GroupParams for SgdParams {
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+}
+
+/// Stochastic gradient descent, optionally with (Nesterov) momentum
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct Sgd<F: Float = f64> {
+    pub groups: ParamGroups<SgdParams>,
+    states: Vec<Gradient<F>>
+}
+
+impl<F: Float> Sgd<F> {
+    /// Plain gradient descent
+    pub fn new(learning_rate: f64) -> Self {
+        Sgd::with_params(SgdParams { learning_rate: learning_rate, momentum: 0.0, nesterov: false })
+    }
+
+    /// Gradient descent with classical momentum
+    pub fn momentum(learning_rate: f64, momentum: f64) -> Self {
+        Sgd::with_params(SgdParams { learning_rate: learning_rate, momentum: momentum, nesterov: false })
+    }
+
+    /// Gradient descent with Nesterov momentum
+    pub fn nesterov(learning_rate: f64, momentum: f64) -> Self {
+        Sgd::with_params(SgdParams { learning_rate: learning_rate, momentum: momentum, nesterov: true })
+    }
+
+    pub fn with_params(params: SgdParams) -> Self {
+        Sgd {
+            groups: ParamGroups::new(params),
+            states: Vec::new()
+        }
+    }
+}
+
+impl<F: Float> Optimizer<F> for Sgd<F> {
+    fn step(&mut self, network: &mut NeuralNetwork<F>, gradient: &Gradient<F>) -> Result<(), GradientError> {
+        if self.states.is_empty() {
+            self.states.push(Gradient::zeros(network));
+        }
+
+        let groups = &self.groups;
+        visit(network, gradient, &mut self.states, |layer, param, grad, state| {
+            let params = groups.for_layer(layer);
+            let lr = F::from_f64(groups.learning_rate_for(layer));
+            let momentum = F::from_f64(params.momentum);
+
+            // state[0]: velocity
+            state[0] = momentum * state[0] + grad;
+            let direction = if params.nesterov { grad + momentum * state[0] } else { state[0] };
+            *param -= lr * direction;
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.groups.learning_rate()
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.groups.set_learning_rate(learning_rate);
+    }
+
+    fn reset(&mut self) {
+        self.states.clear();
+    }
+}
+
+/// Hyperparameters of `Adam`
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct AdamParams {
+    pub learning_rate: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub epsilon: f64,
+
+    pub weight_decay: f64,
+
+    /// Applies the weight decay directly to the weights (AdamW) instead of adding it to the gradient
+    pub decoupled_weight_decay: bool
+}
+
+impl GroupParams for AdamParams {
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+}
+
+impl AdamParams {
+    pub fn defaults() -> Self {
+        AdamParams {
+            learning_rate: 0.001,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            weight_decay: 0.0,
+            decoupled_weight_decay: false
+        }
+    }
+}
+
+/// Adam and AdamW
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct Adam<F: Float = f64> {
+    pub groups: ParamGroups<AdamParams>,
+
+    /// Amount of steps taken, used for bias correction
+    steps: u64,
+    states: Vec<Gradient<F>>
+}
+
+impl<F: Float> Adam<F> {
+    pub fn new(learning_rate: f64) -> Self {
+        let mut params = AdamParams::defaults();
+        params.learning_rate = learning_rate;
+        Adam::with_params(params)
+    }
+
+    /// Adam with decoupled weight decay
+    pub fn adamw(learning_rate: f64, weight_decay: f64) -> Self {
+        let mut params = AdamParams::defaults();
+        params.learning_rate = learning_rate;
+        params.weight_decay = weight_decay;
+        params.decoupled_weight_decay = true;
+        Adam::with_params(params)
+    }
+
+    pub fn with_params(params: AdamParams) -> Self {
+        Adam {
+            groups: ParamGroups::new(params),
+            steps: 0,
+            states: Vec::new()
+        }
+    }
+
+    /// Amount of steps taken
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+}
+
+impl<F: Float> Optimizer<F> for Adam<F> {
+    fn step(&mut self, network: &mut NeuralNetwork<F>, gradient: &Gradient<F>) -> Result<(), GradientError> {
+        if self.states.is_empty() {
+            self.states.push(Gradient::zeros(network));
+            self.states.push(Gradient::zeros(network));
+        }
+
+        let steps = (self.steps + 1) as i32;
+        let groups = &self.groups;
+        try!(visit(network, gradient, &mut self.states, |layer, param, grad, state| {
+            let params = groups.for_layer(layer);
+            let lr = groups.learning_rate_for(layer);
+
+            let mut grad = grad.to_f64();
+            if !params.decoupled_weight_decay {
+                grad += params.weight_decay * param.to_f64();
+            }
+
+            // state[0]: first moment, state[1]: second moment
+            let m = params.beta1 * state[0].to_f64() + (1.0 - params.beta1) * grad;
+            let v = params.beta2 * state[1].to_f64() + (1.0 - params.beta2) * grad * grad;
+            state[0] = F::from_f64(m);
+            state[1] = F::from_f64(v);
+
+            let m_hat = m / (1.0 - params.beta1.powi(steps));
+            let v_hat = v / (1.0 - params.beta2.powi(steps));
+
+            let mut value = param.to_f64() - lr * m_hat / (v_hat.sqrt() + params.epsilon);
+            if params.decoupled_weight_decay {
+                value -= lr * params.weight_decay * param.to_f64();
+            }
+            *param = F::from_f64(value);
+        }));
+
+        self.steps += 1;
+        Ok(())
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.groups.learning_rate()
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.groups.set_learning_rate(learning_rate);
+    }
+
+    fn reset(&mut self) {
+        self.steps = 0;
+        self.states.clear();
+    }
+}
+
+/// Hyperparameters of `RmsProp`
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct RmsPropParams {
+    pub learning_rate: f64,
+
+    /// Decay of the running mean of squared gradients
+    pub decay: f64,
+    pub epsilon: f64,
+
+    /// 0.0 disables momentum
+    pub momentum: f64
+}
+
+impl GroupParams for RmsPropParams {
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+}
+
+/// RMSProp
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct RmsProp<F: Float = f64> {
+    pub groups: ParamGroups<RmsPropParams>,
+    states: Vec<Gradient<F>>
+}
+
+impl<F: Float> RmsProp<F> {
+    pub fn new(learning_rate: f64) -> Self {
+        RmsProp::with_params(RmsPropParams { learning_rate: learning_rate, decay: 0.9, epsilon: 1e-8, momentum: 0.0 })
+    }
+
+    pub fn with_params(params: RmsPropParams) -> Self {
+        RmsProp {
+            groups: ParamGroups::new(params),
+            states: Vec::new()
+        }
+    }
+}
+
+impl<F: Float> Optimizer<F> for RmsProp<F> {
+    fn step(&mut self, network: &mut NeuralNetwork<F>, gradient: &Gradient<F>) -> Result<(), GradientError> {
+        if self.states.is_empty() {
+            self.states.push(Gradient::zeros(network));
+            self.states.push(Gradient::zeros(network));
+        }
+
+        let groups = &self.groups;
+        visit(network, gradient, &mut self.states, |layer, param, grad, state| {
+            let params = groups.for_layer(layer);
+            let lr = groups.learning_rate_for(layer);
+            let grad = grad.to_f64();
+
+            // state[0]: mean square, state[1]: momentum buffer
+            let mean_square = params.decay * state[0].to_f64() + (1.0 - params.decay) * grad * grad;
+            let update = params.momentum * state[1].to_f64() + lr * grad / (mean_square.sqrt() + params.epsilon);
+            state[0] = F::from_f64(mean_square);
+            state[1] = F::from_f64(update);
+
+            *param = F::from_f64(param.to_f64() - update);
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.groups.learning_rate()
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.groups.set_learning_rate(learning_rate);
+    }
+
+    fn reset(&mut self) {
+        self.states.clear();
+    }
+}
+
+/// Hyperparameters of `Adagrad`
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct AdagradParams {
+    pub learning_rate: f64,
+    pub epsilon: f64
+}
+
+impl GroupParams for AdagradParams {
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+}
+
+/// Adagrad
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct Adagrad<F: Float = f64> {
+    pub groups: ParamGroups<AdagradParams>,
+    states: Vec<Gradient<F>>
+}
+
+impl<F: Float> Adagrad<F> {
+    pub fn new(learning_rate: f64) -> Self {
+        Adagrad::with_params(AdagradParams { learning_rate: learning_rate, epsilon: 1e-10 })
+    }
+
+    pub fn with_params(params: AdagradParams) -> Self {
+        Adagrad {
+            groups: ParamGroups::new(params),
+            states: Vec::new()
+        }
+    }
+}
+
+impl<F: Float> Optimizer<F> for Adagrad<F> {
+    fn step(&mut self, network: &mut NeuralNetwork<F>, gradient: &Gradient<F>) -> Result<(), GradientError> {
+        if self.states.is_empty() {
+            self.states.push(Gradient::zeros(network));
+        }
+
+        let groups = &self.groups;
+        visit(network, gradient, &mut self.states, |layer, param, grad, state| {
+            let params = groups.for_layer(layer);
+            let lr = groups.learning_rate_for(layer);
+            let grad = grad.to_f64();
+
+            // state[0]: sum of squared gradients
+            let sum = state[0].to_f64() + grad * grad;
+            state[0] = F::from_f64(sum);
+
+            *param = F::from_f64(param.to_f64() - lr * grad / (sum.sqrt() + params.epsilon));
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.groups.learning_rate()
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.groups.set_learning_rate(learning_rate);
+    }
+
+    fn reset(&mut self) {
+        self.states.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode::SizeLimit;
+    use bincode::rustc_serialize::{encode, decode};
+
+    use neural_network::{Neuron, NeuronType};
+    use neural_network::gradient::NeuronGradient;
+
+    /// Network with the single weight 0.5 and bias 0.0
+    fn network() -> NeuralNetwork {
+        NeuralNetwork::from_layers(1, vec![vec![Neuron::new(vec![0.5], 0.0, NeuronType::Identity)]])
+    }
+
+    fn gradient(weight: f64) -> Gradient {
+        Gradient { layers: vec![vec![NeuronGradient { weights: vec![weight], bias: 0.0 }]] }
+    }
+
+    /// Steps with the gradients 0.2 and -0.1 and returns the weight after each step
+    fn weights<O: Optimizer>(optimizer: &mut O) -> Vec<f64> {
+        let mut network = network();
+        [0.2, -0.1].iter().map(|g| {
+            optimizer.step(&mut network, &gradient(*g)).unwrap();
+            assert_eq!(network.layers()[0][0].bias(), 0.0);
+            network.layers()[0][0].weights()[0]
+        }).collect()
+    }
+
+    fn assert_close(actual: Vec<f64>, expected: &[f64]) {
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1e-12, "{:?} vs {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn sgd() {
+        // w -= 0.1 * g
+        assert_close(weights(&mut Sgd::new(0.1)), &[0.48, 0.49]);
+    }
+
+    #[test]
+    fn sgd_momentum() {
+        // v = 0.2, then 0.9 * 0.2 - 0.1 = 0.08
+        assert_close(weights(&mut Sgd::momentum(0.1, 0.9)), &[0.48, 0.472]);
+    }
+
+    #[test]
+    fn sgd_nesterov() {
+        // direction g + 0.9 * v: 0.2 + 0.18 = 0.38, then -0.1 + 0.072 = -0.028
+        assert_close(weights(&mut Sgd::nesterov(0.1, 0.9)), &[0.462, 0.4648]);
+    }
+
+    #[test]
+    fn adam() {
+        // the first bias corrected step moves by the learning rate
+        let mut adam = Adam::new(0.1);
+        assert_close(weights(&mut adam), &[0.4000000049999997, 0.3733663027186757]);
+        assert_eq!(adam.steps(), 2);
+    }
+
+    #[test]
+    fn adamw() {
+        // like adam, additionally subtracting 0.1 * 0.01 * w
+        assert_close(weights(&mut Adam::adamw(0.1, 0.01)), &[0.3995000049999997, 0.3724668027136757]);
+    }
+
+    #[test]
+    fn rms_prop() {
+        let mut rms_prop = RmsProp::with_params(RmsPropParams { learning_rate: 0.01, decay: 0.9, epsilon: 1e-8, momentum: 0.5 });
+        assert_close(weights(&mut rms_prop), &[0.4683772283983154, 0.46731003603905014]);
+    }
+
+    #[test]
+    fn adagrad() {
+        // the accumulated squares are 0.04 and 0.05
+        assert_close(weights(&mut Adagrad::new(0.1)), &[0.40000000004999997, 0.44472135957999576]);
+    }
+
+    #[test]
+    fn layer_learning_rates() {
+        let mut network = NeuralNetwork::from_layers(1, vec![
+            vec![Neuron::new(vec![0.5], 0.0, NeuronType::Identity)],
+            vec![Neuron::new(vec![0.5], 0.0, NeuronType::Identity)]
+        ]);
+        let layer = NeuronGradient { weights: vec![1.0], bias: 0.0 };
+        let gradient = Gradient { layers: vec![vec![layer.clone()], vec![layer]] };
+
+        let mut sgd = Sgd::new(0.1);
+        sgd.groups.set_layer(1, SgdParams { learning_rate: 0.2, momentum: 0.0, nesterov: false });
+        sgd.set_learning_rate(0.05);
+        sgd.step(&mut network, &gradient).unwrap();
+
+        assert!((network.layers()[0][0].weights()[0] - 0.45).abs() < 1e-12);
+        assert!((network.layers()[1][0].weights()[0] - 0.4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rejects_state_of_another_network() {
+        let mut adam = Adam::new(0.1);
+        let mut network = network();
+        adam.step(&mut network, &gradient(0.2)).unwrap();
+
+        let mut other: NeuralNetwork = NeuralNetwork::from_layers(2, vec![vec![Neuron::new(vec![0.5, 0.5], 0.0, NeuronType::Identity)]]);
+        let other_gradient = Gradient { layers: vec![vec![NeuronGradient { weights: vec![0.2, 0.2], bias: 0.0 }]] };
+
+        match adam.step(&mut other, &other_gradient) {
+            Err(GradientError::ShapeMismatch(0)) => { },
+            result => panic!("expected a shape mismatch, got {:?}", result)
+        }
+        assert_eq!(other.layers()[0][0].weights(), &[0.5, 0.5]);
+        assert_eq!(adam.steps(), 1);
+
+        adam.reset();
+        adam.step(&mut other, &other_gradient).unwrap();
+        assert_eq!(adam.steps(), 1);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut adam = Adam::new(0.1);
+        let mut network = network();
+        adam.step(&mut network, &gradient(0.2)).unwrap();
+
+        let mut restored: Adam = decode(&encode(&adam, SizeLimit::Infinite).unwrap()).unwrap();
+        assert_eq!(restored.steps(), 1);
+
+        // the restored optimizer continues exactly like the original
+        let mut copy = network.clone();
+        adam.step(&mut network, &gradient(-0.1)).unwrap();
+        restored.step(&mut copy, &gradient(-0.1)).unwrap();
+        assert_eq!(network.layers()[0][0].weights(), copy.layers()[0][0].weights());
+        assert_close(network.layers()[0][0].weights().to_vec(), &[0.3733663027186757]);
+    }
+}
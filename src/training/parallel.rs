@@ -0,0 +1,118 @@
+//! Data-parallel gradient computation
+//!
+//! A batch is split into one contiguous shard per thread of a `scoped_threadpool::Pool`. Every
+//! worker backpropagates its shard against the shared network, the shard gradients are then
+//! averaged in shard order, weighted by the sample weights of each shard. The result matches
+//! `loss::gradient` on the whole batch up to floating-point summation order.
+
+use scoped_threadpool::Pool;
+
+use neural_network::NeuralNetwork;
+use neural_network::float::Float;
+use neural_network::gradient::Gradient;
+
+use training::dataset::Dataset;
+use training::loss::{self, Loss, LossError};
+
+/// Weighted mean loss and its gradient over the given samples, computed on all threads of `pool`
+pub fn gradient<F, L>(pool: &mut Pool, loss: &L, network: &NeuralNetwork<F>, data: &Dataset<F>, indices: &[usize]) -> Result<(F, Gradient<F>), LossError>
+    where F: Float, L: Loss<F> + Sync
+{
+    let threads = pool.thread_count() as usize;
+    if threads <= 1 || indices.len() <= 1 {
+        return loss::gradient(loss, network, data, indices);
+    }
+
+    let shard_size = (indices.len() + threads - 1) / threads;
+    let shards: Vec<&[usize]> = indices.chunks(shard_size).collect();
+    let weights: Vec<F> = shards.iter().map(|shard| {
+        shard.iter().fold(F::zero(), |sum, index| sum + data.weight(*index))
+    }).collect();
+
+    let mut results: Vec<Option<Result<(F, Gradient<F>), LossError>>> = shards.iter().map(|_| None).collect();
+
+    pool.scoped(|scope| {
+        for ((shard, weight), result) in shards.iter().zip(weights.iter()).zip(results.iter_mut()) {
+            // shards without weight contribute nothing
+            if *weight == F::zero() {
+                continue;
+            }
+
+            scope.execute(move || {
+                *result = Some(loss::gradient(loss, network, data, shard));
+            });
+        }
+    });
+
+    let total = weights.iter().fold(F::zero(), |sum, w| sum + *w);
+    if total == F::zero() {
+        return Err(LossError::ZeroWeight);
+    }
+
+    let mut value = F::zero();
+    let mut combined = Gradient::zeros(network);
+
+    for (result, weight) in results.into_iter().zip(weights.into_iter()) {
+        let (shard_value, mut shard_gradient) = match result {
+            Some(result) => try!(result),
+            None => continue
+        };
+
+        let share = weight / total;
+        value += share * shard_value;
+        shard_gradient.scale(share);
+        combined.add(&shard_gradient);
+    }
+
+    Ok((value, combined))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, StdRng};
+
+    use neural_network::differential::random_network;
+    use training::loss::MeanSquaredError;
+
+    use super::*;
+
+    fn assert_close(a: &(f64, Gradient<f64>), b: &(f64, Gradient<f64>)) {
+        let close = |x: f64, y: f64| (x - y).abs() <= 1e-12 * x.abs().max(y.abs()).max(1.0);
+        assert!(close(a.0, b.0), "loss {} != {}", a.0, b.0);
+
+        for (layer_a, layer_b) in a.1.layers.iter().zip(b.1.layers.iter()) {
+            for (neuron_a, neuron_b) in layer_a.iter().zip(layer_b.iter()) {
+                assert!(close(neuron_a.bias, neuron_b.bias));
+                for (wa, wb) in neuron_a.weights.iter().zip(neuron_b.weights.iter()) {
+                    assert!(close(*wa, *wb), "weight {} != {}", wa, wb);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_serial_gradient() {
+        let seed: &[usize] = &[1];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+
+        for _ in 0..10 {
+            let network: NeuralNetwork<f64> = random_network(&mut rng, 3, 6);
+            let outputs = network.layers()[network.layers().len() - 1].len();
+
+            let inputs = (0..12).map(|_| (0..network.inputs()).map(|_| rng.gen_range(-1.0, 1.0)).collect()).collect();
+            let targets = (0..12).map(|_| (0..outputs).map(|_| rng.gen_range(-1.0, 1.0)).collect()).collect();
+
+            // with 4 threads the last shard holds samples 9 to 11, all without weight
+            let weights = (0..12).map(|index| if index < 9 { rng.gen_range(0.1, 2.0) } else { 0.0 }).collect();
+            let data = Dataset::new(inputs, targets).unwrap().with_weights(weights).unwrap();
+            let indices: Vec<usize> = (0..12).collect();
+
+            let serial = loss::gradient(&MeanSquaredError, &network, &data, &indices).unwrap();
+            for threads in &[1, 4] {
+                let mut pool = Pool::new(*threads);
+                let parallel = gradient(&mut pool, &MeanSquaredError, &network, &data, &indices).unwrap();
+                assert_close(&parallel, &serial);
+            }
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! Penalties, gradient clipping and weight constraints
+//!
+//! L1 and L2 penalties only apply to weights, biases stay unpenalized. The L2 penalty is
+//! `l2 / 2 * sum(w²)`, so its gradient is `l2 * w`.
+
+use neural_network::NeuralNetwork;
+use neural_network::float::Float;
+use neural_network::gradient::{Gradient, GradientError};
+
+/// Strength of the L1 and L2 penalties
+#[derive(RustcEncodable, RustcDecodable, Copy, Clone, Debug, Default, PartialEq)]
+pub struct Penalty {
+    pub l1: f64,
+    pub l2: f64
+}
+
+/// Contribution of the penalties to the loss
+#[derive(RustcEncodable, RustcDecodable, Copy, Clone, Debug, Default, PartialEq)]
+pub struct PenaltyValue {
+    pub l1: f64,
+    pub l2: f64
+}
+
+impl PenaltyValue {
+    pub fn total(&self) -> f64 {
+        self.l1 + self.l2
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct Regularization {
+    /// Penalty of layers without an override
+    pub penalty: Penalty,
+
+    /// Per-layer penalties
+    layers: Vec<(usize, Penalty)>,
+
+    /// Decoupled weight decay, shrinks weights by `learning_rate * weight_decay` after every step
+    pub weight_decay: f64,
+
+    /// Clips every gradient entry to `[-clip_value, clip_value]`
+    pub clip_value: Option<f64>,
+
+    /// Rescales the gradient when its norm over all parameters exceeds this value
+    pub clip_norm: Option<f64>,
+
+    /// Rescales the weight vector of every neuron to at most this norm after every step
+    pub max_norm: Option<f64>
+}
+
+impl Regularization {
+    /// No regularization at all
+    pub fn new() -> Self {
+        Regularization {
+            penalty: Penalty::default(),
+            layers: Vec::new(),
+            weight_decay: 0.0,
+            clip_value: None,
+            clip_norm: None,
+            max_norm: None
+        }
+    }
+
+    /// Same L1 and L2 penalty for all layers
+    pub fn penalties(l1: f64, l2: f64) -> Self {
+        let mut regularization = Regularization::new();
+        regularization.penalty = Penalty { l1: l1, l2: l2 };
+        regularization
+    }
+
+    /// Overrides the penalty of a single layer
+    pub fn set_layer(&mut self, layer: usize, penalty: Penalty) {
+        self.layers.retain(|&(l, _)| l != layer);
+        self.layers.push((layer, penalty));
+    }
+
+    /// Penalty used for a layer
+    pub fn for_layer(&self, layer: usize) -> Penalty {
+        self.layers.iter().find(|&&(l, _)| l == layer).map_or(self.penalty, |&(_, penalty)| penalty)
+    }
+
+    /// Current contribution of the penalties to the loss
+    pub fn penalty_value<F: Float>(&self, network: &NeuralNetwork<F>) -> PenaltyValue {
+        let mut value = PenaltyValue::default();
+
+        for (l, layer) in network.layers().iter().enumerate() {
+            let penalty = self.for_layer(l);
+            if penalty.l1 == 0.0 && penalty.l2 == 0.0 {
+                continue;
+            }
+
+            for w in layer.iter().flat_map(|neuron| neuron.weights().iter()) {
+                let w = w.to_f64();
+                value.l1 += penalty.l1 * w.abs();
+                value.l2 += penalty.l2 / 2.0 * w * w;
+            }
+        }
+
+        value
+    }
+
+    /// Adds the gradient of the penalties to `gradient`
+    pub fn add_penalty_gradient<F: Float>(&self, network: &NeuralNetwork<F>, gradient: &mut Gradient<F>) -> Result<(), GradientError> {
+        try!(gradient.matches(network));
+
+        for (l, layer) in network.layers().iter().enumerate() {
+            let penalty = self.for_layer(l);
+            if penalty.l1 == 0.0 && penalty.l2 == 0.0 {
+                continue;
+            }
+
+            let (l1, l2) = (F::from_f64(penalty.l1), F::from_f64(penalty.l2));
+            for (neuron, target) in layer.iter().zip(gradient.layers[l].iter_mut()) {
+                for (w, g) in neuron.weights().iter().zip(target.weights.iter_mut()) {
+                    let sign = if *w > F::zero() { F::one() } else if *w < F::zero() { -F::one() } else { F::zero() };
+                    *g += l1 * sign + l2 * *w;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Clips the gradient by value and then by global norm
+    pub fn clip<F: Float>(&self, gradient: &mut Gradient<F>) {
+        if let Some(limit) = self.clip_value {
+            let limit = F::from_f64(limit);
+            gradient.map(|g| g.max(-limit).min(limit));
+        }
+
+        if let Some(limit) = self.clip_norm {
+            let norm = gradient.norm().to_f64();
+            if norm > limit {
+                gradient.scale(F::from_f64(limit / norm));
+            }
+        }
+    }
+
+    /// Applies weight decay and max-norm constraints after an optimizer step
+    pub fn constrain<F: Float>(&self, network: &mut NeuralNetwork<F>, learning_rate: f64) {
+        let decay = F::from_f64(1.0 - learning_rate * self.weight_decay);
+
+        for neuron in network.layers_mut().iter_mut().flat_map(|layer| layer.iter_mut()) {
+            if self.weight_decay != 0.0 {
+                for w in neuron.weights_mut().iter_mut() {
+                    *w *= decay;
+                }
+            }
+
+            if let Some(limit) = self.max_norm {
+                let norm = neuron.weights().iter().fold(0.0, |sum, w| sum + w.to_f64() * w.to_f64()).sqrt();
+                if norm > limit {
+                    let factor = F::from_f64(limit / norm);
+                    for w in neuron.weights_mut().iter_mut() {
+                        *w *= factor;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, StdRng};
+
+    use neural_network::{Neuron, NeuronType};
+    use neural_network::differential::random_network;
+
+    const EPSILON: f64 = 1e-6;
+
+    /// Layers with the weights `[1, -2]` and `[0.5, 0]`, every bias is 3
+    fn network() -> NeuralNetwork {
+        NeuralNetwork::from_layers(2, vec![
+            vec![Neuron::new(vec![1.0, -2.0], 3.0, NeuronType::Identity)],
+            vec![Neuron::new(vec![0.5], 3.0, NeuronType::Identity), Neuron::new(vec![0.0], 3.0, NeuronType::Identity)]
+        ])
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-12, "{} instead of {}", actual, expected);
+    }
+
+    #[test]
+    fn penalty_values() {
+        // 0.1 * (1 + 2 + 0.5) and 0.2 / 2 * (1 + 4 + 0.25), the biases are not penalized
+        let value = Regularization::penalties(0.1, 0.2).penalty_value(&network());
+        assert_close(value.l1, 0.35);
+        assert_close(value.l2, 0.525);
+        assert_close(value.total(), 0.875);
+
+        let mut regularization = Regularization::penalties(0.1, 0.2);
+        regularization.set_layer(1, Penalty { l1: 0.0, l2: 1.0 });
+        let value = regularization.penalty_value(&network());
+        assert_close(value.l1, 0.3);
+        assert_close(value.l2, 0.5 + 0.125);
+
+        assert_eq!(Regularization::new().penalty_value(&network()), PenaltyValue::default());
+    }
+
+    #[test]
+    fn penalty_gradients_match_differences() {
+        let seed: &[usize] = &[1];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+
+        for _ in 0..10 {
+            let mut network: NeuralNetwork = random_network(&mut rng, 3, 4);
+
+            // keep the weights away from the kink of the L1 penalty
+            for neuron in network.layers_mut().iter_mut().flat_map(|layer| layer.iter_mut()) {
+                for w in neuron.weights_mut().iter_mut() {
+                    if w.abs() < 0.01 {
+                        *w = 0.5;
+                    }
+                }
+            }
+
+            let mut regularization = Regularization::penalties(0.03, 0.2);
+            regularization.set_layer(1, Penalty { l1: 0.5, l2: 0.0 });
+
+            let mut gradient = Gradient::zeros(&network);
+            regularization.add_penalty_gradient(&network, &mut gradient).unwrap();
+
+            let total = |network: &NeuralNetwork| regularization.penalty_value(network).total();
+            for l in 0..network.layers().len() {
+                for n in 0..network.layers()[l].len() {
+                    for i in 0..network.layers()[l][n].weights().len() {
+                        let mut shifted = network.clone();
+                        shifted.layers_mut()[l][n].weights_mut()[i] += EPSILON;
+                        let plus = total(&shifted);
+                        shifted.layers_mut()[l][n].weights_mut()[i] -= 2.0 * EPSILON;
+                        let minus = total(&shifted);
+
+                        let numeric = (plus - minus) / (2.0 * EPSILON);
+                        assert!((numeric - gradient.layers[l][n].weights[i]).abs() < 1e-6, "layer {} neuron {} weight {}", l, n, i);
+                    }
+
+                    let mut shifted = network.clone();
+                    *shifted.layers_mut()[l][n].bias_mut() += 1.0;
+                    assert_eq!(total(&shifted), total(&network));
+                    assert_eq!(gradient.layers[l][n].bias, 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn l1_gradient_vanishes_at_zero() {
+        let mut gradient = Gradient::zeros(&network());
+        Regularization::penalties(0.1, 0.2).add_penalty_gradient(&network(), &mut gradient).unwrap();
+
+        assert_eq!(gradient.layers[0][0].weights, vec![0.1 + 0.2, -0.1 - 0.4]);
+        assert_eq!(gradient.layers[1][0].weights, vec![0.1 + 0.1]);
+        assert_eq!(gradient.layers[1][1].weights, vec![0.0]);
+    }
+
+    #[test]
+    fn rejects_gradient_of_another_network() {
+        let other: NeuralNetwork = NeuralNetwork::from_layers(1, vec![vec![Neuron::new(vec![1.0], 0.0, NeuronType::Identity)]]);
+        let mut gradient = Gradient::zeros(&other);
+        match Regularization::penalties(0.1, 0.1).add_penalty_gradient(&network(), &mut gradient) {
+            Err(GradientError::ShapeMismatch(_)) => { },
+            result => panic!("expected a shape mismatch, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn clipping() {
+        let mut gradient = Gradient::zeros(&network());
+        gradient.layers[0][0].weights = vec![3.0, -4.0];
+        gradient.layers[0][0].bias = 10.0;
+
+        let mut by_value = gradient.clone();
+        let mut regularization = Regularization::new();
+        regularization.clip_value = Some(3.5);
+        regularization.clip(&mut by_value);
+        assert_eq!(by_value.layers[0][0].weights, vec![3.0, -3.5]);
+        assert_eq!(by_value.layers[0][0].bias, 3.5);
+
+        // the norm covers the biases as well, sqrt(9 + 16 + 100)
+        let mut by_norm = gradient.clone();
+        let mut regularization = Regularization::new();
+        regularization.clip_norm = Some(1.0);
+        regularization.clip(&mut by_norm);
+        let factor = 1.0 / 125.0f64.sqrt();
+        assert_close(by_norm.layers[0][0].weights[0], 3.0 * factor);
+        assert_close(by_norm.layers[0][0].bias, 10.0 * factor);
+    }
+
+    #[test]
+    fn constraints_leave_biases() {
+        let mut regularization = Regularization::new();
+        regularization.weight_decay = 0.5;
+        regularization.max_norm = Some(1.0);
+
+        let mut network = network();
+        regularization.constrain(&mut network, 0.2);
+
+        // decayed by 1 - 0.2 * 0.5, then [0.9, -1.8] is rescaled to norm 1
+        let norm = (0.81f64 + 3.24).sqrt();
+        assert_close(network.layers()[0][0].weights()[0], 0.9 / norm);
+        assert_close(network.layers()[0][0].weights()[1], -1.8 / norm);
+        assert_close(network.layers()[1][0].weights()[0], 0.45);
+        for neuron in network.layers().iter().flat_map(|layer| layer.iter()) {
+            assert_eq!(neuron.bias(), 3.0);
+        }
+    }
+}
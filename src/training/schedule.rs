@@ -0,0 +1,577 @@
+//! Learning-rate schedules and the learning-rate range test
+//!
+//! Schedules are driven by the training progress in epochs, `1.5` being the middle of the second
+//! epoch. The `Trainer` asks its schedule for the learning rate before every optimizer step and
+//! reports the validation loss after every epoch.
+
+use std::f64::consts::PI;
+
+use rand::{Rng, OsRng, SeedableRng, StdRng};
+
+use neural_network::NeuralNetwork;
+use neural_network::float::Float;
+
+use training::dataset::Dataset;
+use training::loss::{self, Loss};
+use training::optimizer::Optimizer;
+use training::trainer::TrainerError;
+
+/// Trait for learning-rate schedules
+pub trait Schedule {
+    /// Learning rate at the given progress, `base` is the learning rate the optimizer started with
+    fn learning_rate(&self, epoch: f64, base: f64) -> f64;
+
+    /// Receives the validation loss (or training loss without validation) after every epoch
+    fn observe(&mut self, _loss: f64) { }
+
+    /// State changed by `observe`, stored in checkpoints
+    fn state(&self) -> Vec<f64> {
+        Vec::new()
+    }
+
+    /// Restores a state returned by `state`
+    fn set_state(&mut self, _state: &[f64]) { }
+}
+
+/// Keeps the base learning rate, mostly useful inside `Warmup`
+#[derive(RustcEncodable, RustcDecodable, Copy, Clone, Debug)]
+pub struct Constant;
+
+impl Schedule for Constant {
+    fn learning_rate(&self, _: f64, base: f64) -> f64 {
+        base
+    }
+}
+
+/// Multiplies the learning rate by `gamma` every `step_size` epochs
+#[derive(RustcEncodable, RustcDecodable, Copy, Clone, Debug)]
+pub struct StepDecay {
+    pub step_size: usize,
+    pub gamma: f64
+}
+
+impl Schedule for StepDecay {
+    fn learning_rate(&self, epoch: f64, base: f64) -> f64 {
+        let steps = (epoch / self.step_size as f64).floor();
+        base * self.gamma.powf(steps)
+    }
+}
+
+/// Multiplies the learning rate by `gamma` every epoch
+#[derive(RustcEncodable, RustcDecodable, Copy, Clone, Debug)]
+pub struct Exponential {
+    pub gamma: f64
+}
+
+impl Schedule for Exponential {
+    fn learning_rate(&self, epoch: f64, base: f64) -> f64 {
+        base * self.gamma.powf(epoch.floor())
+    }
+}
+
+/// Cosine annealing from the base learning rate down to `min_learning_rate` with warm restarts
+#[derive(RustcEncodable, RustcDecodable, Copy, Clone, Debug)]
+pub struct CosineAnnealing {
+    /// Epochs of the first cycle
+    pub period: f64,
+
+    /// Every cycle is this many times longer than the previous one, 1.0 keeps them equal
+    pub period_mult: f64,
+
+    pub min_learning_rate: f64
+}
+
+impl CosineAnnealing {
+    /// Single cycle without restarts
+    pub fn new(epochs: f64, min_learning_rate: f64) -> Self {
+        CosineAnnealing { period: epochs, period_mult: 1.0, min_learning_rate: min_learning_rate }
+    }
+}
+
+impl Schedule for CosineAnnealing {
+    fn learning_rate(&self, epoch: f64, base: f64) -> f64 {
+        // find the position within the current cycle
+        let (mut start, mut period) = (0.0, self.period);
+        while epoch >= start + period && period > 0.0 {
+            start += period;
+            period *= self.period_mult.max(1.0);
+        }
+
+        let position = if period > 0.0 { (epoch - start) / period } else { 0.0 };
+        self.min_learning_rate + (base - self.min_learning_rate) * (1.0 + (PI * position).cos()) / 2.0
+    }
+}
+
+/// One-cycle policy, the base learning rate is the peak
+///
+/// Rises from `base / div_factor` to `base` during the first `warmup_fraction` of the epochs and
+/// anneals to `base / final_div_factor` afterwards, both along a cosine.
+#[derive(RustcEncodable, RustcDecodable, Copy, Clone, Debug)]
+pub struct OneCycle {
+    pub epochs: f64,
+    pub warmup_fraction: f64,
+    pub div_factor: f64,
+    pub final_div_factor: f64
+}
+
+impl OneCycle {
+    pub fn new(epochs: f64) -> Self {
+        OneCycle { epochs: epochs, warmup_fraction: 0.3, div_factor: 25.0, final_div_factor: 1e4 }
+    }
+}
+
+/// Cosine interpolation from `from` to `to`, `position` runs from 0 to 1
+fn cosine(from: f64, to: f64, position: f64) -> f64 {
+    to + (from - to) * (1.0 + (PI * position.max(0.0).min(1.0)).cos()) / 2.0
+}
+
+impl Schedule for OneCycle {
+    fn learning_rate(&self, epoch: f64, base: f64) -> f64 {
+        let peak = self.epochs * self.warmup_fraction;
+        if epoch < peak {
+            cosine(base / self.div_factor, base, epoch / peak)
+        } else {
+            cosine(base, base / self.final_div_factor, (epoch - peak) / (self.epochs - peak))
+        }
+    }
+}
+
+/// Linear warmup during the first epochs, followed by another schedule
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct Warmup<S: Schedule> {
+    pub epochs: f64,
+
+    /// Fraction of the learning rate used for the first step, greater than 0.0 so that step
+    /// changes the network
+    pub start_factor: f64,
+
+    /// Schedule after the warmup, it sees the progress counted from the start of training
+    pub schedule: S
+}
+
+impl<S: Schedule> Warmup<S> {
+    /// Warmup starting at 1% of the learning rate
+    pub fn new(epochs: f64, schedule: S) -> Self {
+        Warmup {
+            epochs: epochs,
+            start_factor: 0.01,
+            schedule: schedule
+        }
+    }
+}
+
+impl<S: Schedule> Schedule for Warmup<S> {
+    fn learning_rate(&self, epoch: f64, base: f64) -> f64 {
+        let target = self.schedule.learning_rate(epoch, base);
+        if epoch < self.epochs {
+            target * (self.start_factor + (1.0 - self.start_factor) * epoch / self.epochs)
+        } else {
+            target
+        }
+    }
+
+    fn observe(&mut self, loss: f64) {
+        self.schedule.observe(loss);
+    }
+
+    fn state(&self) -> Vec<f64> {
+        self.schedule.state()
+    }
+
+    fn set_state(&mut self, state: &[f64]) {
+        self.schedule.set_state(state);
+    }
+}
+
+/// Lowers the learning rate when the loss stopped improving
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct ReduceOnPlateau {
+    /// Factor applied to the learning rate on a plateau
+    pub factor: f64,
+
+    /// Epochs without improvement before the learning rate is lowered
+    pub patience: usize,
+
+    /// Smallest decrease of the loss that counts as improvement
+    pub min_delta: f64,
+
+    /// Epochs to wait after lowering before counting again
+    pub cooldown: usize,
+
+    pub min_learning_rate: f64,
+
+    best: f64,
+    waiting: usize,
+    cooling: usize,
+    scale: f64
+}
+
+impl ReduceOnPlateau {
+    pub fn new(factor: f64, patience: usize) -> Self {
+        ReduceOnPlateau {
+            factor: factor,
+            patience: patience,
+            min_delta: 1e-4,
+            cooldown: 0,
+            min_learning_rate: 0.0,
+            best: ::std::f64::INFINITY,
+            waiting: 0,
+            cooling: 0,
+            scale: 1.0
+        }
+    }
+
+    /// Factor applied to the base learning rate so far
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+}
+
+impl Schedule for ReduceOnPlateau {
+    fn learning_rate(&self, _: f64, base: f64) -> f64 {
+        (base * self.scale).max(self.min_learning_rate)
+    }
+
+    fn observe(&mut self, loss: f64) {
+        if loss < self.best - self.min_delta {
+            self.best = loss;
+            self.waiting = 0;
+        } else if self.cooling > 0 {
+            self.cooling -= 1;
+        } else {
+            self.waiting += 1;
+            if self.waiting > self.patience {
+                self.scale *= self.factor;
+                self.waiting = 0;
+                self.cooling = self.cooldown;
+            }
+        }
+    }
+
+    fn state(&self) -> Vec<f64> {
+        vec![self.best, self.waiting as f64, self.cooling as f64, self.scale]
+    }
+
+    fn set_state(&mut self, state: &[f64]) {
+        if state.len() == 4 {
+            self.best = state[0];
+            self.waiting = state[1] as usize;
+            self.cooling = state[2] as usize;
+            self.scale = state[3];
+        }
+    }
+}
+
+pub struct RangeTestOptions {
+    /// Learning rate of the first step
+    pub min_learning_rate: f64,
+
+    /// Learning rate of the last step
+    pub max_learning_rate: f64,
+
+    /// Amount of steps, the learning rate grows exponentially between them
+    pub steps: usize,
+
+    pub batch_size: usize,
+
+    /// Exponential smoothing of the recorded loss
+    pub smoothing: f64,
+
+    /// Stops once the smoothed loss exceeds the best one by this factor
+    pub divergence: f64,
+
+    /// Seed for drawing batches, a random seed is chosen when `None`
+    pub seed: Option<usize>
+}
+
+impl RangeTestOptions {
+    pub fn defaults() -> Self {
+        RangeTestOptions {
+            min_learning_rate: 1e-7,
+            max_learning_rate: 10.0,
+            steps: 100,
+            batch_size: 32,
+            smoothing: 0.98,
+            divergence: 4.0,
+            seed: None
+        }
+    }
+}
+
+/// Loss as a function of the learning rate
+#[derive(Clone, Debug, Default)]
+pub struct RangeTest {
+    /// Learning rate and smoothed loss per step
+    pub points: Vec<(f64, f64)>
+}
+
+impl RangeTest {
+    /// Learning rate where the loss falls fastest, a common choice for training
+    pub fn suggestion(&self) -> Option<f64> {
+        let mut best: Option<(f64, f64)> = None;
+        for pair in self.points.windows(2) {
+            let slope = (pair[1].1 - pair[0].1) / (pair[1].0.ln() - pair[0].0.ln());
+            if best.map_or(true, |(_, s)| slope < s) {
+                best = Some((pair[0].0, slope));
+            }
+        }
+        best.map(|(learning_rate, _)| learning_rate)
+    }
+}
+
+/// Learning-rate range test
+///
+/// Trains copies of the network and the optimizer on random batches while growing the learning
+/// rate exponentially and records the smoothed loss of every step, until the loss diverges.
+/// The passed optimizer keeps its state and learning rate.
+pub fn range_test<F, L, O>(network: &NeuralNetwork<F>, loss: &L, optimizer: &O, data: &Dataset<F>, opt_options: Option<RangeTestOptions>) -> Result<RangeTest, TrainerError>
+    where F: Float, L: Loss<F>, O: Optimizer<F> + Clone
+{
+    let options = opt_options.unwrap_or_else(RangeTestOptions::defaults);
+    if data.is_empty() {
+        return Err(TrainerError::EmptyDataset);
+    }
+    if options.batch_size == 0 {
+        return Err(TrainerError::InvalidBatchSize);
+    }
+
+    let seed: &[usize] = &[options.seed.unwrap_or_else(|| OsRng::new().unwrap().gen())];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+
+    let mut network = network.clone();
+    let mut optimizer = optimizer.clone();
+    let growth = (options.max_learning_rate / options.min_learning_rate).powf(1.0 / (::std::cmp::max(options.steps, 2) - 1) as f64);
+    let mut learning_rate = options.min_learning_rate;
+
+    let mut result = RangeTest::default();
+    let (mut average, mut best) = (0.0, ::std::f64::INFINITY);
+    let mut batch = vec![0; ::std::cmp::min(options.batch_size, data.len())];
+
+    for step in 0..options.steps {
+        for index in batch.iter_mut() {
+            *index = rng.gen_range(0, data.len());
+        }
+
+        optimizer.set_learning_rate(learning_rate);
+        let (value, gradient) = try!(loss::gradient(loss, &network, data, &batch));
+        try!(optimizer.step(&mut network, &gradient));
+
+        // smoothed loss with bias correction
+        average = options.smoothing * average + (1.0 - options.smoothing) * value.to_f64();
+        let smoothed = average / (1.0 - options.smoothing.powi(step as i32 + 1));
+
+        if !smoothed.is_finite() || smoothed > options.divergence * best {
+            break;
+        }
+        best = best.min(smoothed);
+
+        result.points.push((learning_rate, smoothed));
+        learning_rate *= growth;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use neural_network::{Neuron, NeuronType};
+    use training::loss::MeanSquaredError;
+    use training::optimizer::Sgd;
+
+    /// Compares the learning rates with base 1.0 at the given epochs
+    fn assert_rates<S: Schedule>(schedule: &S, expected: &[(f64, f64)]) {
+        for &(epoch, rate) in expected {
+            let actual = schedule.learning_rate(epoch, 1.0);
+            assert!((actual - rate).abs() < 1e-12, "epoch {}: {} instead of {}", epoch, actual, rate);
+        }
+    }
+
+    #[test]
+    fn step_decay() {
+        assert_rates(&StepDecay { step_size: 3, gamma: 0.5 }, &[(0.0, 1.0), (2.99, 1.0), (3.0, 0.5), (5.5, 0.5), (6.0, 0.25), (9.0, 0.125)]);
+        assert_rates(&Exponential { gamma: 0.9 }, &[(0.0, 1.0), (0.5, 1.0), (2.5, 0.81)]);
+        assert_eq!(StepDecay { step_size: 3, gamma: 0.5 }.learning_rate(3.0, 0.2), 0.1);
+    }
+
+    #[test]
+    fn cosine_annealing_restarts() {
+        // halfway through a cycle the rate is the mean of base and minimum
+        assert_rates(&CosineAnnealing { period: 10.0, period_mult: 1.0, min_learning_rate: 0.1 },
+            &[(0.0, 1.0), (5.0, 0.55), (10.0, 1.0), (15.0, 0.55), (20.0, 1.0)]);
+
+        // cycles of 10, 20 and 40 epochs
+        let growing = CosineAnnealing { period: 10.0, period_mult: 2.0, min_learning_rate: 0.1 };
+        assert_rates(&growing, &[(5.0, 0.55), (10.0, 1.0), (20.0, 0.55), (30.0, 1.0), (50.0, 0.55), (70.0, 1.0)]);
+        assert!(growing.learning_rate(29.999, 1.0) < 0.1001);
+
+        assert_rates(&CosineAnnealing::new(4.0, 0.0), &[(1.0, (1.0 + (PI / 4.0).cos()) / 2.0), (2.0, 0.5)]);
+    }
+
+    #[test]
+    fn one_cycle() {
+        // peak after 3 of 10 epochs, starting at 1 / 25 and ending at 1 / 10000
+        assert_rates(&OneCycle::new(10.0), &[(0.0, 0.04), (1.5, 0.52), (3.0, 1.0), (6.5, 0.50005), (10.0, 1e-4), (12.0, 1e-4)]);
+    }
+
+    #[test]
+    fn warmup() {
+        assert_rates(&Warmup::new(4.0, Constant), &[(0.0, 0.01), (2.0, 0.505), (4.0, 1.0), (7.0, 1.0)]);
+
+        // the inner schedule sees the progress from the start of training
+        assert_rates(&Warmup::new(2.0, StepDecay { step_size: 1, gamma: 0.5 }), &[(0.0, 0.01), (1.0, 0.2525), (3.0, 0.125)]);
+    }
+
+    #[test]
+    fn reduce_on_plateau() {
+        let mut schedule = ReduceOnPlateau::new(0.5, 1);
+        schedule.observe(1.0);
+        schedule.observe(1.0);
+        assert_rates(&schedule, &[(0.0, 1.0)]);
+
+        // more than `patience` epochs without improvement
+        schedule.observe(1.0);
+        assert_rates(&schedule, &[(0.0, 0.5), (7.0, 0.5)]);
+
+        // improvements smaller than min_delta do not count
+        schedule.observe(0.99995);
+        schedule.observe(0.9999);
+        assert_eq!(schedule.scale(), 0.25);
+
+        schedule.observe(0.5);
+        schedule.observe(0.5);
+        assert_eq!(schedule.scale(), 0.25);
+
+        let mut cooling = ReduceOnPlateau::new(0.1, 0);
+        cooling.cooldown = 2;
+        cooling.min_learning_rate = 0.005;
+        cooling.observe(1.0);
+        cooling.observe(1.0);
+        assert_rates(&cooling, &[(0.0, 0.1)]);
+
+        // two epochs of cooldown, the third one counts again
+        cooling.observe(1.0);
+        cooling.observe(1.0);
+        assert_rates(&cooling, &[(0.0, 0.1)]);
+        cooling.observe(1.0);
+        assert_rates(&cooling, &[(0.0, 0.01)]);
+
+        for _ in 0..3 {
+            cooling.observe(1.0);
+        }
+        assert_rates(&cooling, &[(0.0, 0.005)]);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut schedule = ReduceOnPlateau::new(0.5, 1);
+        schedule.cooldown = 1;
+        for loss in &[1.0, 1.0, 1.0, 1.0] {
+            schedule.observe(*loss);
+        }
+
+        let mut restored = Warmup::new(1.0, ReduceOnPlateau::new(0.5, 1));
+        restored.schedule.cooldown = 1;
+        restored.set_state(&schedule.state());
+        assert_eq!(restored.state(), schedule.state());
+
+        // both continue identically
+        for loss in &[1.0, 1.0, 0.5, 0.7, 0.7, 0.7] {
+            schedule.observe(*loss);
+            restored.observe(*loss);
+            assert_eq!(restored.state(), schedule.state());
+            assert_eq!(restored.learning_rate(2.0, 1.0), schedule.learning_rate(2.0, 1.0));
+        }
+
+        // states of another length are ignored
+        let before = schedule.state();
+        schedule.set_state(&[0.1]);
+        assert_eq!(schedule.state(), before);
+        assert!(Constant.state().is_empty());
+    }
+
+    /// Single weight fitting `y = 2 x`
+    fn linear() -> (NeuralNetwork, Dataset) {
+        let network = NeuralNetwork::from_layers(1, vec![vec![Neuron::new(vec![0.0], 0.0, NeuronType::Identity)]]);
+        let xs: Vec<f64> = (0..16).map(|i| i as f64 / 8.0 - 1.0).collect();
+        let data = Dataset::new(xs.iter().map(|x| vec![*x]).collect(), xs.iter().map(|x| vec![2.0 * x]).collect()).unwrap();
+        (network, data)
+    }
+
+    fn range_options(min: f64, max: f64, steps: usize) -> RangeTestOptions {
+        let mut options = RangeTestOptions::defaults();
+        options.min_learning_rate = min;
+        options.max_learning_rate = max;
+        options.steps = steps;
+        options.batch_size = 4;
+        options.smoothing = 0.5;
+        options.seed = Some(1);
+        options
+    }
+
+    #[test]
+    fn range_test_grows_learning_rate() {
+        let (network, data) = linear();
+        let optimizer = Sgd::new(0.05);
+
+        let result = range_test(&network, &MeanSquaredError, &optimizer, &data, Some(range_options(1e-4, 1e-1, 4))).unwrap();
+        let rates: Vec<f64> = result.points.iter().map(|point| point.0).collect();
+        for (rate, expected) in rates.iter().zip(&[1e-4, 1e-3, 1e-2, 1e-1]) {
+            assert!((rate - expected).abs() < 1e-12 * expected, "{:?}", rates);
+        }
+        assert_eq!(rates.len(), 4);
+
+        // copies are trained, the optimizer keeps its learning rate
+        assert_eq!(optimizer.learning_rate(), 0.05);
+
+        let again = range_test(&network, &MeanSquaredError, &optimizer, &data, Some(range_options(1e-4, 1e-1, 4))).unwrap();
+        assert_eq!(again.points, result.points);
+    }
+
+    #[test]
+    fn range_test_stops_on_divergence() {
+        let (network, data) = linear();
+        let mut options = range_options(1e-3, 1e3, 60);
+        options.divergence = 4.0;
+
+        let result = range_test(&network, &MeanSquaredError, &Sgd::new(0.1), &data, Some(options)).unwrap();
+        assert!(result.points.len() > 10 && result.points.len() < 60, "{} points", result.points.len());
+
+        // every recorded loss stays within the divergence factor of the best one before it
+        let mut best = ::std::f64::INFINITY;
+        for point in &result.points {
+            assert!(point.1.is_finite() && point.1 <= 4.0 * best, "{:?}", result.points);
+            best = best.min(point.1);
+        }
+
+        // the loss falls fastest before it diverges
+        let suggestion = result.suggestion().unwrap();
+        assert!(suggestion > 1e-3 && suggestion < result.points[result.points.len() - 1].0);
+    }
+
+    #[test]
+    fn range_test_errors() {
+        let (network, data) = linear();
+        let empty: Dataset = Dataset::new(Vec::new(), Vec::new()).unwrap();
+
+        match range_test(&network, &MeanSquaredError, &Sgd::new(0.1), &empty, None) {
+            Err(TrainerError::EmptyDataset) => { },
+            result => panic!("expected EmptyDataset, got {:?}", result)
+        }
+
+        let mut options = range_options(1e-3, 1.0, 10);
+        options.batch_size = 0;
+        match range_test(&network, &MeanSquaredError, &Sgd::new(0.1), &data, Some(options)) {
+            Err(TrainerError::InvalidBatchSize) => { },
+            result => panic!("expected InvalidBatchSize, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn suggestion_picks_steepest_descent() {
+        let test = RangeTest { points: vec![(0.001, 1.0), (0.01, 0.9), (0.1, 0.4), (1.0, 0.3), (10.0, 2.0)] };
+        assert_eq!(test.suggestion(), Some(0.01));
+        assert_eq!(RangeTest { points: vec![(0.1, 1.0)] }.suggestion(), None);
+    }
+}
@@ -0,0 +1,635 @@
+//! Hyperparameter search
+//!
+//! A `SearchSpace` lists the values tried for every hyperparameter. The `Strategy` decides which
+//! configurations are evaluated with which budget, usually the amount of epochs. Evaluation is
+//! done by an `Evaluator`, either a closure or a `TrainerEvaluator`, and returns a score where
+//! lower is better. Trials of one round run in parallel on a `scoped_threadpool::Pool`.
+//!
+//! ```ignore
+//! let mut space = SearchSpace::new();
+//! space.learning_rates = log_space(1e-4, 1e-1, 4);
+//! let evaluator = TrainerEvaluator::new(MeanSquaredError, &train, Some(&validation), NeuronType::Identity, |lr| Adam::new(lr));
+//! let table = try!(search(&space, &Strategy::Hyperband { min_budget: 1, max_budget: 27, eta: 3 }, &evaluator, None));
+//! try!(table.write_csv(&mut File::create("search.csv").unwrap()));
+//! ```
+
+use std::cmp::{self, Ordering};
+use std::error::Error;
+use std::f64;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use rand::{Rng, OsRng, SeedableRng, StdRng};
+use scoped_threadpool::Pool;
+
+use neural_network::{NeuralNetwork, Neuron, NeuronType};
+use neural_network::float::Float;
+
+use training::dataset::Dataset;
+use training::early_stopping::EarlyStopping;
+use training::loss::Loss;
+use training::optimizer::Optimizer;
+use training::regularization::{Penalty, Regularization};
+use training::trainer::{Trainer, TrainerError, TrainerOptions};
+
+/// Errors for search
+#[derive(Debug)]
+pub enum SearchError {
+    /// A hyperparameter of the search space has no values
+    EmptySpace,
+
+    /// The strategy has no trials, a budget of zero, a minimum above the maximum budget or an
+    /// `eta` below 2
+    InvalidStrategy
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SearchError::EmptySpace => write!(f, "every hyperparameter needs at least one value"),
+            SearchError::InvalidStrategy => write!(f, "invalid search strategy")
+        }
+    }
+}
+
+impl Error for SearchError {
+    fn description(&self) -> &str {
+        "search error"
+    }
+}
+
+/// Values tried for every hyperparameter
+#[derive(Clone, Debug)]
+pub struct SearchSpace {
+    /// Amounts of hidden layers, the output layer is not counted
+    pub hidden_layers: Vec<usize>,
+
+    /// Widths of the hidden layers, all hidden layers of a network share one width
+    pub widths: Vec<usize>,
+
+    /// Activation functions of the hidden layers
+    pub activations: Vec<NeuronType>,
+
+    pub learning_rates: Vec<f64>,
+
+    /// L1 and L2 penalties
+    pub penalties: Vec<Penalty>
+}
+
+impl SearchSpace {
+    pub fn new() -> Self {
+        SearchSpace {
+            hidden_layers: vec![1, 2],
+            widths: vec![8, 32],
+            activations: vec![NeuronType::TanH, NeuronType::DeLu],
+            learning_rates: log_space(1e-3, 1e-1, 3),
+            penalties: vec![Penalty::default()]
+        }
+    }
+
+    /// Amount of configurations on the grid
+    pub fn size(&self) -> usize {
+        self.hidden_layers.len() * self.widths.len() * self.activations.len() * self.learning_rates.len() * self.penalties.len()
+    }
+
+    /// All configurations, the last hyperparameter changes fastest
+    pub fn grid(&self) -> Vec<Config> {
+        let mut configs = Vec::with_capacity(self.size());
+
+        for &hidden_layers in &self.hidden_layers {
+            for &width in &self.widths {
+                for &activation in &self.activations {
+                    for &learning_rate in &self.learning_rates {
+                        for &penalty in &self.penalties {
+                            configs.push(Config {
+                                hidden_layers: hidden_layers,
+                                width: width,
+                                activation: activation,
+                                learning_rate: learning_rate,
+                                penalty: penalty
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        configs
+    }
+
+    /// Picks every hyperparameter uniformly at random
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Config {
+        Config {
+            hidden_layers: self.hidden_layers[rng.gen_range(0, self.hidden_layers.len())],
+            width: self.widths[rng.gen_range(0, self.widths.len())],
+            activation: self.activations[rng.gen_range(0, self.activations.len())],
+            learning_rate: self.learning_rates[rng.gen_range(0, self.learning_rates.len())],
+            penalty: self.penalties[rng.gen_range(0, self.penalties.len())]
+        }
+    }
+}
+
+/// `count` values from `min` to `max` (both inclusive), evenly spaced on a log scale
+pub fn log_space(min: f64, max: f64, count: usize) -> Vec<f64> {
+    if count <= 1 {
+        return vec![min];
+    }
+
+    let (low, high) = (min.ln(), max.ln());
+    (0..count).map(|i| (low + (high - low) * i as f64 / (count - 1) as f64).exp()).collect()
+}
+
+/// One point of the search space
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Config {
+    pub hidden_layers: usize,
+    pub width: usize,
+    pub activation: NeuronType,
+    pub learning_rate: f64,
+    pub penalty: Penalty
+}
+
+impl Config {
+    /// Builds the network of the configuration with an output layer of the given type
+    ///
+    /// Weights are drawn from `±1/sqrt(fan in)` with a generator seeded by `seed`.
+    pub fn network<F: Float>(&self, inputs: usize, outputs: usize, output_type: NeuronType, seed: usize) -> NeuralNetwork<F> {
+        let seed: &[usize] = &[seed];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+
+        let mut layers = Vec::with_capacity(self.hidden_layers + 1);
+        let mut width = inputs;
+
+        for layer in 0..self.hidden_layers + 1 {
+            let (count, neuron_type) = if layer < self.hidden_layers { (self.width, self.activation) } else { (outputs, output_type) };
+            let limit = 1.0 / (cmp::max(width, 1) as f64).sqrt();
+
+            let neurons = (0..count).map(|_| {
+                let weights = (0..width).map(|_| F::from_f64(rng.gen_range(-limit, limit))).collect();
+                Neuron::new(weights, F::from_f64(rng.gen_range(-limit, limit)), neuron_type)
+            }).collect();
+
+            layers.push(neurons);
+            width = count;
+        }
+
+        NeuralNetwork::from_layers(inputs, layers)
+    }
+}
+
+/// A configuration being evaluated
+#[derive(Clone, Debug)]
+pub struct Trial {
+    /// Number of the trial, unique within a search
+    pub id: usize,
+
+    pub config: Config,
+
+    /// Seed for the initial weights and shuffling, the same for every budget of the trial
+    pub seed: usize
+}
+
+/// How configurations are chosen and how much budget they get
+#[derive(Clone, Debug)]
+pub enum Strategy {
+    /// Every configuration of the grid
+    Grid { budget: usize },
+
+    /// Randomly sampled configurations
+    Random { trials: usize, budget: usize },
+
+    /// Evaluates `trials` random configurations with `min_budget`, then repeatedly keeps the
+    /// best `1/eta` of them and multiplies the budget by `eta` until `max_budget` is reached
+    SuccessiveHalving { trials: usize, min_budget: usize, max_budget: usize, eta: usize },
+
+    /// Runs successive halving brackets that trade the amount of configurations against the
+    /// budget they start with, from many trials at `min_budget` down to few at `max_budget`
+    Hyperband { min_budget: usize, max_budget: usize, eta: usize }
+}
+
+pub struct SearchOptions {
+    /// Trials evaluated at the same time
+    pub threads: usize,
+
+    /// Seed for sampling configurations and trial seeds, a random seed is chosen when `None`
+    pub seed: Option<usize>
+}
+
+impl SearchOptions {
+    pub fn defaults() -> Self {
+        SearchOptions {
+            threads: 4,
+            seed: None
+        }
+    }
+}
+
+/// Evaluates trials, shared between all threads
+pub trait Evaluator: Sync {
+    /// Score of the trial after training with the given budget, lower is better
+    fn evaluate(&self, trial: &Trial, budget: usize) -> f64;
+}
+
+impl<C> Evaluator for C where C: Fn(&Trial, usize) -> f64 + Sync {
+    fn evaluate(&self, trial: &Trial, budget: usize) -> f64 {
+        self(trial, budget)
+    }
+}
+
+/// Trains every trial with a `Trainer` for `budget` epochs
+///
+/// The score is the loss of the best epoch, on the validation set when there is one. Trials
+/// that fail to train get a score of infinity.
+pub struct TrainerEvaluator<'a, F: Float + 'a, L, B> {
+    pub loss: L,
+    pub train: &'a Dataset<F>,
+    pub validation: Option<&'a Dataset<F>>,
+
+    /// Activation of the output layer
+    pub output_type: NeuronType,
+
+    /// Creates the optimizer for a learning rate
+    pub optimizer: B,
+
+    /// `epochs` and `seed` are replaced by the budget and the seed of the trial
+    pub options: TrainerOptions,
+
+    pub early_stopping: Option<EarlyStopping>
+}
+
+impl<'a, F: Float, L, O, B> TrainerEvaluator<'a, F, L, B>
+    where L: Loss<F> + Clone + Sync, O: Optimizer<F>, B: Fn(f64) -> O + Sync
+{
+    pub fn new(loss: L, train: &'a Dataset<F>, validation: Option<&'a Dataset<F>>, output_type: NeuronType, optimizer: B) -> Self {
+        TrainerEvaluator {
+            loss: loss,
+            train: train,
+            validation: validation,
+            output_type: output_type,
+            optimizer: optimizer,
+            options: TrainerOptions::defaults(),
+            early_stopping: None
+        }
+    }
+
+    /// Trains a trial and returns the trainer
+    pub fn train(&self, trial: &Trial, budget: usize) -> Result<Trainer<F, L, O>, TrainerError> {
+        let network = trial.config.network(self.train.input_len(), self.train.target_len(), self.output_type, trial.seed);
+
+        let mut options = self.options.clone();
+        options.epochs = budget;
+        options.seed = Some(trial.seed);
+
+        let mut trainer = Trainer::new(network, self.loss.clone(), (self.optimizer)(trial.config.learning_rate), Some(options));
+        if trial.config.penalty != Penalty::default() {
+            trainer.set_regularization(Regularization::penalties(trial.config.penalty.l1, trial.config.penalty.l2));
+        }
+        if let Some(ref early_stopping) = self.early_stopping {
+            trainer.set_early_stopping(early_stopping.clone());
+        }
+
+        try!(trainer.fit(self.train, self.validation));
+        Ok(trainer)
+    }
+}
+
+impl<'a, F: Float, L, O, B> Evaluator for TrainerEvaluator<'a, F, L, B>
+    where L: Loss<F> + Clone + Sync, O: Optimizer<F>, B: Fn(f64) -> O + Sync
+{
+    fn evaluate(&self, trial: &Trial, budget: usize) -> f64 {
+        match self.train(trial, budget) {
+            Ok(trainer) => trainer.history().best().map_or(f64::INFINITY, |best| best.validation_loss.unwrap_or(best.train_loss)),
+            Err(err) => {
+                warn!(target: "search", "Could not train trial {}: {}", trial.id, err);
+                f64::INFINITY
+            }
+        }
+    }
+}
+
+/// Score of a trial with one budget
+#[derive(Clone, Debug)]
+pub struct TrialResult {
+    pub trial: Trial,
+
+    /// Hyperband bracket, 0 for the other strategies
+    pub bracket: usize,
+
+    /// Round of successive halving, 0 for grid and random search
+    pub rung: usize,
+
+    pub budget: usize,
+    pub score: f64,
+
+    /// Wall-clock time of the evaluation
+    pub seconds: f64
+}
+
+/// All evaluations of a search in the order they finished rounds
+#[derive(Clone, Debug, Default)]
+pub struct ResultTable {
+    pub results: Vec<TrialResult>
+}
+
+/// Orders scores ascending with NaN last
+fn compare_scores(a: f64, b: f64) -> Ordering {
+    let key = |score: f64| if score.is_nan() { f64::INFINITY } else { score };
+    key(a).partial_cmp(&key(b)).unwrap()
+}
+
+impl ResultTable {
+    /// Results evaluated with the largest budget, best first
+    pub fn ranked(&self) -> Vec<&TrialResult> {
+        let budget = self.results.iter().map(|result| result.budget).max().unwrap_or(0);
+        let mut ranked: Vec<&TrialResult> = self.results.iter().filter(|result| result.budget == budget).collect();
+        ranked.sort_by(|a, b| compare_scores(a.score, b.score));
+        ranked
+    }
+
+    /// Best result with the largest budget
+    pub fn best(&self) -> Option<&TrialResult> {
+        self.ranked().into_iter().next()
+    }
+
+    /// Writes all results as CSV with a header line
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        try!(writeln!(writer, "trial,bracket,rung,budget,hidden_layers,width,activation,learning_rate,l1,l2,seed,score,seconds"));
+
+        for result in &self.results {
+            let config = &result.trial.config;
+            try!(writeln!(writer, "{},{},{},{},{},{},{:?},{},{},{},{},{},{}",
+                result.trial.id, result.bracket, result.rung, result.budget, config.hidden_layers, config.width,
+                config.activation, config.learning_rate, config.penalty.l1, config.penalty.l2, result.trial.seed,
+                result.score, result.seconds));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ResultTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "{:>6} {:>7} {:>6} {:>7} {:>6} {:>6} {:>10} {:>10} {:>10} {:>10} {:>12}",
+            "trial", "bracket", "rung", "budget", "layers", "width", "activation", "lr", "l1", "l2", "score"));
+
+        for result in &self.results {
+            let config = &result.trial.config;
+            try!(writeln!(f, "{:>6} {:>7} {:>6} {:>7} {:>6} {:>6} {:>10} {:>10.2e} {:>10.2e} {:>10.2e} {:>12.6}",
+                result.trial.id, result.bracket, result.rung, result.budget, config.hidden_layers, config.width,
+                format!("{:?}", config.activation), config.learning_rate, config.penalty.l1, config.penalty.l2, result.score));
+        }
+
+        Ok(())
+    }
+}
+
+/// Evaluates all trials with the same budget on the pool
+fn evaluate_round<E: Evaluator>(pool: &mut Pool, evaluator: &E, trials: &[Trial], budget: usize, bracket: usize, rung: usize) -> Vec<TrialResult> {
+    let mut results: Vec<Option<TrialResult>> = trials.iter().map(|_| None).collect();
+
+    pool.scoped(|scope| {
+        for (trial, result) in trials.iter().zip(results.iter_mut()) {
+            scope.execute(move || {
+                let start = Instant::now();
+                let score = evaluator.evaluate(trial, budget);
+                let elapsed = start.elapsed();
+
+                *result = Some(TrialResult {
+                    trial: trial.clone(),
+                    bracket: bracket,
+                    rung: rung,
+                    budget: budget,
+                    score: score,
+                    seconds: elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9
+                });
+            });
+        }
+    });
+
+    results.into_iter().map(|result| result.unwrap()).collect()
+}
+
+/// Runs successive halving on the given trials and appends every round to `table`
+fn successive_halving<E: Evaluator>(pool: &mut Pool, evaluator: &E, mut trials: Vec<Trial>, min_budget: usize, max_budget: usize, eta: usize, bracket: usize, table: &mut ResultTable) {
+    let mut budget = min_budget;
+    let mut rung = 0;
+
+    loop {
+        let mut results = evaluate_round(pool, evaluator, &trials, budget, bracket, rung);
+        table.results.extend(results.iter().cloned());
+
+        info!(target: "search", "Bracket {}, rung {}: evaluated {} trials with a budget of {}", bracket, rung, trials.len(), budget);
+
+        if budget >= max_budget {
+            break;
+        }
+
+        let keep = cmp::max(trials.len() / eta, 1);
+        results.sort_by(|a, b| compare_scores(a.score, b.score));
+        trials = results.into_iter().take(keep).map(|result| result.trial).collect();
+
+        budget = cmp::min(budget.saturating_mul(eta), max_budget);
+        rung += 1;
+    }
+}
+
+/// Searches the space with the given strategy
+pub fn search<E: Evaluator>(space: &SearchSpace, strategy: &Strategy, evaluator: &E, opt_options: Option<SearchOptions>) -> Result<ResultTable, SearchError> {
+    let options = opt_options.unwrap_or_else(SearchOptions::defaults);
+
+    if space.size() == 0 {
+        return Err(SearchError::EmptySpace);
+    }
+
+    let valid = match *strategy {
+        Strategy::Grid { budget } => budget > 0,
+        Strategy::Random { trials, budget } => trials > 0 && budget > 0,
+        Strategy::SuccessiveHalving { trials, min_budget, max_budget, eta } => trials > 0 && min_budget > 0 && min_budget <= max_budget && eta >= 2,
+        Strategy::Hyperband { min_budget, max_budget, eta } => min_budget > 0 && min_budget <= max_budget && eta >= 2
+    };
+    if !valid {
+        return Err(SearchError::InvalidStrategy);
+    }
+
+    let seed: &[usize] = &[options.seed.unwrap_or_else(|| OsRng::new().unwrap().gen())];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let mut pool = Pool::new(cmp::max(options.threads, 1) as u32);
+    let mut table = ResultTable::default();
+    let mut next_id = 0;
+
+    let mut new_trials = |configs: Vec<Config>, rng: &mut StdRng| -> Vec<Trial> {
+        configs.into_iter().map(|config| {
+            next_id += 1;
+            Trial { id: next_id - 1, config: config, seed: rng.gen() }
+        }).collect()
+    };
+
+    match *strategy {
+        Strategy::Grid { budget } => {
+            let trials = new_trials(space.grid(), &mut rng);
+            table.results = evaluate_round(&mut pool, evaluator, &trials, budget, 0, 0);
+        },
+        Strategy::Random { trials, budget } => {
+            let configs = (0..trials).map(|_| space.sample(&mut rng)).collect();
+            let trials = new_trials(configs, &mut rng);
+            table.results = evaluate_round(&mut pool, evaluator, &trials, budget, 0, 0);
+        },
+        Strategy::SuccessiveHalving { trials, min_budget, max_budget, eta } => {
+            let configs = (0..trials).map(|_| space.sample(&mut rng)).collect();
+            let trials = new_trials(configs, &mut rng);
+            successive_halving(&mut pool, evaluator, trials, min_budget, max_budget, eta, 0, &mut table);
+        },
+        Strategy::Hyperband { min_budget, max_budget, eta } => {
+            // largest s with min_budget * eta^s <= max_budget, budgets beyond usize do not fit
+            let mut brackets: usize = 1;
+            while eta.checked_pow(brackets as u32).and_then(|factor| min_budget.checked_mul(factor)).map_or(false, |budget| budget <= max_budget) {
+                brackets += 1;
+            }
+
+            for bracket in 0..brackets {
+                let s = brackets - 1 - bracket;
+                let trials = brackets.saturating_mul(eta.pow(s as u32)).saturating_add(s) / (s + 1);
+                let budget = cmp::max(max_budget / eta.pow(s as u32), min_budget);
+
+                let configs = (0..trials).map(|_| space.sample(&mut rng)).collect();
+                let trials = new_trials(configs, &mut rng);
+                successive_halving(&mut pool, evaluator, trials, budget, max_budget, eta, bracket, &mut table);
+            }
+        }
+    }
+
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn space() -> SearchSpace {
+        let mut space = SearchSpace::new();
+        space.hidden_layers = vec![1, 2];
+        space.widths = vec![4];
+        space.activations = vec![NeuronType::TanH, NeuronType::SigMoid];
+        space.learning_rates = log_space(1e-3, 1e-1, 3);
+        space
+    }
+
+    fn options() -> Option<SearchOptions> {
+        Some(SearchOptions { threads: 3, seed: Some(1) })
+    }
+
+    /// Lower ids score better, so the survivors of every rung are known
+    fn by_id(trial: &Trial, _: usize) -> f64 {
+        trial.id as f64
+    }
+
+    /// `(bracket, rung, budget, trials)` of every round
+    fn rounds(table: &ResultTable) -> Vec<(usize, usize, usize, usize)> {
+        let mut rounds: Vec<(usize, usize, usize, usize)> = Vec::new();
+        for result in &table.results {
+            match rounds.last_mut() {
+                Some(round) if (round.0, round.1) == (result.bracket, result.rung) => {
+                    assert_eq!(round.2, result.budget);
+                    round.3 += 1;
+                    continue;
+                },
+                _ => { }
+            }
+            rounds.push((result.bracket, result.rung, result.budget, 1));
+        }
+        rounds
+    }
+
+    fn ids(table: &ResultTable, bracket: usize, rung: usize) -> Vec<usize> {
+        table.results.iter().filter(|result| result.bracket == bracket && result.rung == rung).map(|result| result.trial.id).collect()
+    }
+
+    #[test]
+    fn grid_covers_space() {
+        let space = space();
+        let table = search(&space, &Strategy::Grid { budget: 3 }, &by_id, options()).unwrap();
+
+        assert_eq!(space.size(), 12);
+        assert_eq!(table.results.len(), space.size());
+        assert_eq!(table.results.iter().map(|result| result.trial.config).collect::<Vec<_>>(), space.grid());
+        assert_eq!(table.results.iter().map(|result| result.trial.id).collect::<Vec<_>>(), (0..12).collect::<Vec<_>>());
+        assert!(table.results.iter().all(|result| result.budget == 3 && result.rung == 0));
+        assert_eq!(table.best().unwrap().trial.id, 0);
+    }
+
+    #[test]
+    fn successive_halving_keeps_best_fraction() {
+        let strategy = Strategy::SuccessiveHalving { trials: 27, min_budget: 1, max_budget: 9, eta: 3 };
+        let table = search(&space(), &strategy, &by_id, options()).unwrap();
+        assert_eq!(rounds(&table), vec![(0, 0, 1, 27), (0, 1, 3, 9), (0, 2, 9, 3)]);
+
+        // the best n / eta of a rung continue, best first
+        assert_eq!(ids(&table, 0, 1), (0..9).collect::<Vec<_>>());
+        assert_eq!(ids(&table, 0, 2), vec![0, 1, 2]);
+
+        // at least one trial continues, the budget stops at the maximum
+        let strategy = Strategy::SuccessiveHalving { trials: 10, min_budget: 2, max_budget: 10, eta: 4 };
+        let table = search(&space(), &strategy, &|trial: &Trial, _: usize| -(trial.id as f64), options()).unwrap();
+        assert_eq!(rounds(&table), vec![(0, 0, 2, 10), (0, 1, 8, 2), (0, 2, 10, 1)]);
+        assert_eq!(ids(&table, 0, 2), vec![9]);
+    }
+
+    #[test]
+    fn hyperband_brackets() {
+        let strategy = Strategy::Hyperband { min_budget: 1, max_budget: 9, eta: 3 };
+        let table = search(&space(), &strategy, &by_id, options()).unwrap();
+
+        // three brackets: s = 2 starts ceil(3 * 9 / 3) trials at 9 / 9, s = 1 ceil(3 * 3 / 2) at 9 / 3, s = 0 three at 9
+        assert_eq!(rounds(&table), vec![
+            (0, 0, 1, 9), (0, 1, 3, 3), (0, 2, 9, 1),
+            (1, 0, 3, 5), (1, 1, 9, 1),
+            (2, 0, 9, 3)
+        ]);
+
+        // a fixed seed repeats the configurations and trial seeds
+        let again = search(&space(), &strategy, &by_id, options()).unwrap();
+        let trials = |table: &ResultTable| table.results.iter().map(|result| (result.trial.id, result.trial.config, result.trial.seed)).collect::<Vec<_>>();
+        assert_eq!(trials(&table), trials(&again));
+
+        let other = search(&space(), &strategy, &by_id, Some(SearchOptions { threads: 1, seed: Some(2) })).unwrap();
+        assert!(trials(&table) != trials(&other));
+    }
+
+    #[test]
+    fn hyperband_budgets_near_overflow() {
+        let max = ::std::usize::MAX;
+
+        let single = search(&space(), &Strategy::Hyperband { min_budget: max, max_budget: max, eta: 2 }, &by_id, options()).unwrap();
+        assert_eq!(rounds(&single), vec![(0, 0, max, 1)]);
+
+        let quarter = max / 4;
+        let table = search(&space(), &Strategy::Hyperband { min_budget: quarter, max_budget: max, eta: 2 }, &by_id, options()).unwrap();
+        let first = rounds(&table).into_iter().filter(|round| round.0 == 0).collect::<Vec<_>>();
+        assert_eq!(first, vec![(0, 0, quarter, 4), (0, 1, 2 * quarter, 2), (0, 2, 4 * quarter, 1), (0, 3, max, 1)]);
+        assert_eq!(table.results.iter().map(|result| result.bracket).max(), Some(2));
+    }
+
+    #[test]
+    fn rejects_invalid_searches() {
+        let mut empty = space();
+        empty.widths.clear();
+        match search(&empty, &Strategy::Grid { budget: 1 }, &by_id, options()) {
+            Err(SearchError::EmptySpace) => { },
+            result => panic!("expected EmptySpace, got {:?}", result)
+        }
+
+        let invalid = vec![
+            Strategy::Grid { budget: 0 },
+            Strategy::Random { trials: 0, budget: 1 },
+            Strategy::SuccessiveHalving { trials: 4, min_budget: 3, max_budget: 2, eta: 2 },
+            Strategy::Hyperband { min_budget: 1, max_budget: 9, eta: 1 }
+        ];
+        for strategy in invalid {
+            match search(&space(), &strategy, &by_id, options()) {
+                Err(SearchError::InvalidStrategy) => { },
+                result => panic!("{:?}: expected InvalidStrategy, got {:?}", strategy, result)
+            }
+        }
+    }
+}
@@ -0,0 +1,673 @@
+//! Mini-batch training loop
+//!
+//! The samples of every epoch are shuffled with a generator seeded by `(seed, epoch)`, so a run
+//! with a fixed seed always visits the samples in the same order.
+
+use std::error::Error;
+use std::fmt;
+
+use rand::{Rng, OsRng, SeedableRng, StdRng};
+use rustc_serialize::{Encodable, Decodable};
+use scoped_threadpool::Pool;
+
+use evolution::Control;
+use neural_network::NeuralNetwork;
+use neural_network::float::Float;
+use neural_network::gradient::{Gradient, GradientError};
+
+use training::callback::{Callback, CallbackContext};
+use training::checkpoint::{Checkpoint, CheckpointError, CheckpointManager};
+use training::dataset::Dataset;
+use training::early_stopping::EarlyStopping;
+use training::loss::{self, Loss, LossError};
+use training::optimizer::Optimizer;
+use training::parallel;
+use training::regularization::{Regularization, PenaltyValue};
+use training::schedule::Schedule;
+
+/// Errors for Trainer
+#[derive(Debug)]
+pub enum TrainerError {
+    /// The training dataset has no samples
+    EmptyDataset,
+
+    /// Batch size of zero
+    InvalidBatchSize,
+
+    /// The loss could not be calculated
+    Loss(LossError),
+
+    /// The optimizer could not apply the gradient
+    Gradient(GradientError),
+
+    /// A checkpoint could not be saved or loaded
+    Checkpoint(CheckpointError)
+}
+
+impl fmt::Display for TrainerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TrainerError::EmptyDataset => write!(f, "training dataset is empty"),
+            TrainerError::InvalidBatchSize => write!(f, "batch size must be at least 1"),
+            TrainerError::Loss(ref err) => write!(f, "{}", err),
+            TrainerError::Gradient(ref err) => write!(f, "{}", err),
+            TrainerError::Checkpoint(ref err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl Error for TrainerError {
+    fn description(&self) -> &str {
+        "trainer error"
+    }
+}
+
+impl From<LossError> for TrainerError {
+    fn from(err: LossError) -> Self {
+        TrainerError::Loss(err)
+    }
+}
+
+impl From<GradientError> for TrainerError {
+    fn from(err: GradientError) -> Self {
+        TrainerError::Gradient(err)
+    }
+}
+
+impl From<CheckpointError> for TrainerError {
+    fn from(err: CheckpointError) -> Self {
+        TrainerError::Checkpoint(err)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TrainerOptions {
+    /// Amount of epochs `fit` runs
+    pub epochs: usize,
+
+    /// Samples per mini-batch, the last batch of an epoch may be smaller
+    pub batch_size: usize,
+
+    /// Shuffles the samples before every epoch
+    pub shuffle: bool,
+
+    /// Seed for shuffling, a random seed is chosen when `None`
+    pub seed: Option<usize>
+}
+
+impl TrainerOptions {
+    pub fn defaults() -> Self {
+        TrainerOptions {
+            epochs: 100,
+            batch_size: 32,
+            shuffle: true,
+            seed: None
+        }
+    }
+}
+
+/// Results of a single epoch
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct EpochRecord {
+    pub epoch: usize,
+
+    /// Mean loss of the mini-batches, measured before each update, without penalties
+    pub train_loss: f64,
+
+    /// Contribution of the L1 and L2 penalties to the loss after the epoch
+    pub penalty: PenaltyValue,
+
+    /// Loss on the validation set after the epoch
+    pub validation_loss: Option<f64>,
+
+    /// Learning rate at the start of the epoch
+    pub learning_rate: f64,
+
+    /// Metrics after the epoch, on the validation set if given and the training set otherwise
+    pub metrics: Vec<(String, f64)>
+}
+
+/// Records of all epochs trained so far
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, Default)]
+pub struct History {
+    pub epochs: Vec<EpochRecord>
+}
+
+impl History {
+    pub fn last(&self) -> Option<&EpochRecord> {
+        self.epochs.last()
+    }
+
+    /// Epoch with the lowest validation loss, or training loss without validation
+    pub fn best(&self) -> Option<&EpochRecord> {
+        let key = |record: &EpochRecord| record.validation_loss.unwrap_or(record.train_loss);
+        self.epochs.iter().fold(None, |best: Option<&EpochRecord>, record| match best {
+            Some(best) if key(best) <= key(record) => Some(best),
+            _ => Some(record)
+        })
+    }
+
+    /// Values of a metric over all epochs
+    pub fn metric(&self, name: &str) -> Vec<f64> {
+        self.epochs.iter().filter_map(|record| {
+            record.metrics.iter().find(|metric| metric.0 == name).map(|metric| metric.1)
+        }).collect()
+    }
+}
+
+/// Metric evaluated after every epoch
+pub type Metric<F> = Box<dyn Fn(&NeuralNetwork<F>, &Dataset<F>) -> f64>;
+
+/// `parallel::gradient` for a loss, only available for losses that are `Sync`
+type ParallelGradient<F, L> = fn(&mut Pool, &L, &NeuralNetwork<F>, &Dataset<F>, &[usize]) -> Result<(F, Gradient<F>), LossError>;
+
+/// Trains a network with a loss and an optimizer
+pub struct Trainer<F: Float, L: Loss<F>, O: Optimizer<F>> {
+    network: NeuralNetwork<F>,
+    loss: L,
+    optimizer: O,
+    options: TrainerOptions,
+    seed: usize,
+    metrics: Vec<(String, Metric<F>)>,
+    history: History,
+
+    schedule: Option<Box<dyn Schedule>>,
+    regularization: Option<Regularization>,
+
+    early_stopping: Option<EarlyStopping>,
+
+    /// Copy of the network after the best epoch, kept for early stopping
+    best_network: Option<NeuralNetwork<F>>,
+
+    callbacks: Vec<Box<dyn Callback<F>>>,
+
+    /// Set when a callback returned `Control::Stop`
+    stop_requested: bool,
+
+    /// Workers for data-parallel training, only created for more than one thread
+    parallel: Option<(Pool, ParallelGradient<F, L>)>,
+
+    /// Learning rate of the optimizer when the trainer was created, schedules scale it
+    base_learning_rate: f64
+}
+
+impl<F: Float, L: Loss<F>, O: Optimizer<F>> Trainer<F, L, O> {
+    pub fn new(network: NeuralNetwork<F>, loss: L, optimizer: O, opt_options: Option<TrainerOptions>) -> Self {
+        let options = opt_options.unwrap_or_else(TrainerOptions::defaults);
+        let seed = match options.seed {
+            Some(seed) => seed,
+            None => OsRng::new().unwrap().gen()
+        };
+
+        Trainer {
+            network: network,
+            loss: loss,
+            options: options,
+            seed: seed,
+            metrics: Vec::new(),
+            history: History::default(),
+            schedule: None,
+            regularization: None,
+            early_stopping: None,
+            best_network: None,
+            callbacks: Vec::new(),
+            stop_requested: false,
+            parallel: None,
+            base_learning_rate: optimizer.learning_rate(),
+            optimizer: optimizer
+        }
+    }
+
+    /// Evaluates `metric` after every epoch and records it under `name`
+    pub fn add_metric<M>(&mut self, name: &str, metric: M)
+        where M: Fn(&NeuralNetwork<F>, &Dataset<F>) -> f64 + 'static
+    {
+        self.metrics.push((name.to_string(), Box::new(metric)));
+    }
+
+    /// Adjusts the learning rate before every optimizer step
+    pub fn set_schedule<S: Schedule + 'static>(&mut self, schedule: S) {
+        self.schedule = Some(Box::new(schedule));
+    }
+
+    pub fn schedule(&self) -> Option<&dyn Schedule> {
+        self.schedule.as_ref().map(|schedule| &**schedule)
+    }
+
+    /// Adds penalties, clipping and weight constraints to every step
+    pub fn set_regularization(&mut self, regularization: Regularization) {
+        self.regularization = Some(regularization);
+    }
+
+    pub fn regularization(&self) -> Option<&Regularization> {
+        self.regularization.as_ref()
+    }
+
+    /// Stops `fit` once the monitored value stopped improving
+    pub fn set_early_stopping(&mut self, early_stopping: EarlyStopping) {
+        self.early_stopping = Some(early_stopping);
+    }
+
+    pub fn early_stopping(&self) -> Option<&EarlyStopping> {
+        self.early_stopping.as_ref()
+    }
+
+    /// Adds a callback, callbacks are called in the order they were added
+    pub fn add_callback<C: Callback<F> + 'static>(&mut self, callback: C) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Whether a callback requested a stop or early stopping ran out of patience
+    pub fn should_stop(&self) -> bool {
+        self.stop_requested || self.early_stopping.as_ref().map_or(false, |early_stopping| early_stopping.should_stop())
+    }
+
+    /// Restores the network of the best epoch seen by early stopping, returns whether it did
+    pub fn restore_best(&mut self) -> bool {
+        match self.best_network {
+            Some(ref network) => {
+                self.network = network.clone();
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn network(&self) -> &NeuralNetwork<F> {
+        &self.network
+    }
+
+    pub fn into_network(self) -> NeuralNetwork<F> {
+        self.network
+    }
+
+    pub fn loss(&self) -> &L {
+        &self.loss
+    }
+
+    pub fn optimizer(&self) -> &O {
+        &self.optimizer
+    }
+
+    pub fn optimizer_mut(&mut self) -> &mut O {
+        &mut self.optimizer
+    }
+
+    pub fn options(&self) -> &TrainerOptions {
+        &self.options
+    }
+
+    /// Seed used for shuffling
+    pub fn seed(&self) -> usize {
+        self.seed
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Order in which the samples are visited in the given epoch
+    pub fn sample_order(&self, epoch: usize, samples: usize) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..samples).collect();
+        if self.options.shuffle {
+            let seed: &[usize] = &[self.seed, epoch];
+            let mut rng: StdRng = SeedableRng::from_seed(seed);
+            rng.shuffle(&mut order);
+        }
+        order
+    }
+
+    fn apply_schedule(&mut self, epoch: f64) {
+        if let Some(ref schedule) = self.schedule {
+            self.optimizer.set_learning_rate(schedule.learning_rate(epoch, self.base_learning_rate));
+        }
+    }
+
+    /// Calls an event on every callback and applies learning rate changes and stop requests
+    fn notify<E>(&mut self, epoch: usize, mut event: E)
+        where E: FnMut(&mut dyn Callback<F>, &mut CallbackContext<F>) -> Control
+    {
+        if self.callbacks.is_empty() {
+            return;
+        }
+
+        let previous = self.optimizer.learning_rate();
+        let learning_rate = {
+            let mut context = CallbackContext {
+                epoch: epoch,
+                network: &self.network,
+                history: &self.history,
+                learning_rate: previous
+            };
+
+            for callback in self.callbacks.iter_mut() {
+                if event(&mut **callback, &mut context) == Control::Stop {
+                    self.stop_requested = true;
+                }
+            }
+            context.learning_rate
+        };
+
+        if learning_rate != previous {
+            if self.schedule.is_some() && previous != 0.0 {
+                self.base_learning_rate *= learning_rate / previous;
+            }
+            self.optimizer.set_learning_rate(learning_rate);
+        }
+    }
+
+    /// Trains a single epoch and appends it to the history
+    pub fn epoch(&mut self, train: &Dataset<F>, validation: Option<&Dataset<F>>) -> Result<&EpochRecord, TrainerError> {
+        if train.is_empty() {
+            return Err(TrainerError::EmptyDataset);
+        }
+        if self.options.batch_size == 0 {
+            return Err(TrainerError::InvalidBatchSize);
+        }
+
+        let epoch = self.history.epochs.len();
+        let order = self.sample_order(epoch, train.len());
+        let batches = (order.len() + self.options.batch_size - 1) / self.options.batch_size;
+
+        self.apply_schedule(epoch as f64);
+        self.notify(epoch, |callback, context| callback.epoch_start(context));
+        let learning_rate = self.optimizer.learning_rate();
+
+        let (mut sum, mut count) = (0.0, 0);
+        for (index, batch) in order.chunks(self.options.batch_size).enumerate() {
+            self.apply_schedule(epoch as f64 + index as f64 / batches as f64);
+            let (value, mut gradient) = match self.parallel {
+                Some((ref mut pool, gradient)) => try!(gradient(pool, &self.loss, &self.network, train, batch)),
+                None => try!(loss::gradient(&self.loss, &self.network, train, batch))
+            };
+
+            if let Some(ref regularization) = self.regularization {
+                try!(regularization.add_penalty_gradient(&self.network, &mut gradient));
+                regularization.clip(&mut gradient);
+            }
+
+            try!(self.optimizer.step(&mut self.network, &gradient));
+
+            if let Some(ref regularization) = self.regularization {
+                regularization.constrain(&mut self.network, self.optimizer.learning_rate());
+            }
+
+            sum += value.to_f64() * batch.len() as f64;
+            count += batch.len();
+
+            self.notify(epoch, |callback, context| callback.batch_end(context, index, value.to_f64()));
+            if self.stop_requested {
+                break;
+            }
+        }
+
+        let validation_loss = match validation {
+            Some(data) => Some(try!(loss::mean(&self.loss, &self.network, data)).to_f64()),
+            None => None
+        };
+
+        if let Some(ref mut schedule) = self.schedule {
+            schedule.observe(validation_loss.unwrap_or(sum / count as f64));
+        }
+
+        let evaluated = validation.unwrap_or(train);
+        let metrics = self.metrics.iter().map(|&(ref name, ref metric)| {
+            (name.clone(), metric(&self.network, evaluated))
+        }).collect();
+
+        let penalty = self.regularization.as_ref().map_or(PenaltyValue::default(), |r| r.penalty_value(&self.network));
+
+        info!(target: "trainer", "Epoch {} finished with a loss of {} (L1 penalty {}, L2 penalty {})", epoch, sum / count as f64, penalty.l1, penalty.l2);
+
+        self.history.epochs.push(EpochRecord {
+            epoch: epoch,
+            train_loss: sum / count as f64,
+            penalty: penalty,
+            validation_loss: validation_loss,
+            learning_rate: learning_rate,
+            metrics: metrics
+        });
+
+        let record = self.history.epochs[epoch].clone();
+        if let Some(ref mut early_stopping) = self.early_stopping {
+            if early_stopping.update(&record) && early_stopping.restore_best {
+                self.best_network = Some(self.network.clone());
+            }
+        }
+
+        self.notify(epoch, |callback, context| callback.epoch_end(context, &record));
+        if self.history.best().map(|best| best.epoch) == Some(epoch) {
+            self.notify(epoch, |callback, context| callback.new_best(context, &record));
+        }
+
+        Ok(&self.history.epochs[epoch])
+    }
+
+    /// Trains `options.epochs` epochs, or until early stopping or a callback ends training
+    ///
+    /// With early stopping the weights of the best epoch are restored at the end, unless
+    /// `EarlyStopping::restore_best` is disabled.
+    pub fn fit(&mut self, train: &Dataset<F>, validation: Option<&Dataset<F>>) -> Result<&History, TrainerError> {
+        self.stop_requested = false;
+
+        for _ in 0..self.options.epochs {
+            try!(self.epoch(train, validation));
+
+            if self.should_stop() {
+                info!(target: "trainer", "Stopping early after epoch {}", self.history.epochs.len() - 1);
+                break;
+            }
+        }
+
+        self.restore_best();
+
+        Ok(&self.history)
+    }
+}
+
+impl<F: Float, L: Loss<F> + Sync, O: Optimizer<F>> Trainer<F, L, O> {
+    /// Shards every mini-batch across `threads` threads, see `parallel`
+    pub fn set_threads(&mut self, threads: usize) {
+        self.parallel = if threads > 1 {
+            Some((Pool::new(threads as u32), parallel::gradient::<F, L>))
+        } else {
+            None
+        };
+    }
+}
+
+impl<F: Float, L: Loss<F>, O: Optimizer<F> + Clone> Trainer<F, L, O> {
+    /// Captures the training state after the last finished epoch
+    pub fn checkpoint(&self) -> Checkpoint<F, O> {
+        Checkpoint {
+            network: self.network.clone(),
+            optimizer: self.optimizer.clone(),
+            seed: self.seed,
+            base_learning_rate: self.base_learning_rate,
+            schedule_state: self.schedule.as_ref().map_or(Vec::new(), |schedule| schedule.state()),
+            early_stopping: self.early_stopping.clone(),
+            best_network: self.best_network.clone(),
+            history: self.history.clone()
+        }
+    }
+
+    /// Continues from a checkpoint taken after an epoch
+    ///
+    /// The following epochs match the saved run as long as it was not stopped by a callback and
+    /// its callbacks carry no state between epochs: stop requests and callback state are not
+    /// part of the checkpoint. Loss, options, schedule, regularization, metrics and callbacks
+    /// are kept as configured.
+    pub fn resume(&mut self, checkpoint: Checkpoint<F, O>) {
+        self.network = checkpoint.network;
+        self.optimizer = checkpoint.optimizer;
+        self.seed = checkpoint.seed;
+        self.base_learning_rate = checkpoint.base_learning_rate;
+        if let Some(ref mut schedule) = self.schedule {
+            schedule.set_state(&checkpoint.schedule_state);
+        }
+        self.early_stopping = checkpoint.early_stopping;
+        self.best_network = checkpoint.best_network;
+        self.history = checkpoint.history;
+        self.stop_requested = false;
+    }
+}
+
+impl<F: Float, L: Loss<F>, O: Optimizer<F> + Clone + Encodable + Decodable> Trainer<F, L, O> {
+    /// Like `fit`, but resumes from the latest checkpoint of `manager` and saves a checkpoint
+    /// every `every` epochs and after the last one
+    ///
+    /// Training ends once the history holds `options.epochs` epochs, so an interrupted run
+    /// started again with the same call finishes the remaining epochs.
+    pub fn fit_checkpointed(&mut self, train: &Dataset<F>, validation: Option<&Dataset<F>>, manager: &CheckpointManager, every: usize) -> Result<&History, TrainerError> {
+        if let Some(checkpoint) = try!(manager.load_latest()) {
+            info!(target: "trainer", "Resuming after epoch {}", checkpoint.epoch());
+            self.resume(checkpoint);
+        }
+
+        self.stop_requested = false;
+
+        while self.history.epochs.len() < self.options.epochs && !self.should_stop() {
+            try!(self.epoch(train, validation));
+
+            let finished = self.history.epochs.len();
+            if finished == self.options.epochs || self.should_stop() || (every > 0 && finished % every == 0) {
+                try!(manager.save(&self.checkpoint()));
+            }
+        }
+
+        self.restore_best();
+
+        Ok(&self.history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use neural_network::{Neuron, NeuronType};
+    use neural_network::differential::random_network;
+    use training::loss::MeanSquaredError;
+    use training::optimizer::{Adam, Sgd};
+
+    fn random_data(network: &NeuralNetwork, rng: &mut StdRng, samples: usize) -> Dataset {
+        let outputs = network.layers()[network.layers().len() - 1].len();
+        let inputs = (0..samples).map(|_| (0..network.inputs()).map(|_| rng.gen_range(-1.0, 1.0)).collect()).collect();
+        let targets = (0..samples).map(|_| (0..outputs).map(|_| rng.gen_range(-1.0, 1.0)).collect()).collect();
+        Dataset::new(inputs, targets).unwrap()
+    }
+
+    fn options(epochs: usize, seed: usize) -> TrainerOptions {
+        let mut options = TrainerOptions::defaults();
+        options.epochs = epochs;
+        options.batch_size = 4;
+        options.seed = Some(seed);
+        options
+    }
+
+    fn weights(network: &NeuralNetwork) -> Vec<f64> {
+        network.iter().flat_map(|(_, neuron)| neuron.weights().iter().cloned().chain(Some(neuron.bias()))).collect()
+    }
+
+    fn losses(history: &History) -> Vec<(f64, Option<f64>)> {
+        history.epochs.iter().map(|record| (record.train_loss, record.validation_loss)).collect()
+    }
+
+    #[test]
+    fn fixed_seed_repeats_runs() {
+        let seed: &[usize] = &[1];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let network: NeuralNetwork = random_network(&mut rng, 3, 4);
+        let train = random_data(&network, &mut rng, 20);
+        let validation = random_data(&network, &mut rng, 8);
+
+        let run = |seed: usize| {
+            let mut trainer = Trainer::new(network.clone(), MeanSquaredError, Adam::new(0.01), Some(options(5, seed)));
+            trainer.fit(&train, Some(&validation)).unwrap();
+            trainer
+        };
+
+        let (first, second, other) = (run(5), run(5), run(6));
+        assert_eq!(first.seed(), 5);
+
+        for epoch in 0..5 {
+            let order = first.sample_order(epoch, train.len());
+            assert_eq!(order, second.sample_order(epoch, train.len()));
+            assert!(order != other.sample_order(epoch, train.len()));
+            assert!(order != first.sample_order(epoch + 1, train.len()));
+
+            let mut sorted = order.clone();
+            sorted.sort();
+            assert_eq!(sorted, (0..train.len()).collect::<Vec<_>>());
+        }
+
+        assert_eq!(losses(first.history()), losses(second.history()));
+        assert_eq!(weights(first.network()), weights(second.network()));
+        assert!(weights(first.network()) != weights(other.network()));
+
+        let mut unshuffled = options(1, 5);
+        unshuffled.shuffle = false;
+        let trainer = Trainer::new(network.clone(), MeanSquaredError, Adam::new(0.01), Some(unshuffled));
+        assert_eq!(trainer.sample_order(3, 6), vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    /// Training fits `y = 2 x` while validation wants `y = 0.5 x`, so the validation loss of the
+    /// single weight starting at 0 falls and then rises again
+    fn diverging() -> (NeuralNetwork, Dataset, Dataset) {
+        let network = NeuralNetwork::from_layers(1, vec![vec![Neuron::new(vec![0.0], 0.0, NeuronType::Identity)]]);
+        let xs = [-1.0, -0.75, -0.5, -0.25, 0.25, 0.5, 0.75, 1.0];
+        let inputs: Vec<Vec<f64>> = xs.iter().map(|x| vec![*x]).collect();
+
+        let train = Dataset::new(inputs.clone(), xs.iter().map(|x| vec![2.0 * x]).collect()).unwrap();
+        let validation = Dataset::new(inputs, xs.iter().map(|x| vec![0.5 * x]).collect()).unwrap();
+        (network, train, validation)
+    }
+
+    #[test]
+    fn fit_restores_best_weights() {
+        let (network, train, validation) = diverging();
+
+        let mut trainer = Trainer::new(network.clone(), MeanSquaredError, Sgd::new(0.1), Some(options(100, 2)));
+        trainer.set_early_stopping(EarlyStopping::new(3, 0.0));
+        trainer.fit(&train, Some(&validation)).unwrap();
+
+        let history = trainer.history().clone();
+        let best = history.best().unwrap().clone();
+        assert_eq!(history.epochs.len(), best.epoch + 4);
+        assert!(history.last().unwrap().validation_loss.unwrap() > best.validation_loss.unwrap());
+        assert_eq!(loss::mean(&MeanSquaredError, trainer.network(), &validation).unwrap(), best.validation_loss.unwrap());
+
+        // the restored weights are exactly those after the best epoch
+        let mut until_best = Trainer::new(network.clone(), MeanSquaredError, Sgd::new(0.1), Some(options(best.epoch + 1, 2)));
+        until_best.fit(&train, Some(&validation)).unwrap();
+        assert_eq!(weights(trainer.network()), weights(until_best.network()));
+
+        // without restoring the weights of the last epoch remain
+        let mut early_stopping = EarlyStopping::new(3, 0.0);
+        early_stopping.restore_best = false;
+        let mut keeping = Trainer::new(network.clone(), MeanSquaredError, Sgd::new(0.1), Some(options(100, 2)));
+        keeping.set_early_stopping(early_stopping);
+        keeping.fit(&train, Some(&validation)).unwrap();
+        assert_eq!(loss::mean(&MeanSquaredError, keeping.network(), &validation).unwrap(), history.last().unwrap().validation_loss.unwrap());
+    }
+
+    #[test]
+    fn rejects_empty_data_and_batches() {
+        let (network, train, _) = diverging();
+        let empty: Dataset = Dataset::new(Vec::new(), Vec::new()).unwrap();
+
+        let mut trainer = Trainer::new(network.clone(), MeanSquaredError, Sgd::new(0.1), Some(options(1, 1)));
+        match trainer.fit(&empty, None) {
+            Err(TrainerError::EmptyDataset) => { },
+            result => panic!("expected EmptyDataset, got {:?}", result.map(|_| ()))
+        }
+
+        let mut options = options(1, 1);
+        options.batch_size = 0;
+        let mut trainer = Trainer::new(network, MeanSquaredError, Sgd::new(0.1), Some(options));
+        match trainer.fit(&train, None) {
+            Err(TrainerError::InvalidBatchSize) => { },
+            result => panic!("expected InvalidBatchSize, got {:?}", result.map(|_| ()))
+        }
+    }
+}